@@ -0,0 +1,321 @@
+use std::io::Read;
+
+use serde_json;
+use url::form_urlencoded;
+use kite::{Term, Token};
+
+use analysis::AnalyzerSpec;
+use analysis::tokenizers::TokenizerSpec;
+use index::metadata::IndexMetadata;
+use index::metadata::parse::analysis_tokenizer::parse as parse_tokenizer;
+use index::metadata::parse::analysis_filter::parse as parse_filter;
+
+use api::persistent;
+use api::iron::prelude::*;
+use api::iron::status;
+use api::router::Router;
+use api::utils::json_response;
+
+
+/// Works out which analyzer to run from the "analyzer", "field", "tokenizer" and "filter" keys
+///
+/// Returns None if the text shouldn't be analyzed (eg, the field is not analyzed)
+fn resolve_analyzer(request: &serde_json::Map<String, serde_json::Value>, index_metadata: &IndexMetadata) -> Result<Option<AnalyzerSpec>, String> {
+    if let Some(analyzer_json) = request.get("analyzer") {
+        let analyzer_name = match analyzer_json.as_str() {
+            Some(analyzer_name) => analyzer_name,
+            None => return Err("\"analyzer\" must be a string".to_string()),
+        };
+
+        return match index_metadata.analyzers().get(analyzer_name) {
+            Some(analyzer) => Ok(Some(analyzer.clone())),
+            None => Err(format!("Analyzer not found: {:?}", analyzer_name)),
+        };
+    }
+
+    if let Some(field_json) = request.get("field") {
+        let field_name = match field_json.as_str() {
+            Some(field_name) => field_name,
+            None => return Err("\"field\" must be a string".to_string()),
+        };
+
+        // Fields that aren't in the mappings are analyzed with the default analyzer
+        return match index_metadata.get_field_mapping(field_name) {
+            Some(field_mapping) => Ok(field_mapping.index_analyzer().cloned()),
+            None => Ok(Some(index_metadata.get_default_index_analyzer())),
+        };
+    }
+
+    if request.contains_key("tokenizer") || request.contains_key("filter") {
+        let tokenizer = match request.get("tokenizer") {
+            Some(&serde_json::Value::String(ref tokenizer_name)) => {
+                match index_metadata.tokenizers().get(tokenizer_name) {
+                    Some(tokenizer) => tokenizer.clone(),
+                    None => return Err(format!("Tokenizer not found: {:?}", tokenizer_name)),
+                }
+            }
+            Some(tokenizer_json) => {
+                match parse_tokenizer(tokenizer_json) {
+                    Ok(tokenizer) => tokenizer,
+                    Err(error) => return Err(format!("Tokenizer error: {:?}", error)),
+                }
+            }
+            None => TokenizerSpec::Standard,
+        };
+
+        let filters_json = match request.get("filter") {
+            Some(&serde_json::Value::Array(ref filters_json)) => filters_json.clone(),
+            Some(filter_json) => vec![filter_json.clone()],
+            None => Vec::new(),
+        };
+
+        let mut filters = Vec::with_capacity(filters_json.len());
+        for filter_json in filters_json.iter() {
+            match *filter_json {
+                serde_json::Value::String(ref filter_name) => {
+                    match index_metadata.filters().get(filter_name) {
+                        Some(filter) => filters.push(filter.clone()),
+                        None => return Err(format!("Filter not found: {:?}", filter_name)),
+                    }
+                }
+                _ => {
+                    match parse_filter(filter_json) {
+                        Ok(filter) => filters.push(filter),
+                        Err(error) => return Err(format!("Filter error: {:?}", error)),
+                    }
+                }
+            }
+        }
+
+        return Ok(Some(AnalyzerSpec {
+            tokenizer: tokenizer,
+            filters: filters,
+        }));
+    }
+
+    Ok(Some(index_metadata.get_default_index_analyzer()))
+}
+
+
+/// Runs the text of an analyze request through its analyzer
+///
+/// Tokens only carry a term and a position so offsets and token types are not included
+fn analyze(request: &serde_json::Map<String, serde_json::Value>, index_metadata: &IndexMetadata) -> Result<serde_json::Value, String> {
+    let texts = match request.get("text") {
+        Some(&serde_json::Value::String(ref text)) => vec![text.clone()],
+        Some(&serde_json::Value::Array(ref array)) => {
+            let mut texts = Vec::with_capacity(array.len());
+            for item in array.iter() {
+                match item.as_str() {
+                    Some(text) => texts.push(text.to_string()),
+                    None => return Err("each item in \"text\" must be a string".to_string()),
+                }
+            }
+            texts
+        }
+        Some(_) => return Err("\"text\" must be a string or an array of strings".to_string()),
+        None => return Err("\"text\" is required".to_string()),
+    };
+
+    let analyzer = resolve_analyzer(request, index_metadata)?;
+
+    // Positions carry on from the previous text, as they do when an array is indexed
+    let mut tokens = Vec::new();
+    let mut last_token_position = 0;
+    for text in texts.iter() {
+        let text_tokens = match analyzer {
+            Some(ref analyzer) => analyzer.initialise(text).collect::<Vec<Token>>(),
+            None => vec![Token {term: Term::from_string(text), position: 1}],
+        };
+
+        for token in text_tokens.iter() {
+            tokens.push(json!({
+                "token": String::from_utf8_lossy(token.term.as_bytes()),
+                "position": token.position + last_token_position,
+            }));
+        }
+
+        if let Some(token) = text_tokens.last() {
+            last_token_position += token.position;
+        }
+    }
+
+    Ok(json!({
+        "tokens": tokens,
+    }))
+}
+
+
+pub fn view_analyze(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let index_name = read_path_parameter!(req, "index").map(|index_name| index_name.to_owned());
+
+    let mut request = match json_from_request_body!(req) {
+        Some(serde_json::Value::Object(request)) => request,
+        Some(_) => {
+            return Ok(json_response(status::BadRequest, json!({"message": "Request body must be an object"})));
+        }
+        None => serde_json::Map::new(),
+    };
+
+    // Parse URL parameters
+    // These are only used for keys that aren't in the request body
+    if let Some(ref url_query) = req.url.query() {
+        for (key, value) in form_urlencoded::parse(url_query.as_bytes()) {
+            match key.as_ref() {
+                "text" | "analyzer" | "field" | "tokenizer" => {
+                    if !request.contains_key(&*key) {
+                        request.insert(key.to_string(), serde_json::Value::String(value.to_string()));
+                    }
+                }
+                "filter" => {
+                    if !request.contains_key("filter") {
+                        let filters = value.split(",").map(|filter_name| serde_json::Value::String(filter_name.to_owned())).collect();
+                        request.insert("filter".to_string(), serde_json::Value::Array(filters));
+                    }
+                }
+                _ => warn!("unrecognised GET parameter {:?}", key),
+            }
+        }
+    }
+
+    // Requests without an index can only use the builtin analyzers
+    let result = match index_name {
+        Some(ref index_name) => {
+            let cluster_metadata = system.metadata.read().unwrap();
+            let index = get_index_or_404!(cluster_metadata, index_name.as_str());
+            let index_metadata = index.metadata.read().unwrap();
+
+            analyze(&request, &index_metadata)
+        }
+        None => analyze(&request, &IndexMetadata::default()),
+    };
+
+    match result {
+        Ok(response) => Ok(json_response(status::Ok, response)),
+        Err(error) => Ok(json_response(status::BadRequest, json!({"message": error}))),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use analysis::AnalyzerSpec;
+    use analysis::tokenizers::TokenizerSpec;
+    use analysis::filters::FilterSpec;
+    use index::metadata::IndexMetadata;
+    use mapping::{Mapping, MappingProperty, FieldMapping};
+
+    use super::{resolve_analyzer, analyze};
+
+    fn request(json: serde_json::Value) -> serde_json::Map<String, serde_json::Value> {
+        json.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn test_resolve_named_analyzer() {
+        let analyzer = resolve_analyzer(&request(json!({"analyzer": "standard"})), &IndexMetadata::default());
+
+        assert_eq!(analyzer, Ok(Some(AnalyzerSpec {
+            tokenizer: TokenizerSpec::Standard,
+            filters: vec![FilterSpec::Lowercase, FilterSpec::ASCIIFolding],
+        })));
+    }
+
+    #[test]
+    fn test_resolve_tokenizer_and_filters() {
+        let analyzer = resolve_analyzer(&request(json!({
+            "tokenizer": "lowercase",
+            "filter": ["asciifolding", {"type": "lowercase"}]
+        })), &IndexMetadata::default());
+
+        assert_eq!(analyzer, Ok(Some(AnalyzerSpec {
+            tokenizer: TokenizerSpec::Lowercase,
+            filters: vec![FilterSpec::ASCIIFolding, FilterSpec::Lowercase],
+        })));
+
+        // The standard tokenizer is used when only filters are given
+        let analyzer = resolve_analyzer(&request(json!({"filter": "lowercase"})), &IndexMetadata::default());
+
+        assert_eq!(analyzer, Ok(Some(AnalyzerSpec {
+            tokenizer: TokenizerSpec::Standard,
+            filters: vec![FilterSpec::Lowercase],
+        })));
+    }
+
+    #[test]
+    fn test_resolve_field() {
+        let mut index_metadata = IndexMetadata::default();
+        index_metadata.mappings.insert("test".to_string(), Mapping {
+            properties: hashmap! {
+                "tag".to_string() => MappingProperty::Field(FieldMapping::default()),
+            },
+        });
+
+        // Not analyzed
+        assert_eq!(resolve_analyzer(&request(json!({"field": "tag"})), &index_metadata), Ok(None));
+
+        // Fields that aren't mapped use the default analyzer
+        assert_eq!(resolve_analyzer(&request(json!({"field": "title"})), &index_metadata), Ok(Some(index_metadata.get_default_index_analyzer())));
+    }
+
+    #[test]
+    fn test_resolve_gives_error_for_unknown_names() {
+        let index_metadata = IndexMetadata::default();
+
+        assert_eq!(resolve_analyzer(&request(json!({"analyzer": "foo"})), &index_metadata), Err("Analyzer not found: \"foo\"".to_string()));
+        assert_eq!(resolve_analyzer(&request(json!({"tokenizer": "foo"})), &index_metadata), Err("Tokenizer not found: \"foo\"".to_string()));
+        assert_eq!(resolve_analyzer(&request(json!({"filter": ["foo"]})), &index_metadata), Err("Filter not found: \"foo\"".to_string()));
+    }
+
+    #[test]
+    fn test_analyze() {
+        let response = analyze(&request(json!({"text": "Hello, World!"})), &IndexMetadata::default());
+
+        assert_eq!(response, Ok(json!({
+            "tokens": [
+                {"token": "hello", "position": 1},
+                {"token": "world", "position": 2},
+            ]
+        })));
+    }
+
+    #[test]
+    fn test_analyze_array_continues_positions() {
+        let response = analyze(&request(json!({"text": ["Hello world", "Goodbye"]})), &IndexMetadata::default());
+
+        assert_eq!(response, Ok(json!({
+            "tokens": [
+                {"token": "hello", "position": 1},
+                {"token": "world", "position": 2},
+                {"token": "goodbye", "position": 3},
+            ]
+        })));
+    }
+
+    #[test]
+    fn test_analyze_not_analyzed_field() {
+        let mut index_metadata = IndexMetadata::default();
+        index_metadata.mappings.insert("test".to_string(), Mapping {
+            properties: hashmap! {
+                "tag".to_string() => MappingProperty::Field(FieldMapping::default()),
+            },
+        });
+
+        let response = analyze(&request(json!({"text": ["New York", "Paris"], "field": "tag"})), &index_metadata);
+
+        assert_eq!(response, Ok(json!({
+            "tokens": [
+                {"token": "New York", "position": 1},
+                {"token": "Paris", "position": 2},
+            ]
+        })));
+    }
+
+    #[test]
+    fn test_analyze_gives_error_for_missing_text() {
+        assert_eq!(analyze(&request(json!({})), &IndexMetadata::default()), Err("\"text\" is required".to_string()));
+    }
+}
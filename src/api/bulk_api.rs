@@ -1,99 +1,385 @@
 use std::io::Read;
-use std::collections::HashMap;
+use std::time::Instant;
 
 use serde_json;
+use uuid::Uuid;
 
-use document::DocumentSource;
+use index::write::{WriteError, index_document, update_document, delete_document};
+use cluster::metadata::ClusterMetadata;
 
 use api::persistent;
 use api::iron::prelude::*;
 use api::iron::status;
-use api::utils::{json_response};
+use api::router::Router;
+use api::utils::{json_response, write_error_status, millis_since};
 
 
-pub fn view_post_bulk(req: &mut Request) -> IronResult<Response> {
-    let ref system = get_system!(req);
+/// An error that caused a single bulk action to fail
+///
+/// These are reported in the item for the action and don't stop the rest of the request
+#[derive(Debug)]
+struct ItemError {
+    status: status::Status,
+    error_type: &'static str,
+    reason: String,
+}
 
-    // Lock cluster metedata
-    let cluster_metadata = system.metadata.read().unwrap();
 
-    // Load data from body
-    let mut payload = String::new();
-    req.body.read_to_string(&mut payload).unwrap();
+impl ItemError {
+    fn new(status: status::Status, error_type: &'static str, reason: &str) -> ItemError {
+        ItemError {
+            status: status,
+            error_type: error_type,
+            reason: reason.to_string(),
+        }
+    }
+}
 
-    let mut items = Vec::new();
 
-    // Iterate
-    let mut payload_lines = payload.split('\n');
-    loop {
-        let action_line = payload_lines.next();
+impl From<WriteError> for ItemError {
+    fn from(error: WriteError) -> ItemError {
+        ItemError {
+            status: write_error_status(&error),
+            error_type: error.error_type(),
+            reason: error.reason(),
+        }
+    }
+}
+
+
+/// Reads a parameter from an action line, falling back to a default from the URL
+fn read_action_param(action_params: &serde_json::Map<String, serde_json::Value>, name: &str, default: Option<&str>) -> Option<String> {
+    match action_params.get(name) {
+        Some(&serde_json::Value::String(ref value)) => Some(value.clone()),
+        Some(&serde_json::Value::Number(ref value)) => Some(value.to_string()),
+        _ => default.map(|default| default.to_owned()),
+    }
+}
+
+
+/// Runs a single bulk action, returns its status and the result fields of its item
+fn run_action(cluster_metadata: &ClusterMetadata, action_name: &str, index_name: Option<&str>, mapping_name: Option<&str>, doc_key: Option<&str>, source: Option<&serde_json::Value>) -> Result<(status::Status, serde_json::Value), ItemError> {
+    let index_name = match index_name {
+        Some(index_name) => index_name,
+        None => return Err(ItemError::new(status::BadRequest, "action_request_validation_exception", "index is missing")),
+    };
+
+    let mapping_name = match mapping_name {
+        Some(mapping_name) => mapping_name,
+        None => return Err(ItemError::new(status::BadRequest, "action_request_validation_exception", "type is missing")),
+    };
+
+    let doc_key = match doc_key {
+        Some(doc_key) => doc_key,
+        None => return Err(ItemError::new(status::BadRequest, "action_request_validation_exception", "id is missing")),
+    };
+
+    // Find index
+    let index = match cluster_metadata.names.find_canonical(index_name).and_then(|index_ref| cluster_metadata.indices.get(&index_ref)) {
+        Some(index) => index,
+        None => return Err(ItemError::new(status::NotFound, "index_not_found_exception", "no such index")),
+    };
+    let index_metadata = index.metadata.read().unwrap();
+
+    match action_name {
+        "index" | "create" | "update" => {
+            let source = match source {
+                Some(source) => source,
+                None => return Err(ItemError::new(status::BadRequest, "action_request_validation_exception", "source is missing")),
+            };
+
+            let result = if action_name == "update" {
+                update_document(index, &index_metadata, mapping_name, doc_key, source)?
+            } else {
+                index_document(index, &index_metadata, mapping_name, doc_key, source, action_name == "create")?
+            };
 
-        // Check if end of input
-        if action_line == None || action_line == Some("") {
-            break;
+            Ok((if result.created { status::Created } else { status::Ok }, json!({
+                "_index": index.canonical_name(),
+                "_version": result.version,
+                "result": if result.created { "created" } else { "updated" },
+                "created": result.created,
+            })))
         }
+        "delete" => {
+            match delete_document(index, mapping_name, doc_key) {
+                Ok(version) => {
+                    Ok((status::Ok, json!({
+                        "_index": index.canonical_name(),
+                        "_version": version,
+                        "result": "deleted",
+                        "found": true,
+                    })))
+                }
+                Err(WriteError::DocumentNotFound) => {
+                    // Deleting a document that doesn't exist isn't treated as an error
+                    Ok((status::NotFound, json!({
+                        "_index": index.canonical_name(),
+                        "result": "not_found",
+                        "found": false,
+                    })))
+                }
+                Err(error) => Err(error.into()),
+            }
+        }
+        _ => Err(ItemError::new(status::BadRequest, "action_request_validation_exception", "unrecognised action")),
+    }
+}
+
+
+/// A parsed action of a bulk request
+#[derive(Debug, PartialEq)]
+struct BulkAction {
+    action_name: String,
+    index_name: Option<String>,
+    mapping_name: Option<String>,
+    doc_key: Option<String>,
 
-        // Parse action line
-        let action_json = parse_json!(&action_line.unwrap());
+    /// The source line of the action, if it has one. Sources that can't be parsed only fail
+    /// their own action
+    source: Option<Result<serde_json::Value, String>>,
+}
+
+
+/// Parses every action of a bulk request
+///
+/// Errors in the structure of the request fail the whole request. These are found before
+/// any action is run so a malformed request never writes anything.
+fn parse_bulk_actions(payload: &str, default_index_name: Option<&str>, default_mapping_name: Option<&str>) -> Result<Vec<BulkAction>, String> {
+    let mut actions = Vec::new();
+
+    let mut payload_lines = payload.lines().filter(|line| !line.trim().is_empty());
+    while let Some(action_line) = payload_lines.next() {
+        let action_json = match serde_json::from_str::<serde_json::Value>(action_line) {
+            Ok(action_json) => action_json,
+            Err(_) => return Err("Couldn't parse JSON".to_string()),
+        };
 
         // Check action
         // Action should be an object with only one key, the key name indicates the action and
         // the value is the parameters for that action
-        let action_name = action_json.as_object().unwrap().keys().nth(0).unwrap();
-        let action_params = action_json.as_object()
-                                       .unwrap()
-                                       .get(action_name)
-                                       .unwrap()
-                                       .as_object()
-                                       .unwrap();
-
-        let doc_id = action_params.get("_id").unwrap().as_str().unwrap();
-        let doc_type = action_params.get("_type").unwrap().as_str().unwrap();
-        let doc_index = action_params.get("_index").unwrap().as_str().unwrap();
-
-        match action_name.as_ref() {
-            "index" => {
-                let doc_line = payload_lines.next();
-                let doc_json = parse_json!(&doc_line.unwrap());;
-
-                // Find index
-                let index = get_index_or_404!(cluster_metadata, doc_index);
-                let index_metadata = index.metadata.read().unwrap();
-
-                let doc = {
-                    // Find mapping
-                    let mapping = match index_metadata.mappings.get(doc_type) {
-                        Some(mapping) => mapping,
-                        None => {
-                            return Ok(json_response(status::NotFound, json!({"message": "Mapping not found"})));
-                        }
-                    };
-
-                    // Create document
-                    let document_source = DocumentSource {
-                        key: doc_id,
-                        data: doc_json.as_object().unwrap(),
-                    };
-                    document_source.prepare(mapping).unwrap()
-                };
-
-                index.store.insert_or_update_document(&doc).unwrap();
-
-                // Insert into "items" array
-                let mut item = HashMap::new();
-                // TODO: "create" may not always be right
-                item.insert("create", action_params.clone());
-                items.push(item);
+        let (action_name, action_params) = match action_json.as_object() {
+            Some(action_object) if action_object.len() == 1 => {
+                match action_object.iter().next() {
+                    Some((action_name, &serde_json::Value::Object(ref action_params))) => (action_name, action_params),
+                    _ => return Err("Action parameters must be an object".to_string()),
+                }
             }
-            _ => {
-                warn!("Unrecognised action! {}", action_name);
+            _ => return Err("Action line must be an object with a single key".to_string()),
+        };
+
+        match action_name.as_str() {
+            "index" | "create" | "update" | "delete" => {}
+            _ => return Err(format!("Unrecognised bulk action {:?}", action_name)),
+        }
+
+        // All actions other than delete are followed by a source line
+        let source = if action_name == "delete" {
+            None
+        } else {
+            match payload_lines.next() {
+                Some(source_line) => Some(serde_json::from_str::<serde_json::Value>(source_line).map_err(|error| error.to_string())),
+                None => return Err(format!("Expected a source line after {:?} action", action_name)),
             }
+        };
+
+        // Documents that are indexed without a key are given a random one
+        let doc_key = match read_action_param(action_params, "_id", None) {
+            Some(doc_key) => Some(doc_key),
+            None if action_name == "index" || action_name == "create" => Some(Uuid::new_v4().to_string()),
+            None => None,
+        };
+
+        actions.push(BulkAction {
+            action_name: action_name.clone(),
+            index_name: read_action_param(action_params, "_index", default_index_name),
+            mapping_name: read_action_param(action_params, "_type", default_mapping_name),
+            doc_key: doc_key,
+            source: source,
+        });
+    }
+
+    Ok(actions)
+}
+
+
+pub fn view_post_bulk(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let default_index_name = read_path_parameter!(req, "index").map(|index_name| index_name.to_owned());
+    let default_mapping_name = read_path_parameter!(req, "mapping").map(|mapping_name| mapping_name.to_owned());
+    let start_time = Instant::now();
+
+    // Load data from body
+    let mut payload = String::new();
+    if req.body.read_to_string(&mut payload).is_err() {
+        return Ok(json_response(status::BadRequest, json!({"message": "Couldn't read request body"})));
+    }
+
+    let actions = match parse_bulk_actions(&payload, default_index_name.as_ref().map(|index_name| index_name.as_str()), default_mapping_name.as_ref().map(|mapping_name| mapping_name.as_str())) {
+        Ok(actions) => actions,
+        Err(error) => {
+            return Ok(json_response(status::BadRequest, json!({"message": error})));
         }
+    };
+
+    // Lock cluster metadata
+    let cluster_metadata = system.metadata.read().unwrap();
+
+    let mut items = Vec::new();
+    let mut errors = false;
+
+    for action in actions.iter() {
+        let result = match action.source {
+            Some(Err(ref error)) => Err(ItemError::new(status::BadRequest, "mapper_parsing_exception", &format!("Couldn't parse source: {}", error))),
+            Some(Ok(ref source)) => run_action(&cluster_metadata, &action.action_name, action.index_name.as_ref().map(|index_name| index_name.as_str()), action.mapping_name.as_ref().map(|mapping_name| mapping_name.as_str()), action.doc_key.as_ref().map(|doc_key| doc_key.as_str()), Some(source)),
+            None => run_action(&cluster_metadata, &action.action_name, action.index_name.as_ref().map(|index_name| index_name.as_str()), action.mapping_name.as_ref().map(|mapping_name| mapping_name.as_str()), action.doc_key.as_ref().map(|doc_key| doc_key.as_str()), None),
+        };
+
+        let item = match result {
+            Ok((status, mut item)) => {
+                item["_type"] = json!(action.mapping_name);
+                item["_id"] = json!(action.doc_key);
+                item["status"] = json!(status.to_u16());
+                item
+            }
+            Err(error) => {
+                errors = true;
+
+                json!({
+                    "_index": action.index_name,
+                    "_type": action.mapping_name,
+                    "_id": action.doc_key,
+                    "status": error.status.to_u16(),
+                    "error": {
+                        "type": error.error_type,
+                        "reason": error.reason,
+                    }
+                })
+            }
+        };
+
+        // Insert into "items" array
+        let mut action_item = serde_json::Map::new();
+        action_item.insert(action.action_name.clone(), item);
+        items.push(serde_json::Value::Object(action_item));
+    }
+
+    let took = millis_since(start_time);
+
+    Ok(json_response(status::Ok, json!({
+        "took": took,
+        "errors": errors,
+        "items": items,
+    })))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use cluster::metadata::ClusterMetadata;
+
+    use api::iron::status;
+
+    use super::{BulkAction, read_action_param, run_action, parse_bulk_actions};
+
+    fn action_params(json: serde_json::Value) -> serde_json::Map<String, serde_json::Value> {
+        json.as_object().unwrap().clone()
     }
 
-    return Ok(json_response(status::Ok,
-                            json!({
-                                "took": items.len(),
-                                "items": items,
-                            })));
+    #[test]
+    fn test_read_action_param() {
+        let params = action_params(json!({"_index": "test", "_id": 123, "_type": true}));
+
+        assert_eq!(read_action_param(&params, "_index", Some("default")), Some("test".to_string()));
+        assert_eq!(read_action_param(&params, "_id", None), Some("123".to_string()));
+
+        // Falls back to the default if the parameter is missing or isn't a string or number
+        assert_eq!(read_action_param(&params, "_type", Some("default")), Some("default".to_string()));
+        assert_eq!(read_action_param(&params, "_routing", None), None);
+    }
+
+    #[test]
+    fn test_run_action_validation() {
+        let cluster_metadata = ClusterMetadata::new();
+        let source = json!({"title": "Hello"});
+
+        let error = run_action(&cluster_metadata, "index", None, Some("doc"), Some("1"), Some(&source)).unwrap_err();
+        assert_eq!((error.status, error.reason.as_str()), (status::BadRequest, "index is missing"));
+
+        let error = run_action(&cluster_metadata, "index", Some("test"), None, Some("1"), Some(&source)).unwrap_err();
+        assert_eq!((error.status, error.reason.as_str()), (status::BadRequest, "type is missing"));
+
+        let error = run_action(&cluster_metadata, "update", Some("test"), Some("doc"), None, Some(&source)).unwrap_err();
+        assert_eq!((error.status, error.reason.as_str()), (status::BadRequest, "id is missing"));
+    }
+
+    #[test]
+    fn test_run_action_missing_index() {
+        let cluster_metadata = ClusterMetadata::new();
+
+        let error = run_action(&cluster_metadata, "delete", Some("test"), Some("doc"), Some("1"), None).unwrap_err();
+        assert_eq!((error.status, error.error_type), (status::NotFound, "index_not_found_exception"));
+    }
+
+    #[test]
+    fn test_parse_bulk_actions() {
+        let payload = concat!(
+            "{\"index\": {\"_id\": \"1\"}}\n",
+            "{\"title\": \"Hello\"}\n",
+            "\n",
+            "{\"delete\": {\"_index\": \"other\", \"_type\": \"user\", \"_id\": 2}}\n",
+        );
+
+        assert_eq!(parse_bulk_actions(payload, Some("test"), Some("doc")), Ok(vec![
+            BulkAction {
+                action_name: "index".to_string(),
+                index_name: Some("test".to_string()),
+                mapping_name: Some("doc".to_string()),
+                doc_key: Some("1".to_string()),
+                source: Some(Ok(json!({"title": "Hello"}))),
+            },
+            BulkAction {
+                action_name: "delete".to_string(),
+                index_name: Some("other".to_string()),
+                mapping_name: Some("user".to_string()),
+                doc_key: Some("2".to_string()),
+                source: None,
+            },
+        ]));
+    }
+
+    #[test]
+    fn test_parse_bulk_actions_generates_keys() {
+        let actions = parse_bulk_actions("{\"create\": {}}\n{}\n{\"update\": {}}\n{}\n", Some("test"), Some("doc")).unwrap();
+
+        assert!(actions[0].doc_key.is_some());
+        assert_eq!(actions[1].doc_key, None);
+    }
+
+    #[test]
+    fn test_invalid_source_only_fails_its_action() {
+        let actions = parse_bulk_actions("{\"index\": {}}\n{\"title\": \n{\"index\": {}}\n{}\n", Some("test"), Some("doc")).unwrap();
+
+        assert_eq!(actions.len(), 2);
+        assert!(actions[0].source.as_ref().unwrap().is_err());
+        assert_eq!(actions[1].source, Some(Ok(json!({}))));
+    }
+
+    #[test]
+    fn test_malformed_line_fails_whole_request() {
+        // The first action is valid but nothing is run as a later line is malformed
+        let payload = concat!(
+            "{\"index\": {\"_id\": \"1\"}}\n",
+            "{\"title\": \"Hello\"}\n",
+            "{\"index\": {\"_id\": \"2\"}, \"delete\": {}}\n",
+        );
+
+        assert_eq!(parse_bulk_actions(payload, Some("test"), Some("doc")), Err("Action line must be an object with a single key".to_string()));
+        assert_eq!(parse_bulk_actions("{\"upsert\": {}}\n", None, None), Err("Unrecognised bulk action \"upsert\"".to_string()));
+        assert_eq!(parse_bulk_actions("{\"index\": {}}\n", None, None), Err("Expected a source line after \"index\" action".to_string()));
+        assert_eq!(parse_bulk_actions("{\"delete\": 1}\n", None, None), Err("Action parameters must be an object".to_string()));
+        assert_eq!(parse_bulk_actions("not json\n", None, None), Err("Couldn't parse JSON".to_string()));
+    }
 }
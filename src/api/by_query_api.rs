@@ -0,0 +1,313 @@
+use std::io::Read;
+use std::time::Instant;
+
+use serde_json;
+use kite::document::DocRef;
+use kite_rocksdb::RocksDBReader;
+
+use query_parser::{QueryBuildContext, QueryBuilder, parse as parse_query};
+use collectors::doc_ids::DocIdCollector;
+use index::metadata::IndexMetadata;
+use index::term_dictionary::TermDictionary;
+use index::lookup::{read_stored_string, read_source, read_version};
+use index::write::{replace_document_version, delete_document, WriteError};
+use index::field_ops::parse as parse_field_operations;
+
+use api::persistent;
+use api::iron::prelude::*;
+use api::iron::status;
+use api::router::Router;
+use api::utils::{json_response, write_error_status, millis_since};
+
+
+/// Parses the "query" key of a request
+///
+/// Requests without a query match every document
+fn parse_request_query(request_json: Option<&serde_json::Value>) -> Result<Box<QueryBuilder>, String> {
+    let query = match request_json {
+        Some(&serde_json::Value::Object(ref request)) => {
+            match request.get("query") {
+                Some(query_json) => parse_query(query_json),
+                None => parse_query(&json!({"match_all": {}})),
+            }
+        }
+        Some(_) => return Err("Request must be an object".to_string()),
+        None => parse_query(&json!({"match_all": {}})),
+    };
+
+    query.map_err(|error| {
+        let error = error.in_key("query");
+        format!("Query error at {:?}: {}", error.path().join("."), error.reason())
+    })
+}
+
+
+/// Finds every document that matches the "query" key of a request
+pub fn find_matching_documents(index_reader: &RocksDBReader, index_metadata: &IndexMetadata, term_dictionary: &TermDictionary, request_json: Option<&serde_json::Value>) -> Result<Vec<DocRef>, String> {
+    let query = parse_request_query(request_json)?;
+
+    let query = query.build(&QueryBuildContext::new().set_index_metadata(index_metadata).set_index_reader(index_reader).set_term_dictionary(term_dictionary).no_score(), &index_reader.schema())
+        .map_err(|error| format!("Query error: {}", error.reason()))?;
+
+    let mut collector = DocIdCollector::new();
+    if let Err(error) = index_reader.search(&mut collector, &query) {
+        return Err(format!("Search error: {:?}", error));
+    }
+
+    Ok(collector.into_vec().into_iter().map(DocRef::from_u64).collect())
+}
+
+
+pub fn view_delete_by_query(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+    let start_time = Instant::now();
+
+    // Get index
+    let cluster_metadata = system.metadata.read().unwrap();
+    let index = get_index_or_404!(cluster_metadata, *index_name);
+    let index_metadata = index.metadata.read().unwrap();
+
+    let request_json = json_from_request_body!(req);
+
+    // Find the keys and mappings of the documents to delete
+    // These are read up front as the ids of documents aren't stable once the store is modified
+    // Documents without a stored key can't be deleted, these are reported as failures
+    let docs = {
+        let index_reader = index.store.reader();
+        let doc_refs = match find_matching_documents(&index_reader, &index_metadata, &index.term_dictionary, request_json.as_ref()) {
+            Ok(doc_refs) => doc_refs,
+            Err(error) => {
+                return Ok(json_response(status::BadRequest, json!({"message": error})));
+            }
+        };
+
+        let id_field = index_reader.schema().get_field_by_name("_id");
+        let type_field = index_reader.schema().get_field_by_name("_type");
+        doc_refs.into_iter().map(|doc_ref| {
+            (read_stored_string(&index_reader, id_field, doc_ref), read_stored_string(&index_reader, type_field, doc_ref))
+        }).collect::<Vec<(Option<String>, Option<String>)>>()
+    };
+
+    // Delete documents
+    let mut deleted = 0;
+    let mut failures = Vec::new();
+    for &(ref doc_key, ref mapping_name) in docs.iter() {
+        let (doc_key, mapping_name) = match (doc_key.as_ref(), mapping_name.as_ref()) {
+            (Some(doc_key), Some(mapping_name)) => (doc_key, mapping_name),
+            (doc_key, mapping_name) => {
+                failures.push(json!({
+                    "index": index.canonical_name(),
+                    "type": mapping_name,
+                    "id": doc_key,
+                    "cause": {
+                        "type": "illegal_argument_exception",
+                        "reason": if doc_key.is_none() { "Document has no _id" } else { "Document has no _type" },
+                    },
+                    "status": 400,
+                }));
+                continue;
+            }
+        };
+
+        match delete_document(index, mapping_name, doc_key) {
+            Ok(_) => deleted += 1,
+
+            // Deleted since the query was run
+            Err(WriteError::DocumentNotFound) => {}
+
+            Err(error) => {
+                failures.push(json!({
+                    "index": index.canonical_name(),
+                    "type": mapping_name,
+                    "id": doc_key,
+                    "cause": {
+                        "type": error.error_type(),
+                        "reason": error.reason(),
+                    },
+                    "status": write_error_status(&error).to_u16(),
+                }));
+            }
+        }
+    }
+
+    let took = millis_since(start_time);
+
+    Ok(json_response(status::Ok, json!({
+        "took": took,
+        "timed_out": false,
+        "total": docs.len(),
+        "deleted": deleted,
+        "failures": failures,
+    })))
+}
+
+
+pub fn view_update_by_query(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+    let start_time = Instant::now();
+
+    // Get index
+    let cluster_metadata = system.metadata.read().unwrap();
+    let index = get_index_or_404!(cluster_metadata, *index_name);
+    let index_metadata = index.metadata.read().unwrap();
+
+    let request_json = json_from_request_body!(req);
+
+    // Parse field operations
+    let operations = match request_json.as_ref().and_then(|request_json| request_json.as_object()).and_then(|request| request.get("operations")) {
+        Some(operations_json) => {
+            match parse_field_operations(operations_json) {
+                Ok(operations) => operations,
+                Err(error) => {
+                    return Ok(json_response(status::BadRequest, json!({"message": format!("Operations error: {:?}", error)})));
+                }
+            }
+        }
+        None => Vec::new(),
+    };
+
+    // Read the key, mapping, source and version of each document to update
+    // The version is checked again when each document is written so changes made in between
+    // aren't overwritten
+    let docs = {
+        let index_reader = index.store.reader();
+        let doc_refs = match find_matching_documents(&index_reader, &index_metadata, &index.term_dictionary, request_json.as_ref()) {
+            Ok(doc_refs) => doc_refs,
+            Err(error) => {
+                return Ok(json_response(status::BadRequest, json!({"message": error})));
+            }
+        };
+
+        let id_field = index_reader.schema().get_field_by_name("_id");
+        let type_field = index_reader.schema().get_field_by_name("_type");
+        doc_refs.into_iter().map(|doc_ref| {
+            (read_stored_string(&index_reader, id_field, doc_ref), read_stored_string(&index_reader, type_field, doc_ref), read_source(&index_reader, doc_ref), read_version(&index_reader, doc_ref))
+        }).collect::<Vec<(Option<String>, Option<String>, Option<serde_json::Value>, u64)>>()
+    };
+
+    // Re-index each document from its source through the current mapping
+    let mut updated = 0;
+    let mut version_conflicts = 0;
+    let mut failures = Vec::new();
+    for &(ref doc_key, ref mapping_name, ref source, version) in docs.iter() {
+        let (doc_key, mapping_name, mut source) = match (doc_key.as_ref(), mapping_name.as_ref(), source.as_ref()) {
+            (Some(doc_key), Some(mapping_name), Some(&serde_json::Value::Object(ref source))) => (doc_key, mapping_name, source.clone()),
+            (doc_key, mapping_name, _) => {
+                let reason = match (doc_key, mapping_name) {
+                    (None, _) => "Document has no _id",
+                    (_, None) => "Document has no _type",
+                    _ => "Document has no source",
+                };
+
+                failures.push(json!({
+                    "index": index.canonical_name(),
+                    "type": mapping_name,
+                    "id": doc_key,
+                    "cause": {
+                        "type": "illegal_argument_exception",
+                        "reason": reason,
+                    },
+                    "status": 400,
+                }));
+                continue;
+            }
+        };
+
+        for operation in operations.iter() {
+            operation.apply(&mut source);
+        }
+
+        match replace_document_version(index, &index_metadata, mapping_name, doc_key, &serde_json::Value::Object(source), version) {
+            Ok(_) => updated += 1,
+            Err(error) => {
+                if let WriteError::VersionConflict { .. } = error {
+                    version_conflicts += 1;
+                }
+
+                failures.push(json!({
+                    "index": index.canonical_name(),
+                    "type": mapping_name,
+                    "id": doc_key,
+                    "cause": {
+                        "type": error.error_type(),
+                        "reason": error.reason(),
+                    },
+                    "status": write_error_status(&error).to_u16(),
+                }));
+            }
+        }
+    }
+
+    let took = millis_since(start_time);
+
+    Ok(json_response(status::Ok, json!({
+        "took": took,
+        "timed_out": false,
+        "total": docs.len(),
+        "updated": updated,
+        "version_conflicts": version_conflicts,
+        "failures": failures,
+    })))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use kite::{Term, Query, TermScorer};
+    use kite::schema::{Schema, FieldType, FIELD_INDEXED};
+
+    use query_parser::QueryBuildContext;
+
+    use super::parse_request_query;
+
+    fn build_request_query(request_json: Option<&::serde_json::Value>) -> Result<Query, String> {
+        let mut schema = Schema::new();
+        schema.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        parse_request_query(request_json).map(|query| query.build(&QueryBuildContext::new(), &schema).unwrap())
+    }
+
+    #[test]
+    fn test_query() {
+        let mut schema = Schema::new();
+        let title_field = schema.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse_request_query(Some(&json!({
+            "query": {
+                "term": {
+                    "title": "hello"
+                }
+            }
+        }))).map(|query| query.build(&QueryBuildContext::new(), &schema).unwrap());
+
+        assert_eq!(query, Ok(Query::Term {
+            field: title_field,
+            term: Term::from_string("hello"),
+            scorer: TermScorer::default(),
+        }));
+    }
+
+    #[test]
+    fn test_no_query_matches_everything() {
+        assert_eq!(build_request_query(None), Ok(Query::all()));
+        assert_eq!(build_request_query(Some(&json!({}))), Ok(Query::all()));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_request() {
+        assert_eq!(build_request_query(Some(&json!([]))), Err("Request must be an object".to_string()));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_query() {
+        let query = build_request_query(Some(&json!({
+            "query": {
+                "foo": {}
+            }
+        })));
+
+        assert!(query.unwrap_err().starts_with("Query error at \"query"));
+    }
+}
@@ -1,25 +1,97 @@
 use std::io::Read;
+use std::collections::BTreeMap;
 
 use serde_json;
+use url::form_urlencoded;
+use kite_rocksdb::RocksDBReader;
 
-use document::DocumentSource;
+use index::Index;
+use index::lookup::{find_document_in_mapping, contains_document_without_id, read_stored_string, read_source, read_version};
+use index::write::{index_document, update_document, delete_document};
+use search::source_filter::{SourceFilter, apply_url_param as apply_source_filter_url_param};
 
 use api::persistent;
 use api::iron::prelude::*;
 use api::iron::status;
 use api::router::Router;
-use api::utils::json_response;
+use api::utils::{json_response, write_error_status};
+
+
+/// Builds the JSON representation of a document for the get and multi get APIs
+///
+/// Documents that don't exist are represented with "found" set to false. Documents indexed
+/// before the "_id" field was added are found but only their key can be returned.
+pub fn document_json(index: &Index, index_reader: &RocksDBReader, mapping_name: Option<&str>, doc_key: &str, fields: &[String], source_filter: &SourceFilter) -> serde_json::Value {
+    let doc_ref = match find_document_in_mapping(index_reader, mapping_name, doc_key) {
+        Some(doc_ref) => doc_ref,
+        None if contains_document_without_id(index_reader, doc_key) => {
+            return json!({
+                "_index": index.canonical_name(),
+                "_type": mapping_name,
+                "_id": doc_key,
+                "_version": 1,
+                "found": true,
+            });
+        }
+        None => {
+            return json!({
+                "_index": index.canonical_name(),
+                "_type": mapping_name,
+                "_id": doc_key,
+                "found": false,
+            });
+        }
+    };
+
+    let type_field = index_reader.schema().get_field_by_name("_type");
+    let mut document = json!({
+        "_index": index.canonical_name(),
+        "_type": read_stored_string(index_reader, type_field, doc_ref).or(mapping_name.map(|mapping_name| mapping_name.to_string())),
+        "_id": doc_key,
+        "_version": read_version(index_reader, doc_ref),
+        "found": true,
+    });
+
+    if source_filter.enabled {
+        if let Some(source) = read_source(index_reader, doc_ref).and_then(|source| source_filter.apply(&source)) {
+            document["_source"] = source;
+        }
+    }
+
+    if !fields.is_empty() {
+        let mut field_values = BTreeMap::new();
+
+        for field_name in fields.iter() {
+            let field_ref = match index_reader.schema().get_field_by_name(field_name) {
+                Some(field_ref) => field_ref,
+                None => {
+                    warn!("unknown field {:?}", field_name);
+                    continue;
+                }
+            };
+
+            if let Ok(Some(value)) = index_reader.read_stored_field(field_ref, doc_ref) {
+                field_values.insert(field_name.clone(), vec![value]);
+            }
+        }
+
+        document["fields"] = json!(field_values);
+    }
+
+    document
+}
 
 
 pub fn view_get_doc(req: &mut Request) -> IronResult<Response> {
     let ref system = get_system!(req);
     let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
     let ref mapping_name = read_path_parameter!(req, "mapping").unwrap_or("");
-    // let ref doc_key = read_path_parameter!(req, "doc").unwrap_or("");
+    let ref doc_key = read_path_parameter!(req, "doc").unwrap_or("");
 
     // Get index
     let cluster_metadata = system.metadata.read().unwrap();
     let index = get_index_or_404!(cluster_metadata, *index_name);
+    let index_reader = index.store.reader();
     let index_metadata = index.metadata.read().unwrap();
 
     // Check that the mapping exists
@@ -27,26 +99,57 @@ pub fn view_get_doc(req: &mut Request) -> IronResult<Response> {
         return Ok(json_response(status::NotFound, json!({"message": "Mapping not found"})));
     }
 
-    // Find document
-    /*
-    let index_reader = index.store.reader();
-    let doc = match index_reader.get_document_by_key(doc_key) {
-        Some(doc) => doc,
-        None => {
-            return Ok(json_response(status::NotFound, "{\"message\": \"Document not found\"}"));
+    // Parse URL parameters
+    let mut fields = Vec::new();
+    let mut source_filter: Option<SourceFilter> = None;
+    if let Some(ref url_query) = req.url.query() {
+        for (key, value) in form_urlencoded::parse(url_query.as_bytes()) {
+            match key.as_ref() {
+                "fields" | "stored_fields" => {
+                    fields.extend(value.split(",").map(|field_name| field_name.to_owned()));
+                }
+                "_source" | "_source_include" | "_source_includes" | "_source_exclude" | "_source_excludes" => {
+                    let mut url_source_filter = source_filter.take().unwrap_or_default();
+                    apply_source_filter_url_param(&mut url_source_filter, key.as_ref(), value.as_ref());
+                    source_filter = Some(url_source_filter);
+                }
+                _ => warn!("unrecognised GET parameter {:?}", key),
+            }
         }
-    };
-    */
+    }
 
+    // The source isn't returned by default when specific fields are requested
+    let source_filter = source_filter.unwrap_or_else(|| {
+        if fields.is_empty() { SourceFilter::default() } else { SourceFilter::disabled() }
+    });
+
+    let document = document_json(index, &index_reader, Some(*mapping_name), doc_key, &fields, &source_filter);
+
+    if document["found"] == json!(true) {
+        Ok(json_response(status::Ok, document))
+    } else {
+        Ok(json_response(status::NotFound, document))
+    }
+}
+
+
+pub fn view_head_doc(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+    let ref mapping_name = read_path_parameter!(req, "mapping").unwrap_or("");
+    let ref doc_key = read_path_parameter!(req, "doc").unwrap_or("");
 
-    // Build JSON document
-    // TODO: This is probably completely wrong
-    // let json_object = BTreeMap::new();
-    // FIXME: for (field_name, field_value) in doc.fields.iter() {
-    // FIXME:     json_object.insert(field_name.clone(), Json::Array(field_value.iter().map(|v| v.term.as_json()).collect::<Vec<_>>()));
-    // FIXME: }
+    // Get index
+    let cluster_metadata = system.metadata.read().unwrap();
+    let index = get_index_or_404!(cluster_metadata, *index_name);
+    let index_reader = index.store.reader();
 
-    return Ok(json_response(status::Ok, json!({})));
+    // HEAD responses have no body
+    match find_document_in_mapping(&index_reader, Some(*mapping_name), doc_key) {
+        Some(_) => Ok(Response::with(status::Ok)),
+        None if contains_document_without_id(&index_reader, doc_key) => Ok(Response::with(status::Ok)),
+        None => Ok(Response::with(status::NotFound)),
+    }
 }
 
 
@@ -61,31 +164,62 @@ pub fn view_put_doc(req: &mut Request) -> IronResult<Response> {
     let index = get_index_or_404!(cluster_metadata, *index_name);
     let index_metadata = index.metadata.read().unwrap();
 
-    let doc = {
-        // Find mapping
-        let mapping = match index_metadata.mappings.get(*mapping_name) {
-            Some(mapping) => mapping,
-            None => {
-                return Ok(json_response(status::NotFound, json!({"message": "Mapping not found"})));
-            }
-        };
-
-        // Create document
-        if let Some(data) = json_from_request_body!(req) {
-            let document_source = DocumentSource {
-                key: doc_key,
-                data: data.as_object().unwrap(),
-            };
-            document_source.prepare(mapping).unwrap()
-        } else {
-            return Ok(json_response(status::NotFound, json!({"message": "No data"})));
+    let data = match json_from_request_body!(req) {
+        Some(data) => data,
+        None => {
+            return Ok(json_response(status::BadRequest, json!({"message": "No data"})));
         }
     };
 
-    index.store.insert_or_update_document(&doc).unwrap();
+    match index_document(index, &index_metadata, mapping_name, doc_key, &data, false) {
+        Ok(result) => {
+            Ok(json_response(if result.created { status::Created } else { status::Ok }, json!({
+                "_index": index.canonical_name(),
+                "_type": mapping_name,
+                "_id": doc_key,
+                "_version": result.version,
+                "created": result.created,
+            })))
+        }
+        Err(error) => {
+            Ok(json_response(write_error_status(&error), json!({"message": error.reason()})))
+        }
+    }
+}
+
+
+pub fn view_update_doc(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+    let ref mapping_name = read_path_parameter!(req, "mapping").unwrap_or("");
+    let ref doc_key = read_path_parameter!(req, "doc").unwrap_or("");
+
+    // Get index
+    let cluster_metadata = system.metadata.read().unwrap();
+    let index = get_index_or_404!(cluster_metadata, *index_name);
+    let index_metadata = index.metadata.read().unwrap();
+
+    let update = match json_from_request_body!(req) {
+        Some(update) => update,
+        None => {
+            return Ok(json_response(status::BadRequest, json!({"message": "No data"})));
+        }
+    };
 
-    // TODO: {"_index":"wagtail","_type":"searchtests_searchtest","_id":"searchtests_searchtest:5378","_version":1,"created":true}
-    return Ok(json_response(status::Ok, json!({})));
+    match update_document(index, &index_metadata, mapping_name, doc_key, &update) {
+        Ok(result) => {
+            Ok(json_response(if result.created { status::Created } else { status::Ok }, json!({
+                "_index": index.canonical_name(),
+                "_type": mapping_name,
+                "_id": doc_key,
+                "_version": result.version,
+                "result": if result.created { "created" } else { "updated" },
+            })))
+        }
+        Err(error) => {
+            Ok(json_response(write_error_status(&error), json!({"message": error.reason()})))
+        }
+    }
 }
 
 
@@ -105,13 +239,19 @@ pub fn view_delete_doc(req: &mut Request) -> IronResult<Response> {
         return Ok(json_response(status::NotFound, json!({"message": "Mapping not found"})));
     }
 
-    // Make sure the document exists
-    if !index.store.reader().contains_document_key(doc_key) {
-        return Ok(json_response(status::NotFound, json!({"message": "Document not found"})));
+    match delete_document(index, mapping_name, doc_key) {
+        Ok(version) => {
+            Ok(json_response(status::Ok, json!({
+                "_index": index.canonical_name(),
+                "_type": mapping_name,
+                "_id": doc_key,
+                "_version": version,
+                "found": true,
+                "result": "deleted",
+            })))
+        }
+        Err(error) => {
+            Ok(json_response(write_error_status(&error), json!({"message": error.reason()})))
+        }
     }
-
-    // Delete document
-    index.store.remove_document_by_key(doc_key).unwrap();
-
-    return Ok(json_response(status::Ok, json!({})));
 }
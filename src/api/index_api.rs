@@ -5,8 +5,9 @@ use serde_json;
 use kite_rocksdb::RocksDBStore;
 use uuid::Uuid;
 
-use index::Index;
+use index::{Index, term_dictionary_path};
 use index::metadata::IndexMetadata;
+use index::term_dictionary::TermDictionary;
 use index::metadata::parse::parse as parse_index_metadata;
 
 use api::persistent;
@@ -71,7 +72,9 @@ pub fn view_put_index(req: &mut Request) -> IronResult<Response> {
             // Create index
             let mut indices_dir = system.get_indices_dir();
             indices_dir.push(index_name);
-            let index = Index::new(Uuid::new_v4(), index_name.clone().to_owned(), metadata, RocksDBStore::create(indices_dir).unwrap());
+            let store = RocksDBStore::create(indices_dir).unwrap();
+            let term_dictionary = TermDictionary::create(term_dictionary_path(store.path())).unwrap();
+            let index = Index::new(Uuid::new_v4(), index_name.clone().to_owned(), metadata, store, term_dictionary);
             index.metadata.read().unwrap().save(index.metadata_path()).unwrap();
             let index_ref = cluster_metadata.insert_index(index);
 
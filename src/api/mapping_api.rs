@@ -56,6 +56,7 @@ pub fn view_put_mapping(req: &mut Request) -> IronResult<Response> {
         let index_reader = index.store.reader();
         let schema = index_reader.schema();
         let mut new_fields: HashMap<String, (FieldType, FieldFlags)>  = HashMap::new();
+        let mut fields = Vec::new();
         for (name, property) in mapping.properties.iter() {
             if let MappingProperty::Field(ref field_mapping) = *property {
                 let field_type = match field_mapping.data_type {
@@ -76,22 +77,31 @@ pub fn view_put_mapping(req: &mut Request) -> IronResult<Response> {
                     field_flags |= FIELD_STORED;
                 }
 
-                // Check if this field already exists
-                if let Some(field_ref) = schema.get_field_by_name(&name) {
-                    let field_info = schema.get(&field_ref).expect("get_field_by_name returned an invalid FieldRef");
-
-                    // Field already exists. Check for conflicting type or flags, otherwise ignore.
-                    if field_info.field_type == field_type && field_info.field_flags == field_flags {
-                        continue;
-                    } else {
-                        // Conflict!
-                        // TODO: Better error
-                        return Ok(json_response(status::BadRequest, json!({"acknowledged": false})));
-                    }
+                fields.push((name.clone(), field_type, field_flags));
+
+                // Integers and dates have a separate field for the terms that range queries search
+                if field_mapping.has_sortable_terms() {
+                    fields.push((mapping::sortable_field_name(name), FieldType::PlainString, FIELD_INDEXED));
                 }
+            }
+        }
 
-                new_fields.insert(name.clone(), (field_type, field_flags));
+        for (name, field_type, field_flags) in fields {
+            // Check if this field already exists
+            if let Some(field_ref) = schema.get_field_by_name(&name) {
+                let field_info = schema.get(&field_ref).expect("get_field_by_name returned an invalid FieldRef");
+
+                // Field already exists. Check for conflicting type or flags, otherwise ignore.
+                if field_info.field_type == field_type && field_info.field_flags == field_flags {
+                    continue;
+                } else {
+                    // Conflict!
+                    // TODO: Better error
+                    return Ok(json_response(status::BadRequest, json!({"acknowledged": false})));
+                }
             }
+
+            new_fields.insert(name, (field_type, field_flags));
         }
 
         new_fields
@@ -113,7 +123,11 @@ pub fn view_put_mapping(req: &mut Request) -> IronResult<Response> {
 
         for (name, property) in mapping.properties.iter_mut() {
             if let MappingProperty::Field(ref mut field_mapping) = *property {
-                field_mapping.index_ref = schema.get_field_by_name(&name)
+                field_mapping.index_ref = schema.get_field_by_name(&name);
+
+                if field_mapping.has_sortable_terms() {
+                    field_mapping.sortable_ref = schema.get_field_by_name(&mapping::sortable_field_name(&name));
+                }
             }
         }
     }
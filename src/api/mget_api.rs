@@ -0,0 +1,306 @@
+use std::io::Read;
+
+use serde_json;
+use url::form_urlencoded;
+
+use search::source_filter::{SourceFilter, parse as parse_source_filter, apply_url_param as apply_source_filter_url_param};
+
+use api::persistent;
+use api::iron::prelude::*;
+use api::iron::status;
+use api::router::Router;
+use api::utils::json_response;
+use api::document_api::document_json;
+
+
+/// A document requested by a multi get request
+#[derive(Debug, PartialEq)]
+struct DocumentRequest {
+    index_name: Option<String>,
+    mapping_name: Option<String>,
+    doc_key: String,
+    fields: Vec<String>,
+    source_filter: SourceFilter,
+}
+
+
+fn parse_fields(json: &serde_json::Value) -> Option<Vec<String>> {
+    match *json {
+        serde_json::Value::String(ref field_names) => Some(field_names.split(",").map(|field_name| field_name.to_owned()).collect()),
+        serde_json::Value::Array(ref array) => array.iter().map(|item| item.as_str().map(|field_name| field_name.to_owned())).collect(),
+        _ => None,
+    }
+}
+
+
+/// Parses the "docs" or "ids" key of a multi get request
+fn parse_document_requests(json: &serde_json::Value, index_name: Option<&str>, fields: &[String], source_filter: &SourceFilter) -> Result<Vec<DocumentRequest>, String> {
+    let object = match json.as_object() {
+        Some(object) => object,
+        None => return Err("expected an object".to_string()),
+    };
+
+    let mut document_requests = Vec::new();
+
+    if let Some(docs_json) = object.get("docs") {
+        let docs = match docs_json.as_array() {
+            Some(docs) => docs,
+            None => return Err("\"docs\" must be an array".to_string()),
+        };
+
+        for doc_json in docs.iter() {
+            let doc_object = match doc_json.as_object() {
+                Some(doc_object) => doc_object,
+                None => return Err("each item in \"docs\" must be an object".to_string()),
+            };
+
+            let doc_key = match doc_object.get("_id") {
+                Some(&serde_json::Value::String(ref doc_key)) => doc_key.clone(),
+                Some(&serde_json::Value::Number(ref doc_key)) => doc_key.to_string(),
+                _ => return Err("each item in \"docs\" must have an \"_id\"".to_string()),
+            };
+
+            let doc_fields = match doc_object.get("stored_fields").or(doc_object.get("fields")) {
+                Some(fields_json) => {
+                    match parse_fields(fields_json) {
+                        Some(doc_fields) => doc_fields,
+                        None => return Err("\"stored_fields\" must be a string or an array of strings".to_string()),
+                    }
+                }
+                None => fields.to_vec(),
+            };
+
+            let doc_source_filter = match doc_object.get("_source") {
+                Some(source_json) => {
+                    match parse_source_filter(source_json) {
+                        Ok(doc_source_filter) => doc_source_filter,
+                        Err(error) => return Err(format!("Source filter error: {:?}", error)),
+                    }
+                }
+                None => source_filter.clone(),
+            };
+
+            document_requests.push(DocumentRequest {
+                index_name: doc_object.get("_index").and_then(|index_name| index_name.as_str()).or(index_name).map(|index_name| index_name.to_owned()),
+                mapping_name: doc_object.get("_type").and_then(|mapping_name| mapping_name.as_str()).map(|mapping_name| mapping_name.to_owned()),
+                doc_key: doc_key,
+                fields: doc_fields,
+                source_filter: doc_source_filter,
+            });
+        }
+    } else if let Some(ids_json) = object.get("ids") {
+        let ids = match ids_json.as_array() {
+            Some(ids) => ids,
+            None => return Err("\"ids\" must be an array".to_string()),
+        };
+
+        for id_json in ids.iter() {
+            let doc_key = match *id_json {
+                serde_json::Value::String(ref doc_key) => doc_key.clone(),
+                serde_json::Value::Number(ref doc_key) => doc_key.to_string(),
+                _ => return Err("each item in \"ids\" must be a string".to_string()),
+            };
+
+            document_requests.push(DocumentRequest {
+                index_name: index_name.map(|index_name| index_name.to_owned()),
+                mapping_name: None,
+                doc_key: doc_key,
+                fields: fields.to_vec(),
+                source_filter: source_filter.clone(),
+            });
+        }
+    } else {
+        return Err("expected \"docs\" or \"ids\"".to_string());
+    }
+
+    Ok(document_requests)
+}
+
+
+pub fn view_mget(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let index_name = read_path_parameter!(req, "index").map(|index_name| index_name.to_owned());
+
+    // Parse URL parameters
+    // These give the defaults for each document
+    let mut fields = Vec::new();
+    let mut source_filter: Option<SourceFilter> = None;
+    if let Some(ref url_query) = req.url.query() {
+        for (key, value) in form_urlencoded::parse(url_query.as_bytes()) {
+            match key.as_ref() {
+                "fields" | "stored_fields" => {
+                    fields.extend(value.split(",").map(|field_name| field_name.to_owned()));
+                }
+                "_source" | "_source_include" | "_source_includes" | "_source_exclude" | "_source_excludes" => {
+                    let mut url_source_filter = source_filter.take().unwrap_or_default();
+                    apply_source_filter_url_param(&mut url_source_filter, key.as_ref(), value.as_ref());
+                    source_filter = Some(url_source_filter);
+                }
+                _ => warn!("unrecognised GET parameter {:?}", key),
+            }
+        }
+    }
+
+    // The source isn't returned by default when specific fields are requested
+    let source_filter = source_filter.unwrap_or_else(|| {
+        if fields.is_empty() { SourceFilter::default() } else { SourceFilter::disabled() }
+    });
+
+    let document_requests = match json_from_request_body!(req) {
+        Some(json) => {
+            match parse_document_requests(&json, index_name.as_ref().map(|index_name| index_name.as_str()), &fields, &source_filter) {
+                Ok(document_requests) => document_requests,
+                Err(error) => {
+                    return Ok(json_response(status::BadRequest, json!({"message": error})));
+                }
+            }
+        }
+        None => {
+            return Ok(json_response(status::BadRequest, json!({"message": "Request body is required"})));
+        }
+    };
+
+    // Lock cluster metadata
+    let cluster_metadata = system.metadata.read().unwrap();
+
+    // Fetch each document
+    // Missing documents and indices are reported in place so the results line up with the request
+    let mut docs = Vec::new();
+    for document_request in document_requests.iter() {
+        let index_name = match document_request.index_name {
+            Some(ref index_name) => index_name,
+            None => {
+                docs.push(json!({
+                    "_index": serde_json::Value::Null,
+                    "_type": document_request.mapping_name,
+                    "_id": document_request.doc_key,
+                    "error": {
+                        "type": "action_request_validation_exception",
+                        "reason": "index is missing",
+                    }
+                }));
+                continue;
+            }
+        };
+
+        // Aliases may be used as long as they point to a single index
+        let index_refs = cluster_metadata.names.find(index_name);
+        let index = match index_refs.first().and_then(|index_ref| cluster_metadata.indices.get(index_ref)) {
+            Some(index) if index_refs.len() == 1 => index,
+            _ => {
+                docs.push(json!({
+                    "_index": index_name,
+                    "_type": document_request.mapping_name,
+                    "_id": document_request.doc_key,
+                    "error": {
+                        "type": "index_not_found_exception",
+                        "reason": "no such index",
+                    }
+                }));
+                continue;
+            }
+        };
+
+        let index_reader = index.store.reader();
+        docs.push(document_json(index, &index_reader, document_request.mapping_name.as_ref().map(|mapping_name| mapping_name.as_str()), &document_request.doc_key, &document_request.fields, &document_request.source_filter));
+    }
+
+    Ok(json_response(status::Ok, json!({"docs": docs})))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::source_filter::SourceFilter;
+
+    use super::{DocumentRequest, parse_document_requests};
+
+    #[test]
+    fn test_parse_docs() {
+        let document_requests = parse_document_requests(&json!({
+            "docs": [
+                {"_id": "1"},
+                {"_index": "other", "_type": "user", "_id": 2, "stored_fields": ["name"], "_source": false},
+            ]
+        }), Some("test"), &[], &SourceFilter::default());
+
+        assert_eq!(document_requests, Ok(vec![
+            DocumentRequest {
+                index_name: Some("test".to_string()),
+                mapping_name: None,
+                doc_key: "1".to_string(),
+                fields: vec![],
+                source_filter: SourceFilter::default(),
+            },
+            DocumentRequest {
+                index_name: Some("other".to_string()),
+                mapping_name: Some("user".to_string()),
+                doc_key: "2".to_string(),
+                fields: vec!["name".to_string()],
+                source_filter: SourceFilter::disabled(),
+            },
+        ]));
+    }
+
+    #[test]
+    fn test_parse_ids() {
+        let document_requests = parse_document_requests(&json!({
+            "ids": ["1", 2]
+        }), None, &["name".to_string()], &SourceFilter::disabled());
+
+        assert_eq!(document_requests, Ok(vec![
+            DocumentRequest {
+                index_name: None,
+                mapping_name: None,
+                doc_key: "1".to_string(),
+                fields: vec!["name".to_string()],
+                source_filter: SourceFilter::disabled(),
+            },
+            DocumentRequest {
+                index_name: None,
+                mapping_name: None,
+                doc_key: "2".to_string(),
+                fields: vec!["name".to_string()],
+                source_filter: SourceFilter::disabled(),
+            },
+        ]));
+    }
+
+    #[test]
+    fn test_parse_fields_string() {
+        let document_requests = parse_document_requests(&json!({
+            "docs": [
+                {"_id": "1", "fields": "name,email"},
+            ]
+        }), Some("test"), &[], &SourceFilter::default()).unwrap();
+
+        assert_eq!(document_requests[0].fields, vec!["name".to_string(), "email".to_string()]);
+    }
+
+    #[test]
+    fn test_gives_error_for_missing_id() {
+        let document_requests = parse_document_requests(&json!({
+            "docs": [
+                {"_index": "test"},
+            ]
+        }), None, &[], &SourceFilter::default());
+
+        assert_eq!(document_requests, Err("each item in \"docs\" must have an \"_id\"".to_string()));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_ids() {
+        let document_requests = parse_document_requests(&json!({
+            "ids": [true]
+        }), Some("test"), &[], &SourceFilter::default());
+
+        assert_eq!(document_requests, Err("each item in \"ids\" must be a string".to_string()));
+    }
+
+    #[test]
+    fn test_gives_error_for_missing_docs_and_ids() {
+        let document_requests = parse_document_requests(&json!({}), Some("test"), &[], &SourceFilter::default());
+
+        assert_eq!(document_requests, Err("expected \"docs\" or \"ids\"".to_string()));
+    }
+}
@@ -10,6 +10,10 @@ mod document_api;
 mod index_api;
 mod mapping_api;
 mod bulk_api;
+mod by_query_api;
+mod mget_api;
+mod reindex_api;
+mod analyze_api;
 
 use std::sync::Arc;
 
@@ -37,21 +41,53 @@ fn get_router() -> Router {
     router!(get "/" => view_home,
             get "/:index/_count" => search_api::view_count,
             post "/:index/_count" => search_api::view_count,
+            get "/:index/_validate/query" => search_api::view_validate_query,
+            post "/:index/_validate/query" => search_api::view_validate_query,
             get "/:index/_search" => search_api::view_search,
             post "/:index/_search" => search_api::view_search,
+            get "/_msearch" => search_api::view_msearch,
+            post "/_msearch" => search_api::view_msearch,
+            get "/:index/_msearch" => search_api::view_msearch,
+            post "/:index/_msearch" => search_api::view_msearch,
+            get "/_search/scroll" => search_api::view_scroll,
+            post "/_search/scroll" => search_api::view_scroll,
+            get "/_search/scroll/:scroll_id" => search_api::view_scroll,
+            post "/_search/scroll/:scroll_id" => search_api::view_scroll,
+            delete "/_search/scroll" => search_api::view_clear_scroll,
+            delete "/_search/scroll/:scroll_id" => search_api::view_clear_scroll,
             get "/_alias/:alias" => alias_api::view_get_global_alias,
             get "/:index/_alias" => alias_api::view_get_alias_list,
+            get "/_analyze" => analyze_api::view_analyze,
+            post "/_analyze" => analyze_api::view_analyze,
+            get "/:index/_analyze" => analyze_api::view_analyze,
+            post "/:index/_analyze" => analyze_api::view_analyze,
             get "/:index/_alias/:alias" => alias_api::view_get_alias,
             put "/:index/_alias/:alias" => alias_api::view_put_alias,
+            get "/_mget" => mget_api::view_mget,
+            post "/_mget" => mget_api::view_mget,
+            get "/:index/_mget" => mget_api::view_mget,
+            post "/:index/_mget" => mget_api::view_mget,
             get "/:index/:mapping/:doc" => document_api::view_get_doc,
+            head "/:index/:mapping/:doc" => document_api::view_head_doc,
             put "/:index/:mapping/:doc" => document_api::view_put_doc,
             delete "/:index/:mapping/:doc" => document_api::view_delete_doc,
+            post "/:index/:mapping/:doc/_update" => document_api::view_update_doc,
+            get "/:index/:mapping/:doc/_explain" => search_api::view_explain,
+            post "/:index/:mapping/:doc/_explain" => search_api::view_explain,
             get "/:index" => index_api::view_get_index,
             put "/:index" => index_api::view_put_index,
             delete "/:index" => index_api::view_delete_index,
+            post "/:index/_delete_by_query" => by_query_api::view_delete_by_query,
+            post "/:index/_update_by_query" => by_query_api::view_update_by_query,
+            post "/_reindex" => reindex_api::view_reindex,
             post "/:index/_refresh" => index_api::view_post_refresh_index,
             put "/:index/_mapping/:mapping" => mapping_api::view_put_mapping,
-            post "/_bulk" => bulk_api::view_post_bulk)
+            post "/_bulk" => bulk_api::view_post_bulk,
+            put "/_bulk" => bulk_api::view_post_bulk,
+            post "/:index/_bulk" => bulk_api::view_post_bulk,
+            put "/:index/_bulk" => bulk_api::view_post_bulk,
+            post "/:index/:mapping/_bulk" => bulk_api::view_post_bulk,
+            put "/:index/:mapping/_bulk" => bulk_api::view_post_bulk)
 }
 
 
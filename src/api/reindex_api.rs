@@ -0,0 +1,273 @@
+use std::io::Read;
+use std::time::Instant;
+
+use serde_json;
+
+use index::lookup::{find_document, read_stored_string, read_source};
+use index::write::index_document;
+
+use api::persistent;
+use api::iron::prelude::*;
+use api::iron::status;
+use api::router::Router;
+use api::utils::{json_response, write_error_status, millis_since};
+use api::by_query_api::find_matching_documents;
+
+
+#[derive(Debug, PartialEq)]
+struct ReindexRequest<'a> {
+    source_index_name: &'a str,
+
+    /// Holds the query that selects the documents to copy
+    source_json: &'a serde_json::Value,
+
+    dest_index_name: &'a str,
+
+    /// Documents keep their mapping unless the destination mapping is given
+    dest_mapping_name: Option<&'a str>,
+
+    batch_size: usize,
+}
+
+
+fn parse_reindex_request(request_json: &serde_json::Value) -> Result<ReindexRequest, String> {
+    let source_json = match request_json.as_object().and_then(|request| request.get("source")) {
+        Some(source_json) if source_json.is_object() => source_json,
+        _ => return Err("\"source\" must be an object".to_string()),
+    };
+
+    let dest_json = match request_json.as_object().and_then(|request| request.get("dest")) {
+        Some(dest_json) if dest_json.is_object() => dest_json,
+        _ => return Err("\"dest\" must be an object".to_string()),
+    };
+
+    let source_index_name = match source_json.get("index").and_then(|index_name| index_name.as_str()) {
+        Some(source_index_name) => source_index_name,
+        None => return Err("\"source.index\" is required".to_string()),
+    };
+
+    let dest_index_name = match dest_json.get("index").and_then(|index_name| index_name.as_str()) {
+        Some(dest_index_name) => dest_index_name,
+        None => return Err("\"dest.index\" is required".to_string()),
+    };
+
+    let batch_size = match source_json.get("size") {
+        Some(size_json) => {
+            match size_json.as_u64() {
+                Some(batch_size) if batch_size > 0 => batch_size as usize,
+                _ => return Err("\"source.size\" must be a positive integer".to_string()),
+            }
+        }
+        None => 1000,
+    };
+
+    Ok(ReindexRequest {
+        source_index_name: source_index_name,
+        source_json: source_json,
+        dest_index_name: dest_index_name,
+        dest_mapping_name: dest_json.get("mapping").and_then(|mapping_name| mapping_name.as_str()),
+        batch_size: batch_size,
+    })
+}
+
+
+/// Copies documents from one index into another
+///
+/// The keys of the matching documents are found up front, then the documents are copied in
+/// batches of "size" documents. Locks are released between batches so a long reindex doesn't
+/// block other requests, documents that change in the meantime are copied as they are when
+/// their batch runs. Each document is re-processed through the destination mapping so the
+/// destination may use different analyzers.
+pub fn view_reindex(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let start_time = Instant::now();
+
+    let request_json = match json_from_request_body!(req) {
+        Some(request_json) => request_json,
+        None => {
+            return Ok(json_response(status::BadRequest, json!({"message": "Request body is required"})));
+        }
+    };
+
+    let request = match parse_reindex_request(&request_json) {
+        Ok(request) => request,
+        Err(error) => {
+            return Ok(json_response(status::BadRequest, json!({"message": error})));
+        }
+    };
+
+    let mut created = 0;
+    let mut updated = 0;
+    let mut batches = 0;
+    let mut failures = Vec::new();
+
+    // Find the keys of the documents to copy
+    // DocRefs are only valid for the reader that found them so each batch looks the documents up again by key
+    let doc_keys = {
+        let cluster_metadata = system.metadata.read().unwrap();
+        let source_index = get_index_or_404!(cluster_metadata, request.source_index_name);
+        get_index_or_404!(cluster_metadata, request.dest_index_name);
+
+        let source_reader = source_index.store.reader();
+        let source_metadata = source_index.metadata.read().unwrap();
+        let doc_refs = match find_matching_documents(&source_reader, &source_metadata, &source_index.term_dictionary, Some(request.source_json)) {
+            Ok(doc_refs) => doc_refs,
+            Err(error) => {
+                return Ok(json_response(status::BadRequest, json!({"message": error})));
+            }
+        };
+
+        let id_field = source_reader.schema().get_field_by_name("_id");
+        let mut doc_keys = Vec::with_capacity(doc_refs.len());
+        for doc_ref in doc_refs {
+            match read_stored_string(&source_reader, id_field, doc_ref) {
+                Some(doc_key) => doc_keys.push(doc_key),
+                None => {
+                    failures.push(json!({
+                        "index": request.dest_index_name,
+                        "type": serde_json::Value::Null,
+                        "id": serde_json::Value::Null,
+                        "cause": {
+                            "type": "illegal_argument_exception",
+                            "reason": "Document has no _id",
+                        },
+                        "status": 400,
+                    }));
+                }
+            }
+        }
+
+        doc_keys
+    };
+
+    for batch in doc_keys.chunks(request.batch_size) {
+        batches += 1;
+
+        let cluster_metadata = system.metadata.read().unwrap();
+        let source_index = get_index_or_404!(cluster_metadata, request.source_index_name);
+        let dest_index = get_index_or_404!(cluster_metadata, request.dest_index_name);
+        let dest_metadata = dest_index.metadata.read().unwrap();
+
+        let source_reader = source_index.store.reader();
+        let type_field = source_reader.schema().get_field_by_name("_type");
+
+        for doc_key in batch.iter() {
+            let doc_ref = match find_document(&source_reader, doc_key) {
+                Some(doc_ref) => doc_ref,
+                None => {
+                    failures.push(json!({
+                        "index": dest_index.canonical_name(),
+                        "type": serde_json::Value::Null,
+                        "id": doc_key,
+                        "cause": {
+                            "type": "document_missing_exception",
+                            "reason": "Document was deleted from the source index",
+                        },
+                        "status": 404,
+                    }));
+                    continue;
+                }
+            };
+
+            let mapping_name = match request.dest_mapping_name {
+                Some(mapping_name) => Some(mapping_name.to_string()),
+                None => read_stored_string(&source_reader, type_field, doc_ref),
+            };
+
+            let result = match (mapping_name.as_ref(), read_source(&source_reader, doc_ref)) {
+                (Some(mapping_name), Some(source)) => {
+                    index_document(dest_index, &dest_metadata, mapping_name, doc_key, &source, false).map_err(|error| {
+                        (write_error_status(&error).to_u16(), error.error_type(), error.reason())
+                    })
+                }
+                (None, _) => Err((400, "illegal_argument_exception", "Document has no mapping".to_string())),
+                (_, None) => Err((400, "illegal_argument_exception", "Document has no source".to_string())),
+            };
+
+            match result {
+                Ok(result) => {
+                    if result.created {
+                        created += 1;
+                    } else {
+                        updated += 1;
+                    }
+                }
+                Err((status_code, error_type, reason)) => {
+                    failures.push(json!({
+                        "index": dest_index.canonical_name(),
+                        "type": mapping_name,
+                        "id": doc_key,
+                        "cause": {
+                            "type": error_type,
+                            "reason": reason,
+                        },
+                        "status": status_code,
+                    }));
+                }
+            }
+        }
+    }
+
+    let took = millis_since(start_time);
+
+    Ok(json_response(status::Ok, json!({
+        "took": took,
+        "timed_out": false,
+        "total": created + updated + failures.len(),
+        "created": created,
+        "updated": updated,
+        "batches": batches,
+        "failures": failures,
+    })))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{ReindexRequest, parse_reindex_request};
+
+    #[test]
+    fn test_parse_reindex_request() {
+        let request_json = json!({
+            "source": {
+                "index": "old",
+                "query": {"match_all": {}},
+                "size": 100
+            },
+            "dest": {
+                "index": "new",
+                "mapping": "user"
+            }
+        });
+
+        assert_eq!(parse_reindex_request(&request_json), Ok(ReindexRequest {
+            source_index_name: "old",
+            source_json: &request_json["source"],
+            dest_index_name: "new",
+            dest_mapping_name: Some("user"),
+            batch_size: 100,
+        }));
+    }
+
+    #[test]
+    fn test_parse_reindex_request_defaults() {
+        let request_json = json!({
+            "source": {"index": "old"},
+            "dest": {"index": "new"}
+        });
+
+        let request = parse_reindex_request(&request_json).unwrap();
+
+        assert_eq!(request.dest_mapping_name, None);
+        assert_eq!(request.batch_size, 1000);
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_request() {
+        assert_eq!(parse_reindex_request(&json!({"dest": {"index": "new"}})), Err("\"source\" must be an object".to_string()));
+        assert_eq!(parse_reindex_request(&json!({"source": {"index": "old"}, "dest": "new"})), Err("\"dest\" must be an object".to_string()));
+        assert_eq!(parse_reindex_request(&json!({"source": {}, "dest": {"index": "new"}})), Err("\"source.index\" is required".to_string()));
+        assert_eq!(parse_reindex_request(&json!({"source": {"index": "old"}, "dest": {}})), Err("\"dest.index\" is required".to_string()));
+        assert_eq!(parse_reindex_request(&json!({"source": {"index": "old", "size": 0}, "dest": {"index": "new"}})), Err("\"source.size\" must be a positive integer".to_string()));
+    }
+}
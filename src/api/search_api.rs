@@ -1,20 +1,37 @@
 use std::io::Read;
-use std::collections::BTreeMap;
+use std::cmp;
+use std::usize;
+use std::time::{Duration, Instant};
 
 use serde_json;
 use url::form_urlencoded;
-use kite::document::DocRef;
 use kite::query::Query;
+use kite::document::DocRef;
 use kite::collectors::top_score::TopScoreCollector;
 use kite::collectors::total_count::TotalCountCollector;
 
 use query_parser::{QueryBuildContext, parse as parse_query};
+use search::sort::{SortSpec, ResolvedSortSpec, SortValueSource, SortOrder, SortMissing, parse as parse_sort, parse_url_param as parse_sort_url_param, add_tiebreaker, parse_search_after};
+use search::aggregations::{AggregationBuilder, parse as parse_aggregations, build as build_aggregations};
+use search::highlight::{HighlightSpec, QueryTerms, parse as parse_highlight};
+use search::source_filter::{SourceFilter, parse as parse_source_filter, apply_url_param as apply_source_filter_url_param};
+use search::hits::HitFormat;
+use search::scroll::{ScrollContext, ScrollCursor, parse_keep_alive};
+use search::explain::Explainer;
+use index::lookup::{find_document_in_mapping, contains_document_without_id, read_stored_string};
+use collectors::multi::MultiCollector;
+use collectors::top_sorted::{TopSortedCollector, SortedDocument};
+use collectors::aggregations::AggregationsCollector;
+
+use system::System;
+use index::Index;
+use cluster::metadata::ClusterMetadata;
 
 use api::persistent;
 use api::iron::prelude::*;
 use api::iron::status;
 use api::router::Router;
-use api::utils::json_response;
+use api::utils::{json_response, query_error_json, query_build_error_json, millis_since};
 
 
 pub fn view_count(req: &mut Request) -> IronResult<Response> {
@@ -35,16 +52,19 @@ pub fn view_count(req: &mut Request) -> IronResult<Response> {
 
             match query {
                 Ok(query) => {
+                    let query = match query.build(&QueryBuildContext::new().set_index_metadata(&index_metadata).set_index_reader(&index_reader).set_term_dictionary(&index.term_dictionary).no_score(), &index_reader.schema()) {
+                        Ok(query) => query,
+                        Err(error) => {
+                            return Ok(json_response(status::BadRequest, query_build_error_json(error)));
+                        }
+                    };
+
                     let mut collector = TotalCountCollector::new();
-                    index_reader.search(&mut collector, &query.build(&QueryBuildContext::new().set_index_metadata(&index_metadata).no_score(), &index_reader.schema())).unwrap();
+                    index_reader.search(&mut collector, &query).unwrap();
                     collector.get_total_count()
                 }
-                Err(_) => {
-                    // TODO: What specifically is bad about the Query?
-                    let mut response = Response::with((status::BadRequest,
-                                                       "{\"message\": \"Query error\"}"));
-                    response.headers.set_raw("Content-Type", vec![b"application/json".to_vec()]);
-                    return Ok(response);
+                Err(error) => {
+                    return Ok(json_response(status::BadRequest, query_error_json(error, &query_json)));
                 }
             }
         }
@@ -59,9 +79,11 @@ pub fn view_count(req: &mut Request) -> IronResult<Response> {
 }
 
 
-pub fn view_search(req: &mut Request) -> IronResult<Response> {
+pub fn view_explain(req: &mut Request) -> IronResult<Response> {
     let ref system = get_system!(req);
     let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+    let ref mapping_name = read_path_parameter!(req, "mapping").unwrap_or("");
+    let ref doc_key = read_path_parameter!(req, "doc").unwrap_or("");
 
     // Get index
     let cluster_metadata = system.metadata.read().unwrap();
@@ -69,96 +91,831 @@ pub fn view_search(req: &mut Request) -> IronResult<Response> {
     let index_reader = index.store.reader();
     let index_metadata = index.metadata.read().unwrap();
 
+    // Parse query
+    let request_json = match json_from_request_body!(req) {
+        Some(request_json) => request_json,
+        None => {
+            return Ok(json_response(status::BadRequest, json!({"message": "Missing query"})));
+        }
+    };
+
+    let query = match request_json.as_object().and_then(|request| request.get("query")) {
+        Some(query_json) => parse_query(query_json),
+        None => {
+            return Ok(json_response(status::BadRequest, json!({"message": "Missing query"})));
+        }
+    };
+
+    let query = match query {
+        Ok(query) => query.build(&QueryBuildContext::new().set_index_metadata(&index_metadata).set_index_reader(&index_reader).set_term_dictionary(&index.term_dictionary), &index_reader.schema()),
+        Err(error) => {
+            return Ok(json_response(status::BadRequest, query_error_json(error, &request_json)));
+        }
+    };
+
+    let query = match query {
+        Ok(query) => query,
+        Err(error) => {
+            return Ok(json_response(status::BadRequest, query_build_error_json(error)));
+        }
+    };
+
+    let doc_ref = match find_document_in_mapping(&index_reader, Some(*mapping_name), doc_key) {
+        Some(doc_ref) => doc_ref,
+        None if contains_document_without_id(&index_reader, doc_key) => {
+            return Ok(json_response(status::BadRequest, json!({
+                "message": "Document was indexed before its key could be searched for, it must be reindexed to be explained",
+            })));
+        }
+        None => {
+            return Ok(json_response(status::NotFound, json!({
+                "_index": index.canonical_name(),
+                "_type": mapping_name,
+                "_id": doc_key,
+                "matched": false,
+            })));
+        }
+    };
+
+    let explainer = Explainer::new(&index_reader, &index_metadata);
+    let explanation = explainer.explain(&query, doc_ref.as_u64());
+
+    let mut response = json!({
+        "_index": index.canonical_name(),
+        "_type": mapping_name,
+        "_id": doc_key,
+        "matched": explanation.is_some(),
+    });
+
+    if let Some(explanation) = explanation {
+        response["explanation"] = explanation.as_json();
+    }
+
+    Ok(json_response(status::Ok, response))
+}
+
+
+pub fn view_validate_query(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+
+    // Get index
+    let cluster_metadata = system.metadata.read().unwrap();
+    let index = get_index_or_404!(cluster_metadata, *index_name);
+
+    // Parse URL parameters
+    let mut explain = false;
+    if let Some(ref url_query) = req.url.query() {
+        for (key, value) in form_urlencoded::parse(url_query.as_bytes()) {
+            match key.as_ref() {
+                "explain" => {
+                    explain = value.as_ref() == "true";
+                }
+                _ => warn!("unrecognised GET parameter {:?}", key),
+            }
+        }
+    }
+
+    // Requests without a query match every document
+    let request_json = json_from_request_body!(req).unwrap_or_else(|| json!({}));
+    let query = match request_json.as_object() {
+        Some(request) => {
+            match request.get("query") {
+                Some(query_json) => parse_query(query_json),
+                None => parse_query(&json!({"match_all": {}})),
+            }
+        }
+        None => {
+            return Ok(json_response(status::BadRequest, json!({"message": "Request must be an object"})));
+        }
+    };
+
+    // Build the query against the index so errors that depend on the mappings are found too
+    let index_reader = index.store.reader();
+    let index_metadata = index.metadata.read().unwrap();
+    let query = match query {
+        Ok(query) => {
+            query.build(&QueryBuildContext::new().set_index_metadata(&index_metadata).set_index_reader(&index_reader).set_term_dictionary(&index.term_dictionary), &index_reader.schema())
+                .map_err(|error| query_build_error_json(error)["error"].clone())
+        }
+        Err(error) => Err(query_error_json(error, &request_json)["error"].clone()),
+    };
+
+    let mut explanation = json!({
+        "index": index.canonical_name(),
+        "valid": query.is_ok(),
+    });
+
+    match query {
+        Ok(query) => {
+            explanation["explanation"] = json!(format!("{:?}", query));
+        }
+        Err(error) => {
+            explanation["error"] = error;
+        }
+    }
+
+    let mut response = json!({
+        "valid": explanation["valid"],
+        "_shards": {
+            "total": 1,
+            "successful": 1,
+            "failed": 0,
+        },
+    });
+
+    if explain {
+        response["explanations"] = json!([explanation]);
+    }
+
+    Ok(json_response(status::Ok, response))
+}
+
+
+pub fn view_search(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+
+    // Get index
+    let cluster_metadata = system.metadata.read().unwrap();
+    let index = get_index_or_404!(cluster_metadata, *index_name);
+
+    let url_query = req.url.query().map(|url_query| url_query.to_owned());
     match json_from_request_body!(req) {
         Some(query_json) => {
-            // Parse query
-            let query = parse_query(query_json.as_object().unwrap().get("query").unwrap());
-            debug!("{:#?}", query);
+            match run_search(system, &cluster_metadata, index, &query_json, url_query.as_ref().map(|url_query| url_query.as_str())) {
+                Ok(response) => Ok(json_response(status::Ok, response)),
+                Err((status, response)) => Ok(json_response(status, response)),
+            }
+        }
+        None => Ok(json_response(status::BadRequest, json!({"message": "Missing query"}))),
+    }
+}
 
-            match query {
-                Ok(query) => {
-                    let mut from = 0;
-                    let mut size = 10;
-                    let mut fields = Vec::new();
-
-                    // TODO: Rewrite this
-                    if let Some(ref url_query) = req.url.query() {
-                        for (key, value) in form_urlencoded::parse(url_query.as_bytes()) {
-                            match key.as_ref() {
-                                "from" => {
-                                    from = value.as_ref().parse().expect("need a number");
-                                }
-                                "size" => {
-                                    size = value.as_ref().parse().expect("need a number");
-                                }
-                                "fields" => {
-                                    for field_name in value.split(",") {
-                                        let field_ref = match index_reader.schema().get_field_by_name(field_name) {
-                                            Some(field_ref) => field_ref,
-                                            None => {
-                                                warn!("unknown field {:?}", field_name);
-                                                continue;
-                                            }
-                                        };
-
-                                        fields.push((field_name.to_owned(), field_ref));
+
+/// Runs a search request against an index
+///
+/// Errors are returned as the status code and body of the response to send back
+fn run_search(system: &System, cluster_metadata: &ClusterMetadata, index: &Index, query_json: &serde_json::Value, url_query: Option<&str>) -> Result<serde_json::Value, (status::Status, serde_json::Value)> {
+    let start_time = Instant::now();
+    let index_reader = index.store.reader();
+    let index_metadata = index.metadata.read().unwrap();
+
+    if !query_json.is_object() {
+        return Err((status::BadRequest, json!({"message": "Search request must be an object"})));
+    }
+
+    // Parse query
+    // Requests that only contain aggregations match every document
+    let query = match query_json.as_object().unwrap().get("query") {
+        Some(query_json) => parse_query(query_json),
+        None => parse_query(&json!({"match_all": {}})),
+    };
+    debug!("{:#?}", query);
+
+    match query {
+        Ok(query) => {
+            let mut from = 0;
+            let mut size = 10;
+            let mut fields = Vec::new();
+            let mut sort: Option<Vec<SortSpec>> = None;
+            let mut track_scores = false;
+            let mut explain = false;
+            let mut scroll: Option<Duration> = None;
+
+            // Sorting from the request body
+            if let Some(sort_json) = query_json.as_object().unwrap().get("sort") {
+                match parse_sort(sort_json) {
+                    Ok(sort_specs) => sort = Some(sort_specs),
+                    Err(error) => {
+                        return Err((status::BadRequest, json!({"message": format!("Sort error: {}", error.reason())})));
+                    }
+                }
+            }
+
+            // Paging from the request body
+            if let Some(from_json) = query_json.as_object().unwrap().get("from") {
+                match from_json.as_u64() {
+                    Some(value) => from = value as usize,
+                    None => {
+                        return Err((status::BadRequest, json!({"message": "\"from\" must be a positive integer"})));
+                    }
+                }
+            }
+
+            if let Some(size_json) = query_json.as_object().unwrap().get("size") {
+                match size_json.as_u64() {
+                    Some(value) => size = value as usize,
+                    None => {
+                        return Err((status::BadRequest, json!({"message": "\"size\" must be a positive integer"})));
+                    }
+                }
+            }
+
+            if let Some(track_scores_json) = query_json.as_object().unwrap().get("track_scores") {
+                track_scores = track_scores_json.as_bool().unwrap_or(false);
+            }
+
+            if let Some(explain_json) = query_json.as_object().unwrap().get("explain") {
+                explain = explain_json.as_bool().unwrap_or(false);
+            }
+
+            // Aggregations
+            let mut aggregations: Option<Vec<(String, Box<AggregationBuilder>)>> = None;
+            if let Some(aggregations_json) = query_json.as_object().unwrap().get("aggs").or(query_json.as_object().unwrap().get("aggregations")) {
+                match parse_aggregations(aggregations_json) {
+                    Ok(parsed_aggregations) => aggregations = Some(parsed_aggregations),
+                    Err(error) => {
+                        return Err((status::BadRequest, json!({"message": format!("Aggregation error: {}", error.reason())})));
+                    }
+                }
+            }
+
+            // Highlighting
+            let mut highlight: Option<HighlightSpec> = None;
+            if let Some(highlight_json) = query_json.as_object().unwrap().get("highlight") {
+                match parse_highlight(highlight_json) {
+                    Ok(highlight_spec) => highlight = Some(highlight_spec),
+                    Err(error) => {
+                        return Err((status::BadRequest, json!({"message": format!("Highlight error: {:?}", error)})));
+                    }
+                }
+            }
+
+            // Source filtering
+            let mut source_filter: Option<SourceFilter> = None;
+            if let Some(source_json) = query_json.as_object().unwrap().get("_source") {
+                match parse_source_filter(source_json) {
+                    Ok(parsed_source_filter) => source_filter = Some(parsed_source_filter),
+                    Err(error) => {
+                        return Err((status::BadRequest, json!({"message": format!("Source filter error: {:?}", error)})));
+                    }
+                }
+            }
+
+            // TODO: Rewrite this
+            if let Some(url_query) = url_query {
+                for (key, value) in form_urlencoded::parse(url_query.as_bytes()) {
+                    match key.as_ref() {
+                        "from" => {
+                            from = value.as_ref().parse().expect("need a number");
+                        }
+                        "size" => {
+                            size = value.as_ref().parse().expect("need a number");
+                        }
+                        "fields" => {
+                            for field_name in value.split(",") {
+                                let field_ref = match index_reader.schema().get_field_by_name(field_name) {
+                                    Some(field_ref) => field_ref,
+                                    None => {
+                                        warn!("unknown field {:?}", field_name);
+                                        continue;
                                     }
+                                };
+
+                                fields.push((field_name.to_owned(), field_ref));
+                            }
+                        }
+                        "sort" => {
+                            match parse_sort_url_param(value.as_ref()) {
+                                Ok(sort_specs) => sort = Some(sort_specs),
+                                Err(error) => {
+                                    return Err((status::BadRequest, json!({"message": format!("Sort error: {}", error.reason())})));
                                 }
-                                // terminate_after
-                                // explain
-                                // version
-                                // timeout
-                                // fielddata_fields
-                                // track_scores
-                                // stats
-                                // suggest_field
-                                _ => warn!("unrecognised GET parameter {:?}", key),
                             }
                         }
+                        "track_scores" => {
+                            track_scores = value.as_ref() == "true";
+                        }
+                        "explain" => {
+                            explain = value.as_ref() == "true";
+                        }
+                        "scroll" => {
+                            match parse_keep_alive(value.as_ref()) {
+                                Some(keep_alive) => scroll = Some(keep_alive),
+                                None => {
+                                    return Err((status::BadRequest, json!({"message": format!("Invalid scroll keep alive: {:?}", value)})));
+                                }
+                            }
+                        }
+                        "_source" | "_source_include" | "_source_includes" | "_source_exclude" | "_source_excludes" => {
+                            let mut url_source_filter = source_filter.take().unwrap_or_default();
+                            apply_source_filter_url_param(&mut url_source_filter, key.as_ref(), value.as_ref());
+                            source_filter = Some(url_source_filter);
+                        }
+                        // terminate_after
+                        // version
+                        // timeout
+                        // fielddata_fields
+                        // stats
+                        // suggest_field
+                        _ => warn!("unrecognised GET parameter {:?}", key),
+                    }
+                }
+            }
+
+            // The source isn't returned by default when specific fields are requested
+            let source_filter = source_filter.unwrap_or_else(|| {
+                if fields.is_empty() { SourceFilter::default() } else { SourceFilter::disabled() }
+            });
+
+            // Scroll contexts return every matching document in order, starting with the first
+            if scroll.is_some() && from != 0 {
+                return Err((status::BadRequest, json!({"message": "\"from\" can't be used with \"scroll\""})));
+            }
+
+            // Do the search
+            // The total count collector runs alongside the top documents collector so
+            // "hits.total" counts every match, not just the ones on this page
+            let query = match query.build(&QueryBuildContext::new().set_index_metadata(&index_metadata).set_index_reader(&index_reader).set_term_dictionary(&index.term_dictionary), &index_reader.schema()) {
+                Ok(query) => query,
+                Err(error) => {
+                    return Err((status::BadRequest, query_build_error_json(error)));
+                }
+            };
+            let mut total_count_collector = TotalCountCollector::new();
+            let no_aggregations = Vec::new();
+            let aggregators = match build_aggregations(aggregations.as_ref().unwrap_or(&no_aggregations), &index_metadata, index_reader.schema()) {
+                Ok(aggregators) => aggregators,
+                Err(error) => {
+                    return Err((status::BadRequest, json!({"message": format!("Aggregation error: {}", error.reason())})));
+                }
+            };
+            let mut aggregations_collector = AggregationsCollector::new(&index_reader, aggregators);
+            let highlighter = match highlight.as_ref().map(|highlight| highlight.resolve(&index_metadata, index_reader.schema())) {
+                Some(Ok(highlighter)) => Some(highlighter),
+                Some(Err(error)) => {
+                    return Err((status::BadRequest, json!({"message": format!("Highlight error: {:?}", error)})));
+                }
+                None => None,
+            };
+
+            // Scrolling searches are always sorted so their pages are in a consistent order.
+            // These are ordered by score if no sort was given.
+            let resolved_sort = match sort {
+                Some(ref sort) => {
+                    match sort.iter().map(|spec| spec.resolve(&index_metadata, index_reader.schema())).collect::<Result<Vec<ResolvedSortSpec>, _>>() {
+                        Ok(mut resolved_sort) => {
+                            add_tiebreaker(&mut resolved_sort, index_reader.schema());
+                            Some(resolved_sort)
+                        }
+                        Err(error) => {
+                            return Err((status::BadRequest, json!({"message": format!("Sort error: {}", error.reason())})));
+                        }
                     }
+                }
+                None if scroll.is_some() => {
+                    let mut resolved_sort = vec![ResolvedSortSpec {
+                        source: SortValueSource::Score,
+                        order: SortOrder::Desc,
+                        missing: SortMissing::Last,
+                    }];
+                    add_tiebreaker(&mut resolved_sort, index_reader.schema());
+                    Some(resolved_sort)
+                }
+                None => None,
+            };
 
-                    // Do the search
-                    let mut collector = TopScoreCollector::new(from + size);
-                    index_reader.search(&mut collector, &query.build(&QueryBuildContext::new().set_index_metadata(&index_metadata), &index_reader.schema())).unwrap();
+            // Scrolling searches sort every matching document up front so the following pages
+            // come from the same result set
+            let max_docs = if scroll.is_some() { usize::MAX } else { from + size };
 
-                    // Convert hits into JSON
-                    let mut hits = Vec::new();
-                    for doc_match in collector.into_sorted_vec().iter().skip(from) {
-                        let mut field_values = BTreeMap::new();
+            // Continue from the last hit of the previous page
+            let search_after = match (query_json.as_object().unwrap().get("search_after"), resolved_sort.as_ref()) {
+                (Some(search_after_json), Some(resolved_sort)) if scroll.is_none() => {
+                    if from != 0 {
+                        return Err((status::BadRequest, json!({"message": "\"from\" can't be used with \"search_after\""})));
+                    }
 
-                        for &(ref field_name, field_ref) in fields.iter() {
-                            let value = match index_reader.read_stored_field(field_ref, DocRef::from_u64(doc_match.doc_id())) {
-                                Ok(Some(value)) => vec![value],
-                                Ok(None) => vec![],
-                                Err(_) => vec![],
-                            };
+                    match parse_search_after(search_after_json, resolved_sort) {
+                        Ok(search_after) => Some(search_after),
+                        Err(error) => {
+                            return Err((status::BadRequest, json!({"message": format!("Sort error: {}", error.reason())})));
+                        }
+                    }
+                }
+                (Some(_), _) => {
+                    return Err((status::BadRequest, json!({"message": "\"search_after\" requires a \"sort\" and can't be used with \"scroll\""})));
+                }
+                (None, _) => None,
+            };
 
-                            field_values.insert(field_name.clone(), value);
+            let mut doc_matches = match resolved_sort {
+                Some(ref resolved_sort) => {
+                    let mut top_sorted_collector = TopSortedCollector::new(&index_reader, resolved_sort.clone(), track_scores, max_docs);
+                    if let Some(search_after) = search_after {
+                        top_sorted_collector.set_search_after(search_after);
+                    }
+
+                    {
+                        let mut count_and_aggregate = MultiCollector::new(&mut total_count_collector, &mut aggregations_collector);
+                        let mut collector = MultiCollector::new(&mut top_sorted_collector, &mut count_and_aggregate);
+                        index_reader.search(&mut collector, &query).unwrap();
+                    }
+
+                    top_sorted_collector.into_sorted_vec()
+                }
+                None => {
+                    let mut top_score_collector = TopScoreCollector::new(max_docs);
+                    {
+                        let mut count_and_aggregate = MultiCollector::new(&mut total_count_collector, &mut aggregations_collector);
+                        let mut collector = MultiCollector::new(&mut top_score_collector, &mut count_and_aggregate);
+                        index_reader.search(&mut collector, &query).unwrap();
+                    }
+
+                    top_score_collector.into_sorted_vec().iter().map(|doc_match| {
+                        SortedDocument {
+                            doc_id: doc_match.doc_id(),
+                            score: doc_match.score(),
+                            sort_values: Vec::new(),
                         }
+                    }).collect::<Vec<SortedDocument>>()
+                }
+            };
+
+            // Keep the documents that follow the first page of a scrolling search
+            let scroll_docs = if scroll.is_some() {
+                let first_page_len = cmp::min(size, doc_matches.len());
+                Some(doc_matches.split_off(first_page_len))
+            } else {
+                None
+            };
+
+            let hit_format = HitFormat {
+                fields: fields,
+                source_filter: source_filter,
+                highlighter: highlighter,
+                query_terms: QueryTerms::from_query(&query),
+                include_sort: sort.is_some(),
+            };
 
-                        hits.push(json!({
-                            "_score": doc_match.score().unwrap(),
-                            "fields": field_values,
-                        }));
+            // Convert hits into JSON
+            let max_score = doc_matches.iter().filter_map(|doc_match| doc_match.score).fold(None, |max_score: Option<f32>, score| {
+                match max_score {
+                    Some(max_score) if max_score >= score => Some(max_score),
+                    _ => Some(score),
+                }
+            });
+            let total = total_count_collector.get_total_count();
+
+            // Explanations are only added to the first page of a scrolling search
+            let hits = {
+                let explainer = if explain { Some(Explainer::new(&index_reader, &index_metadata)) } else { None };
+
+                doc_matches.iter().skip(from).map(|doc_match| {
+                    let mut hit = hit_format.hit_json(index.canonical_name(), &index_reader, doc_match);
+                    if let Some(explanation) = explainer.as_ref().and_then(|explainer| explainer.explain(&query, doc_match.doc_id)) {
+                        hit["_explanation"] = explanation.as_json();
                     }
 
-                    // TODO: {"took":5,"timed_out":false,"_shards":{"total":5,"successful":5,"failed":0},"hits":{"total":4,"max_score":1.0,"hits":[{"_index":"wagtail","_type":"searchtests_searchtest_searchtests_searchtestchild","_id":"searchtests_searchtest:5380","_score":1.0,"fields":{"pk":["5380"]}},{"_index":"wagtail","_type":"searchtests_searchtest","_id":"searchtests_searchtest:5379","_score":1.0,"fields":{"pk":["5379"]}}]}}
-                    Ok(json_response(status::Ok,
-                                     json!({
-                                         "hits": {
-                                             "total": hits.len(),
-                                             "hits": hits
-                                        }})))
+                    hit
+                }).collect::<Vec<serde_json::Value>>()
+            };
+
+            // Keep the remaining documents in a scroll context so the following pages can be found
+            let scroll_id = match (scroll, scroll_docs) {
+                (Some(keep_alive), Some(scroll_docs)) => {
+                    let index_ref = cluster_metadata.names.find_canonical(index.canonical_name()).unwrap();
+                    let cursor = ScrollCursor::new(scroll_docs, size);
+                    let scroll_context = ScrollContext::new(index_ref, cursor, total, max_score, hit_format, keep_alive);
+                    Some(system.scrolls.lock().unwrap().insert(scroll_context))
                 }
-                Err(_) => {
-                    // TODO: What specifically is bad about the Query?
-                    let mut response = Response::with((status::BadRequest,
-                                                       "{\"message\": \"Query error\"}"));
-                    response.headers.set_raw("Content-Type", vec![b"application/json".to_vec()]);
-                    Ok(response)
+                _ => None,
+            };
+
+            let took = millis_since(start_time);
+
+            let mut response = json!({
+                "took": took,
+                "timed_out": false,
+                "_shards": {
+                    "total": 1,
+                    "successful": 1,
+                    "failed": 0,
+                },
+                "hits": {
+                    "total": total,
+                    "max_score": max_score,
+                    "hits": hits,
                 }
+            });
+
+            if let Some(scroll_id) = scroll_id {
+                response["_scroll_id"] = json!(scroll_id);
             }
+
+            if aggregations.is_some() {
+                match aggregations_collector.as_json() {
+                    Ok(aggregations_json) => response["aggregations"] = aggregations_json,
+                    Err(error) => {
+                        return Err((status::BadRequest, json!({"message": format!("Aggregation error: {}", error.reason())})));
+                    }
+                }
+            }
+
+            Ok(response)
         }
-        None => Ok(json_response(status::BadRequest, json!({"message": "Missing query"}))),
+        Err(error) => Err((status::BadRequest, query_error_json(error, query_json))),
+    }
+}
+
+
+/// A search in a multi search request
+#[derive(Debug, PartialEq)]
+struct SearchRequest {
+    index_name: Option<String>,
+    body: serde_json::Value,
+}
+
+
+/// Splits the body of a multi search request into its searches
+///
+/// Each search is made up of a header line followed by a body line. Searches with lines that
+/// aren't valid JSON are returned as errors so they don't affect the other searches
+fn parse_msearch_payload(payload: &str, default_index_name: Option<&str>) -> Result<Vec<Result<SearchRequest, String>>, String> {
+    let mut search_requests = Vec::new();
+    let mut payload_lines = payload.split('\n').filter(|line| !line.trim().is_empty());
+    loop {
+        let header_line = match payload_lines.next() {
+            Some(header_line) => header_line,
+            None => break,
+        };
+
+        let body_line = match payload_lines.next() {
+            Some(body_line) => body_line,
+            None => return Err("Expected a body line after each header line".to_string()),
+        };
+
+        search_requests.push(match (serde_json::from_str::<serde_json::Value>(header_line), serde_json::from_str::<serde_json::Value>(body_line)) {
+            (Ok(header_json), Ok(body_json)) => {
+                // The index may be given in the header, otherwise the one in the URL is used
+                let index_name = match header_json.get("index") {
+                    Some(&serde_json::Value::String(ref index_name)) => Some(index_name.clone()),
+                    Some(&serde_json::Value::Array(ref index_names)) if index_names.len() == 1 => index_names[0].as_str().map(|index_name| index_name.to_owned()),
+                    _ => default_index_name.map(|index_name| index_name.to_owned()),
+                };
+
+                Ok(SearchRequest {
+                    index_name: index_name,
+                    body: body_json,
+                })
+            }
+            (Err(error), _) | (_, Err(error)) => Err(format!("JSON parse error: {}", error)),
+        });
+    }
+
+    Ok(search_requests)
+}
+
+
+pub fn view_msearch(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let default_index_name = read_path_parameter!(req, "index").map(|index_name| index_name.to_owned());
+
+    // Load data from body
+    let mut payload = String::new();
+    req.body.read_to_string(&mut payload).unwrap();
+
+    let search_requests = match parse_msearch_payload(&payload, default_index_name.as_ref().map(|index_name| index_name.as_str())) {
+        Ok(search_requests) => search_requests,
+        Err(error) => {
+            return Ok(json_response(status::BadRequest, json!({"message": error})));
+        }
+    };
+
+    // Lock cluster metadata
+    let cluster_metadata = system.metadata.read().unwrap();
+
+    // Errors are reported in place of the search's response so they don't affect the other searches
+    let mut responses = Vec::new();
+    for search_request in search_requests {
+        let response = match search_request {
+            Ok(search_request) => {
+                let index = search_request.index_name.as_ref()
+                    .and_then(|index_name| cluster_metadata.names.find_canonical(index_name))
+                    .and_then(|index_ref| cluster_metadata.indices.get(&index_ref));
+
+                match index {
+                    Some(index) => run_search(system, &cluster_metadata, index, &search_request.body, None),
+                    None => Err((status::NotFound, json!({"message": "Index not found"}))),
+                }
+            }
+            Err(error) => Err((status::BadRequest, json!({"message": error}))),
+        };
+
+        responses.push(match response {
+            Ok(response) => response,
+            Err((status, error)) => {
+                json!({
+                    "error": error,
+                    "status": status.to_u16(),
+                })
+            }
+        });
+    }
+
+    Ok(json_response(status::Ok, json!({"responses": responses})))
+}
+
+
+/// Reads the scroll ids from the body of a scroll request
+fn read_scroll_ids(json: &serde_json::Value) -> Vec<String> {
+    match json.get("scroll_id") {
+        Some(&serde_json::Value::String(ref scroll_id)) => vec![scroll_id.clone()],
+        Some(&serde_json::Value::Array(ref scroll_ids)) => {
+            scroll_ids.iter().filter_map(|scroll_id| scroll_id.as_str()).map(|scroll_id| scroll_id.to_owned()).collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+
+pub fn view_scroll(req: &mut Request) -> IronResult<Response> {
+    let start_time = Instant::now();
+    let ref system = get_system!(req);
+    let mut scroll_id = read_path_parameter!(req, "scroll_id").map(|scroll_id| scroll_id.to_owned());
+    let mut keep_alive = None;
+
+    if let Some(ref url_query) = req.url.query() {
+        for (key, value) in form_urlencoded::parse(url_query.as_bytes()) {
+            match key.as_ref() {
+                "scroll_id" => scroll_id = Some(value.into_owned()),
+                "scroll" => keep_alive = Some(value.into_owned()),
+                _ => warn!("unrecognised GET parameter {:?}", key),
+            }
+        }
+    }
+
+    if let Some(json) = json_from_request_body!(req) {
+        if let Some(body_scroll_id) = read_scroll_ids(&json).pop() {
+            scroll_id = Some(body_scroll_id);
+        }
+
+        if let Some(body_keep_alive) = json.get("scroll").and_then(|keep_alive| keep_alive.as_str()) {
+            keep_alive = Some(body_keep_alive.to_owned());
+        }
+    }
+
+    let scroll_id = match scroll_id {
+        Some(scroll_id) => scroll_id,
+        None => {
+            return Ok(json_response(status::BadRequest, json!({"message": "Missing scroll id"})));
+        }
+    };
+
+    let keep_alive = match keep_alive {
+        Some(keep_alive) => {
+            match parse_keep_alive(&keep_alive) {
+                Some(keep_alive) => Some(keep_alive),
+                None => {
+                    return Ok(json_response(status::BadRequest, json!({"message": format!("Invalid scroll keep alive: {:?}", keep_alive)})));
+                }
+            }
+        }
+        None => None,
+    };
+
+    // Find scroll context
+    // The registry is only locked while the context is looked up. The context itself stays
+    // locked while its next page is found, so concurrent requests for it take turns.
+    let scroll_context = match system.scrolls.lock().unwrap().get(&scroll_id) {
+        Some(scroll_context) => scroll_context,
+        None => {
+            return Ok(json_response(status::NotFound, json!({"message": "Scroll context not found"})));
+        }
+    };
+    let mut scroll_context = scroll_context.lock().unwrap();
+    let scroll_context = &mut *scroll_context;
+
+    let cluster_metadata = system.metadata.read().unwrap();
+    let index = match cluster_metadata.indices.get(&scroll_context.index_ref) {
+        Some(index) => index,
+        None => {
+            return Ok(json_response(status::NotFound, json!({"message": "Index not found"})));
+        }
+    };
+    let index_reader = index.store.reader();
+
+    // Get the next page
+    scroll_context.touch(keep_alive);
+    let doc_matches = scroll_context.cursor.next_page();
+    let hit_format = &scroll_context.hit_format;
+
+    // Documents that were deleted and merged away since the scroll was created can't be read
+    let id_field = index_reader.schema().get_field_by_name("_id");
+    let hits = doc_matches.iter().filter(|doc_match| {
+        read_stored_string(&index_reader, id_field, DocRef::from_u64(doc_match.doc_id)).is_some()
+    }).map(|doc_match| {
+        hit_format.hit_json(index.canonical_name(), &index_reader, doc_match)
+    }).collect::<Vec<serde_json::Value>>();
+
+    let took = millis_since(start_time);
+
+    Ok(json_response(status::Ok, json!({
+        "_scroll_id": scroll_id,
+        "took": took,
+        "timed_out": false,
+        "_shards": {
+            "total": 1,
+            "successful": 1,
+            "failed": 0,
+        },
+        "hits": {
+            "total": scroll_context.total,
+            "max_score": scroll_context.max_score,
+            "hits": hits,
+        }
+    })))
+}
+
+
+pub fn view_clear_scroll(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let mut scroll_ids = match read_path_parameter!(req, "scroll_id") {
+        Some(scroll_ids) => scroll_ids.split(",").map(|scroll_id| scroll_id.to_owned()).collect(),
+        None => Vec::new(),
+    };
+
+    if let Some(json) = json_from_request_body!(req) {
+        scroll_ids.extend(read_scroll_ids(&json));
+    }
+
+    let mut scrolls = system.scrolls.lock().unwrap();
+    let num_freed = if scroll_ids.iter().any(|scroll_id| scroll_id == "_all") {
+        scrolls.clear()
+    } else {
+        scroll_ids.iter().filter(|scroll_id| scrolls.remove(scroll_id)).count()
+    };
+
+    let response = json!({
+        "succeeded": true,
+        "num_freed": num_freed,
+    });
+
+    if num_freed > 0 {
+        Ok(json_response(status::Ok, response))
+    } else {
+        Ok(json_response(status::NotFound, response))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{SearchRequest, parse_msearch_payload};
+
+    #[test]
+    fn test_parse_msearch_payload() {
+        let payload = concat!(
+            "{\"index\": \"other\"}\n",
+            "{\"query\": {\"match_all\": {}}}\n",
+            "\n",
+            "{}\n",
+            "{\"size\": 5}\n",
+            "{\"index\": [\"another\"]}\n",
+            "{}\n",
+        );
+
+        assert_eq!(parse_msearch_payload(payload, Some("test")), Ok(vec![
+            Ok(SearchRequest {
+                index_name: Some("other".to_string()),
+                body: json!({"query": {"match_all": {}}}),
+            }),
+            Ok(SearchRequest {
+                index_name: Some("test".to_string()),
+                body: json!({"size": 5}),
+            }),
+            Ok(SearchRequest {
+                index_name: Some("another".to_string()),
+                body: json!({}),
+            }),
+        ]));
+    }
+
+    #[test]
+    fn test_parse_msearch_payload_without_index() {
+        assert_eq!(parse_msearch_payload("{}\n{}\n", None), Ok(vec![
+            Ok(SearchRequest {
+                index_name: None,
+                body: json!({}),
+            }),
+        ]));
+    }
+
+    #[test]
+    fn test_invalid_json_only_affects_its_search() {
+        let search_requests = parse_msearch_payload("{}\n{\"size\": \n{}\n{}\n", Some("test")).unwrap();
+
+        assert_eq!(search_requests.len(), 2);
+        assert!(search_requests[0].is_err());
+        assert_eq!(search_requests[1], Ok(SearchRequest {
+            index_name: Some("test".to_string()),
+            body: json!({}),
+        }));
+    }
+
+    #[test]
+    fn test_gives_error_for_missing_body_line() {
+        assert_eq!(parse_msearch_payload("{}\n{}\n{}\n", Some("test")), Err("Expected a body line after each header line".to_string()));
     }
 }
@@ -1,5 +1,10 @@
+use std::time::Instant;
+
 use serde_json;
 
+use index::write::WriteError;
+use query_parser::{QueryParseError, QueryBuildError};
+
 use api::iron::prelude::*;
 use api::iron::status;
 
@@ -103,3 +108,46 @@ macro_rules! json_from_request_body {
         }
     }}
 }
+
+
+/// Number of milliseconds since the given time, for the "took" key of responses
+pub fn millis_since(start_time: Instant) -> u64 {
+    let elapsed = start_time.elapsed();
+    elapsed.as_secs() * 1000 + (elapsed.subsec_nanos() / 1000000) as u64
+}
+
+
+/// Chooses the response status for an error from writing a document
+pub fn write_error_status(error: &WriteError) -> status::Status {
+    match *error {
+        WriteError::MappingNotFound | WriteError::DocumentNotFound => status::NotFound,
+        WriteError::DocumentAlreadyExists | WriteError::VersionConflict { .. } => status::Conflict,
+        WriteError::InvalidRequest(_) | WriteError::PrepareDocument(_) => status::BadRequest,
+        WriteError::Store(_) => status::InternalServerError,
+    }
+}
+
+
+/// Builds the body of the response for a query that couldn't be parsed
+///
+/// The error must be relative to the "query" key of the request.
+pub fn query_error_json(error: QueryParseError, request_json: &serde_json::Value) -> serde_json::Value {
+    let error = error.in_key("query");
+
+    json!({
+        "message": format!("Query error: {}", error.reason()),
+        "error": error.details(request_json),
+    })
+}
+
+
+/// Builds the body of the response for a query that couldn't be built for the index
+pub fn query_build_error_json(error: QueryBuildError) -> serde_json::Value {
+    json!({
+        "message": format!("Query error: {}", error.reason()),
+        "error": {
+            "type": "query_shard_exception",
+            "reason": error.reason(),
+        },
+    })
+}
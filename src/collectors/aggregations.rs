@@ -0,0 +1,49 @@
+//! Passes each matching document into a list of aggregators
+
+use serde_json::Value as Json;
+use kite::document::DocRef;
+use kite::collectors::{Collector, DocumentMatch};
+use kite_rocksdb::RocksDBReader;
+
+use search::aggregations::{Aggregator, AggregationParseError};
+
+
+pub struct AggregationsCollector<'a, 'b: 'a> {
+    index_reader: &'a RocksDBReader<'b>,
+    aggregators: Vec<(String, Box<Aggregator>)>,
+}
+
+
+impl<'a, 'b> AggregationsCollector<'a, 'b> {
+    pub fn new(index_reader: &'a RocksDBReader<'b>, aggregators: Vec<(String, Box<Aggregator>)>) -> AggregationsCollector<'a, 'b> {
+        AggregationsCollector {
+            index_reader: index_reader,
+            aggregators: aggregators,
+        }
+    }
+
+    pub fn as_json(&self) -> Result<Json, AggregationParseError> {
+        let mut results = json!({});
+
+        for &(ref name, ref aggregator) in self.aggregators.iter() {
+            results[name.as_str()] = aggregator.as_json()?;
+        }
+
+        Ok(results)
+    }
+}
+
+
+impl<'a, 'b> Collector for AggregationsCollector<'a, 'b> {
+    fn needs_score(&self) -> bool {
+        false
+    }
+
+    fn collect(&mut self, doc: DocumentMatch) {
+        let doc_ref = DocRef::from_u64(doc.doc_id());
+
+        for &mut (_, ref mut aggregator) in self.aggregators.iter_mut() {
+            aggregator.collect(self.index_reader, doc_ref);
+        }
+    }
+}
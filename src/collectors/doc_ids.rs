@@ -0,0 +1,51 @@
+//! Collects the ids of every matching document
+
+use kite::collectors::{Collector, DocumentMatch};
+
+
+pub struct DocIdCollector {
+    doc_ids: Vec<u64>,
+}
+
+
+impl DocIdCollector {
+    pub fn new() -> DocIdCollector {
+        DocIdCollector {
+            doc_ids: Vec::new(),
+        }
+    }
+
+    pub fn into_vec(self) -> Vec<u64> {
+        self.doc_ids
+    }
+}
+
+
+impl Collector for DocIdCollector {
+    fn needs_score(&self) -> bool {
+        false
+    }
+
+    fn collect(&mut self, doc: DocumentMatch) {
+        self.doc_ids.push(doc.doc_id());
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use kite::collectors::{Collector, DocumentMatch};
+
+    use super::DocIdCollector;
+
+    #[test]
+    fn test_doc_id_collector() {
+        let mut collector = DocIdCollector::new();
+
+        collector.collect(DocumentMatch::new_unscored(3));
+        collector.collect(DocumentMatch::new_unscored(1));
+        collector.collect(DocumentMatch::new_scored(2, 1.0f32));
+
+        assert_eq!(collector.into_vec(), vec![3, 1, 2]);
+    }
+}
@@ -0,0 +1,65 @@
+//! Finds the score of a single document
+
+use kite::collectors::{Collector, DocumentMatch};
+
+
+pub struct DocScoreCollector {
+    doc_id: u64,
+    score: Option<f32>,
+}
+
+
+impl DocScoreCollector {
+    pub fn new(doc_id: u64) -> DocScoreCollector {
+        DocScoreCollector {
+            doc_id: doc_id,
+            score: None,
+        }
+    }
+
+    /// The score of the document, None if it didn't match
+    pub fn score(&self) -> Option<f32> {
+        self.score
+    }
+}
+
+
+impl Collector for DocScoreCollector {
+    fn needs_score(&self) -> bool {
+        true
+    }
+
+    fn collect(&mut self, doc: DocumentMatch) {
+        if doc.doc_id() == self.doc_id {
+            self.score = Some(doc.score().unwrap_or(0.0f32));
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use kite::collectors::{Collector, DocumentMatch};
+
+    use super::DocScoreCollector;
+
+    #[test]
+    fn test_doc_score_collector() {
+        let mut collector = DocScoreCollector::new(2);
+
+        collector.collect(DocumentMatch::new_scored(1, 3.0f32));
+        collector.collect(DocumentMatch::new_scored(2, 1.5f32));
+        collector.collect(DocumentMatch::new_scored(3, 2.0f32));
+
+        assert_eq!(collector.score(), Some(1.5f32));
+    }
+
+    #[test]
+    fn test_doc_score_collector_no_match() {
+        let mut collector = DocScoreCollector::new(2);
+
+        collector.collect(DocumentMatch::new_scored(1, 3.0f32));
+
+        assert_eq!(collector.score(), None);
+    }
+}
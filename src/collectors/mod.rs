@@ -0,0 +1,7 @@
+//! Collectors that can be passed into a search alongside the ones provided by Kite
+
+pub mod multi;
+pub mod aggregations;
+pub mod doc_ids;
+pub mod doc_score;
+pub mod top_sorted;
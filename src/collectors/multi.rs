@@ -0,0 +1,81 @@
+//! Passes each match into two collectors at once
+//!
+//! This allows, for example, the total number of matches to be counted while
+//! collecting the top scoring documents in a single pass over the index.
+
+use kite::collectors::{Collector, DocumentMatch};
+
+
+pub struct MultiCollector<'a, A: Collector + 'a, B: Collector + 'a> {
+    first: &'a mut A,
+    second: &'a mut B,
+}
+
+
+impl<'a, A: Collector, B: Collector> MultiCollector<'a, A, B> {
+    pub fn new(first: &'a mut A, second: &'a mut B) -> MultiCollector<'a, A, B> {
+        MultiCollector {
+            first: first,
+            second: second,
+        }
+    }
+}
+
+
+impl<'a, A: Collector, B: Collector> Collector for MultiCollector<'a, A, B> {
+    fn needs_score(&self) -> bool {
+        self.first.needs_score() || self.second.needs_score()
+    }
+
+    fn collect(&mut self, doc: DocumentMatch) {
+        // DocumentMatch can't be cloned so we need to build a copy for the first collector
+        let doc_copy = match doc.score() {
+            Some(score) => DocumentMatch::new_scored(doc.doc_id(), score),
+            None => DocumentMatch::new_unscored(doc.doc_id()),
+        };
+
+        self.first.collect(doc_copy);
+        self.second.collect(doc);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use kite::collectors::{Collector, DocumentMatch};
+    use kite::collectors::top_score::TopScoreCollector;
+    use kite::collectors::total_count::TotalCountCollector;
+
+    use super::MultiCollector;
+
+    #[test]
+    fn test_multi_collector_needs_score() {
+        let mut first = TotalCountCollector::new();
+        let mut second = TotalCountCollector::new();
+        assert_eq!(MultiCollector::new(&mut first, &mut second).needs_score(), false);
+
+        let mut first = TotalCountCollector::new();
+        let mut second = TopScoreCollector::new(10);
+        assert_eq!(MultiCollector::new(&mut first, &mut second).needs_score(), true);
+    }
+
+    #[test]
+    fn test_multi_collector_collect() {
+        let mut top_score_collector = TopScoreCollector::new(2);
+        let mut total_count_collector = TotalCountCollector::new();
+
+        {
+            let mut collector = MultiCollector::new(&mut top_score_collector, &mut total_count_collector);
+            collector.collect(DocumentMatch::new_scored(0, 1.0f32));
+            collector.collect(DocumentMatch::new_scored(1, 0.5f32));
+            collector.collect(DocumentMatch::new_scored(2, 2.0f32));
+        }
+
+        assert_eq!(total_count_collector.get_total_count(), 3);
+
+        let docs = top_score_collector.into_sorted_vec();
+        assert_eq!(docs.len(), 2);
+        assert_eq!(docs[0].doc_id(), 2);
+        assert_eq!(docs[1].doc_id(), 0);
+    }
+}
@@ -0,0 +1,104 @@
+//! Collects the top documents, ordered by a list of sort keys
+
+use std::cmp::Ordering;
+
+use kite::document::DocRef;
+use kite::collectors::{Collector, DocumentMatch};
+use kite_rocksdb::RocksDBReader;
+
+use search::sort::{ResolvedSortSpec, SortValueSource, SortValue, compare_sort_values};
+
+
+#[derive(Debug)]
+pub struct SortedDocument {
+    pub doc_id: u64,
+    pub score: Option<f32>,
+    pub sort_values: Vec<Option<SortValue>>,
+}
+
+
+pub struct TopSortedCollector<'a, 'b: 'a> {
+    index_reader: &'a RocksDBReader<'b>,
+    sort: Vec<ResolvedSortSpec>,
+    track_scores: bool,
+    max_docs: usize,
+    search_after: Option<Vec<Option<SortValue>>>,
+    docs: Vec<SortedDocument>,
+}
+
+
+impl<'a, 'b> TopSortedCollector<'a, 'b> {
+    pub fn new(index_reader: &'a RocksDBReader<'b>, sort: Vec<ResolvedSortSpec>, track_scores: bool, max_docs: usize) -> TopSortedCollector<'a, 'b> {
+        TopSortedCollector {
+            index_reader: index_reader,
+            sort: sort,
+            track_scores: track_scores,
+            max_docs: max_docs,
+            search_after: None,
+            docs: Vec::new(),
+        }
+    }
+
+    /// Only collect documents that sort after the given sort values
+    pub fn set_search_after(&mut self, search_after: Vec<Option<SortValue>>) {
+        self.search_after = Some(search_after);
+    }
+
+    fn sort_and_truncate(&mut self) {
+        let sort = &self.sort;
+        self.docs.sort_by(|a, b| {
+            match compare_sort_values(sort, &a.sort_values, &b.sort_values) {
+                // Fall back to index order so results are consistent between pages
+                Ordering::Equal => a.doc_id.cmp(&b.doc_id),
+                ordering => ordering,
+            }
+        });
+        self.docs.truncate(self.max_docs);
+    }
+
+    pub fn into_sorted_vec(mut self) -> Vec<SortedDocument> {
+        self.sort_and_truncate();
+        self.docs
+    }
+}
+
+
+impl<'a, 'b> Collector for TopSortedCollector<'a, 'b> {
+    fn needs_score(&self) -> bool {
+        self.track_scores || self.sort.iter().any(|spec| spec.source == SortValueSource::Score)
+    }
+
+    fn collect(&mut self, doc: DocumentMatch) {
+        let doc_ref = DocRef::from_u64(doc.doc_id());
+
+        let mut sort_values = Vec::with_capacity(self.sort.len());
+        for spec in self.sort.iter() {
+            sort_values.push(match spec.source {
+                SortValueSource::Score => doc.score().map(SortValue::Score),
+                SortValueSource::StoredField(field_ref) => {
+                    match self.index_reader.read_stored_field(field_ref, doc_ref) {
+                        Ok(Some(value)) => Some(SortValue::from_field_value(value)),
+                        Ok(None) | Err(_) => None,
+                    }
+                }
+            });
+        }
+
+        if let Some(ref search_after) = self.search_after {
+            if compare_sort_values(&self.sort, &sort_values, search_after) != Ordering::Greater {
+                return;
+            }
+        }
+
+        self.docs.push(SortedDocument {
+            doc_id: doc.doc_id(),
+            score: if self.needs_score() { doc.score() } else { None },
+            sort_values: sort_values,
+        });
+
+        // Trim the list of documents once it grows well beyond the number we need to keep
+        if self.docs.len() / 2 > self.max_docs {
+            self.sort_and_truncate();
+        }
+    }
+}
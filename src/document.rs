@@ -1,13 +1,19 @@
+use std::str;
+
 use serde_json;
 use kite::Document;
+use kite::term_vector::TermVector;
 use fnv::FnvHashMap;
 
-use mapping::{Mapping, MappingProperty, FieldValueError};
+use mapping::{Mapping, MappingProperty, FieldType, FieldValueError};
+use index::lookup::can_read_field_terms;
 
 
 #[derive(Debug)]
 pub struct DocumentSource<'a> {
     pub key: &'a str,
+    pub mapping_name: &'a str,
+    pub version: u64,
     pub data: &'a serde_json::Map<String, serde_json::Value>,
 }
 
@@ -25,11 +31,27 @@ pub enum PrepareDocumentError {
 }
 
 
+/// Converts the terms of a text field into JSON for the "_positions" field
+///
+/// The terms of text fields are always UTF-8, so they're written as the keys of an object.
+fn term_positions_json(term_vector: &TermVector) -> serde_json::Value {
+    let mut positions_json = serde_json::Map::new();
+    for (term, positions) in term_vector.iter() {
+        if let Ok(term) = str::from_utf8(term.as_bytes()) {
+            positions_json.insert(term.to_string(), json!(positions));
+        }
+    }
+
+    serde_json::Value::Object(positions_json)
+}
+
+
 impl<'a> DocumentSource<'a> {
     pub fn prepare(&self, mapping: &Mapping) -> Result<Document, PrepareDocumentError> {
         let mut indexed_fields = FnvHashMap::default();
         let mut stored_fields = FnvHashMap::default();
         let mut all_field_strings: Vec<String> = Vec::new();
+        let mut field_positions = serde_json::Map::new();
 
         for (field_name, field_value) in self.data {
             if *field_value == serde_json::Value::Null {
@@ -51,8 +73,20 @@ impl<'a> DocumentSource<'a> {
                                     }
                                 }
 
+                                // Keep the positions of the terms of text fields for phrase queries
+                                if field_mapping.data_type == FieldType::String && can_read_field_terms(field_name) {
+                                    field_positions.insert(field_name.clone(), term_positions_json(&value));
+                                }
+
                                 // Insert the field
                                 indexed_fields.insert(field_mapping.index_ref.unwrap(), value);
+
+                                // Insert the sortable terms that range queries search
+                                if let Some(sortable_ref) = field_mapping.sortable_ref {
+                                    if let Ok(Some(sortable_value)) = field_mapping.process_value_for_sortable(field_value) {
+                                        indexed_fields.insert(sortable_ref, sortable_value);
+                                    }
+                                }
                             }
                             Ok(None) => {}
                             Err(error) => {
@@ -118,6 +152,56 @@ impl<'a> DocumentSource<'a> {
             }
         }
 
+        // Insert _id and _type fields
+        for &(field_name, field_value) in &[("_id", self.key), ("_type", self.mapping_name)] {
+            if let Some(&MappingProperty::Field(ref field_mapping)) = mapping.properties.get(field_name) {
+                let index_ref = match field_mapping.index_ref {
+                    Some(index_ref) => index_ref,
+                    None => continue,
+                };
+                let value_json = serde_json::Value::String(field_value.to_string());
+
+                if let Ok(Some(value)) = field_mapping.process_value_for_index(&value_json) {
+                    indexed_fields.insert(index_ref, value);
+                }
+
+                if let Ok(Some(value)) = field_mapping.process_value_for_store(&value_json) {
+                    stored_fields.insert(index_ref, value);
+                }
+            }
+        }
+
+        // Insert _version field
+        if let Some(&MappingProperty::Field(ref field_mapping)) = mapping.properties.get("_version") {
+            if let Some(index_ref) = field_mapping.index_ref {
+                if let Ok(Some(value)) = field_mapping.process_value_for_store(&serde_json::Value::from(self.version)) {
+                    stored_fields.insert(index_ref, value);
+                }
+            }
+        }
+
+        // Insert _source field
+        if let Some(&MappingProperty::Field(ref field_mapping)) = mapping.properties.get("_source") {
+            if let Some(index_ref) = field_mapping.index_ref {
+                let source_json = serde_json::Value::String(serde_json::to_string(self.data).unwrap());
+
+                if let Ok(Some(value)) = field_mapping.process_value_for_store(&source_json) {
+                    stored_fields.insert(index_ref, value);
+                }
+            }
+        }
+
+        // Insert _positions field
+        if let Some(&MappingProperty::Field(ref field_mapping)) = mapping.properties.get("_positions") {
+            if let Some(index_ref) = field_mapping.index_ref {
+                let positions_json = serde_json::Value::String(serde_json::Value::Object(field_positions).to_string());
+
+                if let Ok(Some(value)) = field_mapping.process_value_for_store(&positions_json) {
+                    stored_fields.insert(index_ref, value);
+                }
+            }
+        }
+
         Ok(Document {
             key: self.key.to_string(),
             indexed_fields: indexed_fields,
@@ -125,3 +209,162 @@ impl<'a> DocumentSource<'a> {
         })
     }
 }
+
+
+/// Merges the fields of a partial document into a source document
+///
+/// Objects are merged recursively, all other values are replaced.
+pub fn merge_source(source: &mut serde_json::Map<String, serde_json::Value>, partial: &serde_json::Map<String, serde_json::Value>) {
+    for (key, value) in partial.iter() {
+        let merged = match (source.get_mut(key), value) {
+            (Some(&mut serde_json::Value::Object(ref mut source_object)), &serde_json::Value::Object(ref partial_object)) => {
+                merge_source(source_object, partial_object);
+                true
+            }
+            _ => false,
+        };
+
+        if !merged {
+            source.insert(key.clone(), value.clone());
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+    use kite::document::FieldValue;
+    use kite::schema::{Schema, FieldType, FIELD_STORED};
+
+    use mapping::{Mapping, MappingProperty};
+    use mapping::build::{MappingBuilder, MappingPropertyBuilder, FieldMappingBuilder};
+    use index::metadata::IndexMetadata;
+
+    use super::{DocumentSource, merge_source};
+
+    /// Builds a mapping with a "title" field and links each of its fields to a stored field
+    fn make_mapping() -> Mapping {
+        let builder = MappingBuilder {
+            properties: hashmap! {
+                "title".to_string() => MappingPropertyBuilder::Field(FieldMappingBuilder::default()),
+            },
+        };
+        let mut mapping = builder.build(&IndexMetadata::default());
+
+        let mut schema = Schema::new();
+        for (field_name, property) in mapping.properties.iter_mut() {
+            if let MappingProperty::Field(ref mut field_mapping) = *property {
+                field_mapping.index_ref = Some(schema.add_field(field_name.clone(), FieldType::PlainString, FIELD_STORED).unwrap());
+            }
+        }
+
+        mapping
+    }
+
+    #[test]
+    fn test_prepare_stores_version_and_source() {
+        let mapping = make_mapping();
+        let data = json!({"title": "Hello"});
+        let document_source = DocumentSource {
+            key: "1",
+            mapping_name: "test",
+            version: 3,
+            data: data.as_object().unwrap(),
+        };
+
+        let document = document_source.prepare(&mapping).unwrap();
+        let stored_value = |field_name: &str| {
+            match mapping.properties.get(field_name) {
+                Some(&MappingProperty::Field(ref field_mapping)) => document.stored_fields.get(&field_mapping.index_ref.unwrap()).cloned(),
+                _ => None,
+            }
+        };
+
+        assert_eq!(document.key, "1");
+        match stored_value("_version") {
+            Some(FieldValue::Integer(3)) => {}
+            value => panic!("unexpected _version: {:?}", value),
+        }
+        match stored_value("_source") {
+            Some(FieldValue::String(ref source)) if source == "{\"title\":\"Hello\"}" => {}
+            value => panic!("unexpected _source: {:?}", value),
+        }
+    }
+
+    #[test]
+    fn test_prepare_stores_positions() {
+        let mapping = make_mapping();
+        let data = json!({"title": "Hello hello world"});
+        let document_source = DocumentSource {
+            key: "1",
+            mapping_name: "test",
+            version: 1,
+            data: data.as_object().unwrap(),
+        };
+
+        let document = document_source.prepare(&mapping).unwrap();
+        let positions = match mapping.properties.get("_positions") {
+            Some(&MappingProperty::Field(ref field_mapping)) => document.stored_fields.get(&field_mapping.index_ref.unwrap()).cloned(),
+            _ => None,
+        };
+
+        match positions {
+            Some(FieldValue::String(ref positions)) => {
+                assert_eq!(serde_json::from_str::<serde_json::Value>(positions).unwrap(), json!({
+                    "title": {
+                        "hello": [1, 2],
+                        "world": [3]
+                    }
+                }));
+            }
+            value => panic!("unexpected _positions: {:?}", value),
+        }
+    }
+
+    #[test]
+    fn test_prepare_gives_error_for_unmapped_field() {
+        let mapping = make_mapping();
+        let data = json!({"body": "Hello"});
+        let document_source = DocumentSource {
+            key: "1",
+            mapping_name: "test",
+            version: 1,
+            data: data.as_object().unwrap(),
+        };
+
+        assert!(document_source.prepare(&mapping).is_err());
+    }
+
+    #[test]
+    fn test_merge_source() {
+        let mut source = json!({
+            "title": "Hello",
+            "views": 10,
+            "author": {
+                "name": "Karl",
+                "email": "karl@example.com"
+            }
+        });
+
+        let partial = json!({
+            "views": 11,
+            "author": {
+                "email": "karl@example.org"
+            },
+            "published": true
+        });
+
+        merge_source(source.as_object_mut().unwrap(), partial.as_object().unwrap());
+
+        assert_eq!(source, json!({
+            "title": "Hello",
+            "views": 11,
+            "author": {
+                "name": "Karl",
+                "email": "karl@example.org"
+            },
+            "published": true
+        }));
+    }
+}
@@ -0,0 +1,191 @@
+//! Simple field operations that are applied to source documents by update by query
+//!
+//! Each operation is an object with a single key naming the operation, for example:
+//! {"set": {"field": "user.name", "value": "Karl"}} or {"remove": {"field": "draft"}}.
+//! Fields are dotted paths, setting a field creates any objects along the path that are missing.
+
+use serde_json::Value as Json;
+use serde_json::Map;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldOperation {
+    Set {
+        field: String,
+        value: Json,
+    },
+    Remove {
+        field: String,
+    },
+}
+
+
+impl FieldOperation {
+    pub fn apply(&self, source: &mut Map<String, Json>) {
+        match *self {
+            FieldOperation::Set { ref field, ref value } => set_field(source, field, value.clone()),
+            FieldOperation::Remove { ref field } => remove_field(source, field),
+        }
+    }
+}
+
+
+fn set_field(object: &mut Map<String, Json>, path: &str, value: Json) {
+    match path.find('.') {
+        Some(split_at) => {
+            let (name, rest) = (&path[..split_at], &path[split_at + 1..]);
+
+            // Replace anything that's in the way with an empty object
+            if !object.get(name).map_or(false, |child| child.is_object()) {
+                object.insert(name.to_string(), Json::Object(Map::new()));
+            }
+
+            if let Some(&mut Json::Object(ref mut child)) = object.get_mut(name) {
+                set_field(child, rest, value);
+            }
+        }
+        None => {
+            object.insert(path.to_string(), value);
+        }
+    }
+}
+
+
+fn remove_field(object: &mut Map<String, Json>, path: &str) {
+    match path.find('.') {
+        Some(split_at) => {
+            if let Some(&mut Json::Object(ref mut child)) = object.get_mut(&path[..split_at]) {
+                remove_field(child, &path[split_at + 1..]);
+            }
+        }
+        None => {
+            object.remove(path);
+        }
+    }
+}
+
+
+#[derive(Debug, PartialEq)]
+pub enum FieldOperationParseError {
+    ExpectedArray,
+    ExpectedObject,
+    ExpectedSingleKey,
+    UnrecognisedOperation(String),
+    MissingField,
+    MissingValue,
+}
+
+
+fn parse_operation(json: &Json) -> Result<FieldOperation, FieldOperationParseError> {
+    let object = json.as_object().ok_or(FieldOperationParseError::ExpectedObject)?;
+    if object.len() != 1 {
+        return Err(FieldOperationParseError::ExpectedSingleKey);
+    }
+
+    let (operation_name, params) = match object.iter().next() {
+        Some((operation_name, &Json::Object(ref params))) => (operation_name, params),
+        _ => return Err(FieldOperationParseError::ExpectedObject),
+    };
+
+    let field = match params.get("field").and_then(|field| field.as_str()) {
+        Some(field) => field.to_string(),
+        None => return Err(FieldOperationParseError::MissingField),
+    };
+
+    match operation_name.as_str() {
+        "set" => {
+            match params.get("value") {
+                Some(value) => {
+                    Ok(FieldOperation::Set {
+                        field: field,
+                        value: value.clone(),
+                    })
+                }
+                None => Err(FieldOperationParseError::MissingValue),
+            }
+        }
+        "remove" => {
+            Ok(FieldOperation::Remove {
+                field: field,
+            })
+        }
+        _ => Err(FieldOperationParseError::UnrecognisedOperation(operation_name.clone())),
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Vec<FieldOperation>, FieldOperationParseError> {
+    let array = json.as_array().ok_or(FieldOperationParseError::ExpectedArray)?;
+
+    let mut operations = Vec::with_capacity(array.len());
+    for item in array.iter() {
+        operations.push(parse_operation(item)?);
+    }
+
+    Ok(operations)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{FieldOperation, FieldOperationParseError, parse};
+
+    #[test]
+    fn test_parse() {
+        let operations = parse(&json!([
+            {"set": {"field": "published", "value": true}},
+            {"remove": {"field": "draft"}}
+        ]));
+
+        assert_eq!(operations, Ok(vec![
+            FieldOperation::Set {
+                field: "published".to_string(),
+                value: json!(true),
+            },
+            FieldOperation::Remove {
+                field: "draft".to_string(),
+            },
+        ]));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse(&json!({"set": {"field": "published", "value": true}})), Err(FieldOperationParseError::ExpectedArray));
+        assert_eq!(parse(&json!([{"set": {"field": "published"}}])), Err(FieldOperationParseError::MissingValue));
+        assert_eq!(parse(&json!([{"remove": {}}])), Err(FieldOperationParseError::MissingField));
+        assert_eq!(parse(&json!([{"rename": {"field": "title"}}])), Err(FieldOperationParseError::UnrecognisedOperation("rename".to_string())));
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut source = json!({
+            "title": "Hello",
+            "draft": true,
+            "user": {
+                "name": "Karl",
+                "email": "karl@example.com"
+            }
+        });
+
+        let operations = parse(&json!([
+            {"set": {"field": "user.name", "value": "Dan"}},
+            {"set": {"field": "stats.views", "value": 0}},
+            {"remove": {"field": "draft"}},
+            {"remove": {"field": "user.email"}}
+        ])).unwrap();
+
+        for operation in operations.iter() {
+            operation.apply(source.as_object_mut().unwrap());
+        }
+
+        assert_eq!(source, json!({
+            "title": "Hello",
+            "user": {
+                "name": "Dan"
+            },
+            "stats": {
+                "views": 0
+            }
+        }));
+    }
+}
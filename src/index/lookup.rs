@@ -0,0 +1,153 @@
+//! Reads documents and their fields back out of an index
+
+use serde_json;
+use kite::{Term, Query, TermScorer};
+use kite::term_vector::TermVector;
+use kite::document::{DocRef, FieldValue};
+use kite::schema::FieldRef;
+use kite_rocksdb::RocksDBReader;
+
+use mapping::FieldMapping;
+use collectors::doc_ids::DocIdCollector;
+
+
+/// Finds a document by its key
+///
+/// The store doesn't give access to its key index, so this searches the "_id" field instead
+pub fn find_document(index_reader: &RocksDBReader, key: &str) -> Option<DocRef> {
+    let id_field = match index_reader.schema().get_field_by_name("_id") {
+        Some(id_field) => id_field,
+        None => return None,
+    };
+
+    let query = Query::Term {
+        field: id_field,
+        term: Term::from_string(key),
+        scorer: TermScorer::default(),
+    };
+
+    let mut collector = DocIdCollector::new();
+    index_reader.search(&mut collector, &query).unwrap();
+    collector.into_vec().pop().map(DocRef::from_u64)
+}
+
+
+/// Checks for a document that's in the store but can't be found by searching the "_id" field
+///
+/// Documents that were indexed before the "_id" field was added can't be read back, but the
+/// store still knows their keys so they can be checked for, replaced and deleted.
+pub fn contains_document_without_id(index_reader: &RocksDBReader, key: &str) -> bool {
+    find_document(index_reader, key).is_none() && index_reader.contains_document_key(key)
+}
+
+
+/// Finds a document, optionally checking that it belongs to the given mapping
+pub fn find_document_in_mapping(index_reader: &RocksDBReader, mapping_name: Option<&str>, doc_key: &str) -> Option<DocRef> {
+    let doc_ref = match find_document(index_reader, doc_key) {
+        Some(doc_ref) => doc_ref,
+        None => return None,
+    };
+
+    // Document keys are unique across the whole index so the document may belong to another mapping
+    let type_field = index_reader.schema().get_field_by_name("_type");
+    match (mapping_name, read_stored_string(index_reader, type_field, doc_ref)) {
+        (Some(mapping_name), Some(ref doc_mapping_name)) if doc_mapping_name != mapping_name => None,
+        _ => Some(doc_ref),
+    }
+}
+
+
+pub fn read_stored_string(index_reader: &RocksDBReader, field_ref: Option<FieldRef>, doc_ref: DocRef) -> Option<String> {
+    match field_ref.map(|field_ref| index_reader.read_stored_field(field_ref, doc_ref)) {
+        Some(Ok(Some(FieldValue::String(value)))) => Some(value),
+        _ => None,
+    }
+}
+
+
+/// Reads the original JSON document that was indexed
+pub fn read_source(index_reader: &RocksDBReader, doc_ref: DocRef) -> Option<serde_json::Value> {
+    let source_field = index_reader.schema().get_field_by_name("_source");
+
+    match read_stored_string(index_reader, source_field, doc_ref) {
+        Some(source) => serde_json::from_str(&source).ok(),
+        None => None,
+    }
+}
+
+
+/// Reads the version number of a document
+///
+/// Documents that were indexed before versions were stored count as the first version
+pub fn read_version(index_reader: &RocksDBReader, doc_ref: DocRef) -> u64 {
+    let version_field = index_reader.schema().get_field_by_name("_version");
+
+    match version_field.map(|version_field| index_reader.read_stored_field(version_field, doc_ref)) {
+        Some(Ok(Some(FieldValue::Integer(version)))) => version as u64,
+        _ => 1,
+    }
+}
+
+
+/// Checks if `read_field_terms` can read the terms of a field
+///
+/// Only text fields at the top level of the source have their positions stored. The "_all" field
+/// and the other metadata fields aren't read from a single value in the source, and fields inside
+/// objects aren't indexed.
+pub fn can_read_field_terms(field_name: &str) -> bool {
+    !field_name.starts_with('_') && !field_name.contains('.')
+}
+
+
+/// Converts the positions of the terms of a field from the "_positions" field back into terms
+fn parse_term_positions(json: &serde_json::Value) -> Option<TermVector> {
+    let terms = match *json {
+        serde_json::Value::Object(ref terms) => terms,
+        _ => return None,
+    };
+
+    let mut term_vector = TermVector::new();
+    for (term, positions_json) in terms.iter() {
+        let positions = match positions_json.as_array() {
+            Some(positions) => positions.iter().filter_map(|position| position.as_u64()).map(|position| position as u32).collect(),
+            None => return None,
+        };
+
+        term_vector.insert(Term::from_string(term), positions);
+    }
+
+    Some(term_vector)
+}
+
+
+/// Reads the terms of a field and their positions in a document
+///
+/// The store doesn't keep the positions of terms and can't list the terms of a document, so
+/// queries that need them (match_phrase and match_phrase_prefix) find the documents that could
+/// match with an ordinary query first and then read the terms of each one with this. These are
+/// stored in the "_positions" field when the document is indexed. Documents that were indexed
+/// before that have their terms rebuilt from their source instead.
+pub fn read_field_terms(index_reader: &RocksDBReader, doc_ref: DocRef, field_name: &str, field_mapping: &FieldMapping) -> Option<TermVector> {
+    let positions_field = index_reader.schema().get_field_by_name("_positions");
+    if let Some(positions) = read_stored_string(index_reader, positions_field, doc_ref) {
+        return match serde_json::from_str(&positions) {
+            Ok(serde_json::Value::Object(mut positions)) => positions.remove(field_name).and_then(|positions| parse_term_positions(&positions)),
+            _ => None,
+        };
+    }
+
+    let value = match read_source(index_reader, doc_ref) {
+        Some(serde_json::Value::Object(mut source)) => {
+            match source.remove(field_name) {
+                Some(value) => value,
+                None => return None,
+            }
+        }
+        _ => return None,
+    };
+
+    match field_mapping.process_value_for_index(&value) {
+        Ok(Some(term_vector)) => Some(term_vector),
+        _ => None,
+    }
+}
@@ -1,13 +1,18 @@
 pub mod maintenance;
 pub mod metadata;
+pub mod lookup;
+pub mod write;
+pub mod field_ops;
+pub mod term_dictionary;
 
-use std::sync::RwLock;
-use std::path::PathBuf;
+use std::sync::{RwLock, Mutex};
+use std::path::{Path, PathBuf};
 
 use kite_rocksdb::RocksDBStore;
 use uuid::Uuid;
 
 use index::metadata::IndexMetadata;
+use index::term_dictionary::TermDictionary;
 
 
 #[derive(Debug)]
@@ -16,16 +21,22 @@ pub struct Index {
     canonical_name: String,
     pub metadata: RwLock<IndexMetadata>,
     pub store: RocksDBStore,
+    pub term_dictionary: TermDictionary,
+
+    /// Held by writes from reading a document's current version until the new version is stored
+    write_lock: Mutex<()>,
 }
 
 
 impl Index {
-    pub fn new(id: Uuid, canonical_name: String, metadata: IndexMetadata, store: RocksDBStore) -> Index {
+    pub fn new(id: Uuid, canonical_name: String, metadata: IndexMetadata, store: RocksDBStore, term_dictionary: TermDictionary) -> Index {
         Index {
             id: id,
             canonical_name: canonical_name,
             metadata: RwLock::new(metadata),
             store: store,
+            term_dictionary: term_dictionary,
+            write_lock: Mutex::new(()),
         }
     }
 
@@ -43,3 +54,11 @@ impl Index {
         path
     }
 }
+
+
+/// The path of the term dictionary file in an index's directory
+pub fn term_dictionary_path(index_path: &Path) -> PathBuf {
+    let mut path = index_path.to_path_buf();
+    path.push("terms.jsonl");
+    path
+}
@@ -0,0 +1,215 @@
+//! Lists the terms that have been indexed into each field
+//!
+//! The store has a term dictionary but doesn't give access to it, so queries that expand a term
+//! into the terms of a field (match_phrase_prefix and fuzzy) use this one instead. Terms are added
+//! as documents are written and appended to a file in the index directory so they can be loaded
+//! again. Terms stay in the dictionary after the documents that contain them are replaced or
+//! deleted, expansions to these just don't match anything.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::{RwLock, Mutex};
+
+use serde_json;
+use kite::{Term, Document};
+use kite::schema::FieldRef;
+
+
+#[derive(Debug)]
+pub struct TermDictionary {
+    terms: RwLock<HashMap<FieldRef, BTreeSet<Term>>>,
+
+    /// The file that new terms are appended to, None if the index was created before terms were
+    /// listed so the dictionary is incomplete
+    file: Option<Mutex<File>>,
+}
+
+
+impl TermDictionary {
+    /// Creates the dictionary of a new index
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<TermDictionary, String> {
+        let file = OpenOptions::new().append(true).create_new(true).open(path).map_err(|error| error.to_string())?;
+
+        Ok(TermDictionary {
+            terms: RwLock::new(HashMap::new()),
+            file: Some(Mutex::new(file)),
+        })
+    }
+
+    /// Loads the dictionary of an existing index
+    ///
+    /// Indices that were created before terms were listed don't have a dictionary file, these
+    /// are given an incomplete dictionary that terms aren't added to.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<TermDictionary, String> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(TermDictionary {
+                terms: RwLock::new(HashMap::new()),
+                file: None,
+            });
+        }
+
+        let mut file = OpenOptions::new().read(true).append(true).open(path).map_err(|error| error.to_string())?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).map_err(|error| error.to_string())?;
+
+        let mut terms = HashMap::new();
+        for line in contents.lines() {
+            // The last line is skipped if the server stopped while it was being written
+            if let Ok((field_ref, term)) = serde_json::from_str::<(FieldRef, Term)>(line) {
+                terms.entry(field_ref).or_insert_with(BTreeSet::new).insert(term);
+            }
+        }
+
+        // Start a new line after a line that was cut short
+        if !contents.is_empty() && !contents.ends_with('\n') {
+            file.write_all(b"\n").map_err(|error| error.to_string())?;
+        }
+
+        Ok(TermDictionary {
+            terms: RwLock::new(terms),
+            file: Some(Mutex::new(file)),
+        })
+    }
+
+    /// Checks if the dictionary has every term in the index
+    pub fn is_complete(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Adds the terms of a document that aren't in the dictionary yet
+    ///
+    /// This must be done before the document is written to the store, so the dictionary always
+    /// has the terms of the documents that can be found.
+    pub fn add_document(&self, doc: &Document) -> Result<(), String> {
+        let file = match self.file {
+            Some(ref file) => file,
+            None => return Ok(()),
+        };
+
+        let mut terms = self.terms.write().unwrap();
+        let mut new_terms = Vec::new();
+        for (field_ref, term_vector) in doc.indexed_fields.iter() {
+            let field_terms = terms.get(field_ref);
+
+            for term in term_vector.keys() {
+                if !field_terms.map_or(false, |field_terms| field_terms.contains(term)) {
+                    new_terms.push((*field_ref, term.clone()));
+                }
+            }
+        }
+
+        if new_terms.is_empty() {
+            return Ok(());
+        }
+
+        // The terms are written out first so they're only added if they can be loaded again
+        let mut lines = String::new();
+        for new_term in new_terms.iter() {
+            lines.push_str(&serde_json::to_string(new_term).unwrap());
+            lines.push('\n');
+        }
+        file.lock().unwrap().write_all(lines.as_bytes()).map_err(|error| error.to_string())?;
+
+        for (field_ref, term) in new_terms {
+            terms.entry(field_ref).or_insert_with(BTreeSet::new).insert(term);
+        }
+
+        Ok(())
+    }
+
+    /// Gives each term of a field that starts with the prefix to the callback, in term order,
+    /// until it returns false
+    pub fn scan_prefix<F: FnMut(&Term) -> bool>(&self, field_ref: FieldRef, prefix: &[u8], mut callback: F) {
+        let terms = self.terms.read().unwrap();
+        let field_terms = match terms.get(&field_ref) {
+            Some(field_terms) => field_terms,
+            None => return,
+        };
+
+        for term in field_terms.range(Term::from_bytes(prefix)..) {
+            if !term.as_bytes().starts_with(prefix) || !callback(term) {
+                break;
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Read;
+    use std::path::PathBuf;
+
+    use kite::{Term, Token, Document};
+    use kite::schema::{Schema, FieldType, FieldRef, FIELD_INDEXED};
+    use fnv::FnvHashMap;
+    use uuid::Uuid;
+
+    use super::TermDictionary;
+
+    fn temp_path() -> PathBuf {
+        env::temp_dir().join(format!("rusticsearch-test-terms-{}", Uuid::new_v4()))
+    }
+
+    fn scan_terms(term_dictionary: &TermDictionary, field_ref: FieldRef, prefix: &str) -> Vec<Term> {
+        let mut terms = Vec::new();
+        term_dictionary.scan_prefix(field_ref, prefix.as_bytes(), |term| {
+            terms.push(term.clone());
+            true
+        });
+        terms
+    }
+
+    #[test]
+    fn test_add_and_reload() {
+        let mut schema = Schema::new();
+        let title_field = schema.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let body_field = schema.add_field("body".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let mut indexed_fields = FnvHashMap::default();
+        indexed_fields.insert(title_field, vec![
+            Token {term: Term::from_string("quick"), position: 1},
+            Token {term: Term::from_string("brown"), position: 2},
+            Token {term: Term::from_string("quiet"), position: 3},
+        ].into());
+        indexed_fields.insert(body_field, vec![Token {term: Term::from_string("quilt"), position: 1}].into());
+        let doc = Document {
+            key: "1".to_string(),
+            indexed_fields: indexed_fields,
+            stored_fields: FnvHashMap::default(),
+        };
+
+        let path = temp_path();
+        let term_dictionary = TermDictionary::create(&path).unwrap();
+        assert!(term_dictionary.is_complete());
+        term_dictionary.add_document(&doc).unwrap();
+        term_dictionary.add_document(&doc).unwrap();
+
+        let quick_terms = vec![Term::from_string("quick"), Term::from_string("quiet")];
+        assert_eq!(scan_terms(&term_dictionary, title_field, "qui"), quick_terms);
+
+        // Each term is only written once
+        let term_dictionary = TermDictionary::open(&path).unwrap();
+        assert_eq!(scan_terms(&term_dictionary, title_field, "qui"), quick_terms);
+        assert_eq!(scan_terms(&term_dictionary, title_field, "").len(), 3);
+        assert_eq!(scan_terms(&term_dictionary, body_field, "qui"), vec![Term::from_string("quilt")]);
+
+        let mut contents = String::new();
+        File::open(&path).unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents.lines().count(), 4);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_open_without_file() {
+        let term_dictionary = TermDictionary::open(temp_path()).unwrap();
+
+        assert!(!term_dictionary.is_complete());
+    }
+}
@@ -0,0 +1,355 @@
+//! Indexes, updates and deletes documents
+//!
+//! These are shared by the document and bulk APIs. Each write replaces the whole document
+//! in the store and bumps its version. Writes to an index are serialised so the version that
+//! was read can't change before the new version of the document is stored.
+
+use serde_json;
+
+use document::{DocumentSource, PrepareDocumentError, merge_source};
+use mapping::Mapping;
+use index::Index;
+use index::metadata::IndexMetadata;
+use index::lookup::{find_document, find_document_in_mapping, contains_document_without_id, read_source, read_version};
+
+
+#[derive(Debug)]
+pub enum WriteError {
+    MappingNotFound,
+    DocumentNotFound,
+    DocumentAlreadyExists,
+
+    /// The document was changed after it was read
+    VersionConflict {
+        current: u64,
+        expected: u64,
+    },
+
+    InvalidRequest(String),
+    PrepareDocument(PrepareDocumentError),
+    Store(String),
+}
+
+
+impl WriteError {
+    /// The Elasticsearch exception type of the error
+    pub fn error_type(&self) -> &'static str {
+        match *self {
+            WriteError::MappingNotFound => "type_missing_exception",
+            WriteError::DocumentNotFound => "document_missing_exception",
+            WriteError::DocumentAlreadyExists => "version_conflict_engine_exception",
+            WriteError::VersionConflict { .. } => "version_conflict_engine_exception",
+            WriteError::InvalidRequest(_) => "action_request_validation_exception",
+            WriteError::PrepareDocument(_) => "mapper_parsing_exception",
+            WriteError::Store(_) => "exception",
+        }
+    }
+
+    pub fn reason(&self) -> String {
+        match *self {
+            WriteError::MappingNotFound => "Mapping not found".to_string(),
+            WriteError::DocumentNotFound => "Document not found".to_string(),
+            WriteError::DocumentAlreadyExists => "Document already exists".to_string(),
+            WriteError::VersionConflict { current, expected } => format!("version conflict, current version [{}] is different than the one provided [{}]", current, expected),
+            WriteError::InvalidRequest(ref reason) => reason.clone(),
+            WriteError::PrepareDocument(ref error) => format!("{:?}", error),
+            WriteError::Store(ref error) => error.clone(),
+        }
+    }
+}
+
+
+#[derive(Debug, PartialEq)]
+pub struct WriteResult {
+    pub version: u64,
+
+    /// Set if the document didn't exist before the write
+    pub created: bool,
+}
+
+
+/// Checks that a document exists in the given mapping, returns its current version
+///
+/// The mapping and version of documents indexed before the "_id" field was added can't be read,
+/// so these are assumed to be in the given mapping and on their first version.
+fn find_version(index: &Index, mapping_name: &str, doc_key: &str) -> Option<u64> {
+    let index_reader = index.store.reader();
+
+    if let Some(doc_ref) = find_document_in_mapping(&index_reader, Some(mapping_name), doc_key) {
+        return Some(read_version(&index_reader, doc_ref));
+    }
+
+    if contains_document_without_id(&index_reader, doc_key) {
+        return Some(1);
+    }
+
+    None
+}
+
+
+/// Inserts a document or replaces an existing one with the same key
+///
+/// If "create_only" is set, this fails if the document already exists.
+pub fn index_document(index: &Index, index_metadata: &IndexMetadata, mapping_name: &str, doc_key: &str, data: &serde_json::Value, create_only: bool) -> Result<WriteResult, WriteError> {
+    let mapping = match index_metadata.mappings.get(mapping_name) {
+        Some(mapping) => mapping,
+        None => return Err(WriteError::MappingNotFound),
+    };
+
+    let data = match data.as_object() {
+        Some(data) => data,
+        None => return Err(WriteError::InvalidRequest("Document must be an object".to_string())),
+    };
+
+    let _write_guard = index.write_lock.lock().unwrap();
+    let current_version = find_version(index, mapping_name, doc_key);
+    if create_only && current_version.is_some() {
+        return Err(WriteError::DocumentAlreadyExists);
+    }
+
+    store_document(index, mapping, mapping_name, doc_key, data, current_version)
+}
+
+
+/// Replaces a document, checking that it hasn't changed since the given version was read
+///
+/// This is for requests that read documents and then write them back, like update by query.
+pub fn replace_document_version(index: &Index, index_metadata: &IndexMetadata, mapping_name: &str, doc_key: &str, data: &serde_json::Value, expected_version: u64) -> Result<WriteResult, WriteError> {
+    let mapping = match index_metadata.mappings.get(mapping_name) {
+        Some(mapping) => mapping,
+        None => return Err(WriteError::MappingNotFound),
+    };
+
+    let data = match data.as_object() {
+        Some(data) => data,
+        None => return Err(WriteError::InvalidRequest("Document must be an object".to_string())),
+    };
+
+    let _write_guard = index.write_lock.lock().unwrap();
+    let current_version = match find_version(index, mapping_name, doc_key) {
+        Some(current_version) => current_version,
+        None => return Err(WriteError::DocumentNotFound),
+    };
+
+    if current_version != expected_version {
+        return Err(WriteError::VersionConflict {
+            current: current_version,
+            expected: expected_version,
+        });
+    }
+
+    store_document(index, mapping, mapping_name, doc_key, data, Some(current_version))
+}
+
+
+/// Writes the next version of a document, the caller must hold the index's write lock
+fn store_document(index: &Index, mapping: &Mapping, mapping_name: &str, doc_key: &str, data: &serde_json::Map<String, serde_json::Value>, current_version: Option<u64>) -> Result<WriteResult, WriteError> {
+    // Replacing a document increments its version
+    let version = current_version.map_or(1, |version| version + 1);
+    let document_source = DocumentSource {
+        key: doc_key,
+        mapping_name: mapping_name,
+        version: version,
+        data: data,
+    };
+    let doc = document_source.prepare(mapping).map_err(WriteError::PrepareDocument)?;
+
+    if let Err(error) = index.term_dictionary.add_document(&doc) {
+        return Err(WriteError::Store(error));
+    }
+
+    if let Err(error) = index.store.insert_or_update_document(&doc) {
+        return Err(WriteError::Store(format!("{:?}", error)));
+    }
+
+    Ok(WriteResult {
+        version: version,
+        created: current_version.is_none(),
+    })
+}
+
+
+/// A parsed update request
+#[derive(Debug)]
+struct Update<'a> {
+    partial_doc: Option<&'a serde_json::Map<String, serde_json::Value>>,
+    upsert: Option<&'a serde_json::Map<String, serde_json::Value>>,
+    doc_as_upsert: bool,
+}
+
+
+impl<'a> Update<'a> {
+    fn parse(update: &'a serde_json::Value) -> Result<Update<'a>, WriteError> {
+        let update = match update.as_object() {
+            Some(update) => update,
+            None => return Err(WriteError::InvalidRequest("Update must be an object".to_string())),
+        };
+
+        if update.contains_key("script") {
+            return Err(WriteError::InvalidRequest("Scripted updates aren't supported".to_string()));
+        }
+
+        let partial_doc = match update.get("doc") {
+            Some(&serde_json::Value::Object(ref partial_doc)) => Some(partial_doc),
+            Some(_) => return Err(WriteError::InvalidRequest("\"doc\" must be an object".to_string())),
+            None => None,
+        };
+
+        let upsert = match update.get("upsert") {
+            Some(&serde_json::Value::Object(ref upsert)) => Some(upsert),
+            Some(_) => return Err(WriteError::InvalidRequest("\"upsert\" must be an object".to_string())),
+            None => None,
+        };
+
+        if partial_doc.is_none() && upsert.is_none() {
+            return Err(WriteError::InvalidRequest("Expected \"doc\" or \"upsert\"".to_string()));
+        }
+
+        Ok(Update {
+            partial_doc: partial_doc,
+            upsert: upsert,
+            doc_as_upsert: update.get("doc_as_upsert").and_then(|doc_as_upsert| doc_as_upsert.as_bool()).unwrap_or(false),
+        })
+    }
+
+    /// Works out the new source of the document from its current source
+    ///
+    /// The current source is None if the document doesn't exist
+    fn apply(&self, current_source: Option<serde_json::Map<String, serde_json::Value>>) -> Result<serde_json::Map<String, serde_json::Value>, WriteError> {
+        match current_source {
+            Some(mut source) => {
+                if let Some(partial_doc) = self.partial_doc {
+                    merge_source(&mut source, partial_doc);
+                }
+
+                Ok(source)
+            }
+            None => {
+                match (self.upsert, self.partial_doc) {
+                    (Some(upsert), _) => Ok(upsert.clone()),
+                    (None, Some(partial_doc)) if self.doc_as_upsert => Ok(partial_doc.clone()),
+                    _ => Err(WriteError::DocumentNotFound),
+                }
+            }
+        }
+    }
+}
+
+
+/// Updates the fields of an existing document
+///
+/// The update is an object containing a partial document ("doc") that is merged into the
+/// stored source. If the document doesn't exist, it's created from "upsert" or, if
+/// "doc_as_upsert" is set, from the partial document.
+pub fn update_document(index: &Index, index_metadata: &IndexMetadata, mapping_name: &str, doc_key: &str, update: &serde_json::Value) -> Result<WriteResult, WriteError> {
+    let update = Update::parse(update)?;
+
+    let mapping = match index_metadata.mappings.get(mapping_name) {
+        Some(mapping) => mapping,
+        None => return Err(WriteError::MappingNotFound),
+    };
+
+    let _write_guard = index.write_lock.lock().unwrap();
+    let current_version = find_version(index, mapping_name, doc_key);
+
+    // Read the current source of the document
+    let current_source = match current_version {
+        Some(_) => {
+            let index_reader = index.store.reader();
+            match find_document(&index_reader, doc_key).and_then(|doc_ref| read_source(&index_reader, doc_ref)) {
+                Some(serde_json::Value::Object(source)) => Some(source),
+                _ => return Err(WriteError::InvalidRequest("Document has no source to update".to_string())),
+            }
+        }
+        None => None,
+    };
+
+    let source = update.apply(current_source)?;
+    store_document(index, mapping, mapping_name, doc_key, &source, current_version)
+}
+
+
+/// Deletes a document, returns the version it would have had after the delete
+pub fn delete_document(index: &Index, mapping_name: &str, doc_key: &str) -> Result<u64, WriteError> {
+    let _write_guard = index.write_lock.lock().unwrap();
+    let version = match find_version(index, mapping_name, doc_key) {
+        Some(version) => version,
+        None => return Err(WriteError::DocumentNotFound),
+    };
+
+    if let Err(error) = index.store.remove_document_by_key(doc_key) {
+        return Err(WriteError::Store(format!("{:?}", error)));
+    }
+
+    Ok(version + 1)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use super::{Update, WriteError};
+
+    fn apply(update: serde_json::Value, current_source: Option<serde_json::Value>) -> Result<serde_json::Value, WriteError> {
+        let update = Update::parse(&update)?;
+        let current_source = current_source.map(|source| source.as_object().unwrap().clone());
+
+        update.apply(current_source).map(serde_json::Value::Object)
+    }
+
+    #[test]
+    fn test_merges_partial_document() {
+        let source = apply(json!({
+            "doc": {"views": 11}
+        }), Some(json!({"title": "Hello", "views": 10})));
+
+        assert_eq!(source.unwrap(), json!({"title": "Hello", "views": 11}));
+    }
+
+    #[test]
+    fn test_upsert() {
+        let update = json!({
+            "doc": {"views": 11},
+            "upsert": {"title": "New", "views": 1}
+        });
+
+        // The partial document is used when the document exists
+        assert_eq!(apply(update.clone(), Some(json!({"title": "Hello", "views": 10}))).unwrap(), json!({"title": "Hello", "views": 11}));
+
+        // Otherwise the document is created from the upsert
+        assert_eq!(apply(update, None).unwrap(), json!({"title": "New", "views": 1}));
+    }
+
+    #[test]
+    fn test_doc_as_upsert() {
+        let source = apply(json!({
+            "doc": {"title": "New"},
+            "doc_as_upsert": true
+        }), None);
+
+        assert_eq!(source.unwrap(), json!({"title": "New"}));
+    }
+
+    #[test]
+    fn test_missing_document() {
+        let source = apply(json!({
+            "doc": {"title": "New"}
+        }), None);
+
+        match source {
+            Err(WriteError::DocumentNotFound) => {}
+            result => panic!("expected DocumentNotFound, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_update() {
+        for update in vec![json!([]), json!({}), json!({"doc": "foo"}), json!({"upsert": 1}), json!({"script": "ctx._source.views += 1"})] {
+            match Update::parse(&update) {
+                Err(WriteError::InvalidRequest(_)) => {}
+                result => panic!("expected InvalidRequest for {}, got {:?}", update, result),
+            }
+        }
+    }
+}
@@ -1,6 +1,7 @@
 extern crate kite;
 extern crate kite_rocksdb;
 extern crate chrono;
+extern crate chrono_tz;
 #[macro_use]
 extern crate router;
 extern crate url;
@@ -21,6 +22,8 @@ extern crate fnv;
 
 pub mod analysis;
 pub mod query_parser;
+pub mod collectors;
+pub mod search;
 pub mod mapping;
 pub mod document;
 pub mod index;
@@ -84,6 +84,7 @@ impl FieldMappingBuilder {
         FieldMapping {
             data_type: self.field_type,
             index_ref: None,
+            sortable_ref: None,
             is_indexed: self.is_indexed,
             is_stored: self.is_stored,
             is_in_all: self.is_in_all,
@@ -175,6 +176,63 @@ impl MappingBuilder {
             ));
         }
 
+        // Insert _id and _type fields
+        // These hold the document's key and mapping name so they can be read back from search results
+        for field_name in &["_id", "_type"] {
+            if !properties.contains_key(*field_name) {
+                properties.insert(field_name.to_string(), MappingProperty::Field(
+                    FieldMapping {
+                        data_type: FieldType::String,
+                        is_stored: true,
+                        is_in_all: false,
+                        .. FieldMapping::default()
+                    }
+                ));
+            }
+        }
+
+        // Insert _version field
+        // This is incremented each time the document is indexed
+        if !properties.contains_key("_version") {
+            properties.insert("_version".to_string(), MappingProperty::Field(
+                FieldMapping {
+                    data_type: FieldType::Integer,
+                    is_indexed: false,
+                    is_stored: true,
+                    is_in_all: false,
+                    .. FieldMapping::default()
+                }
+            ));
+        }
+
+        // Insert _source field
+        // This holds the original JSON document so it can be returned by the get and search APIs
+        if !properties.contains_key("_source") {
+            properties.insert("_source".to_string(), MappingProperty::Field(
+                FieldMapping {
+                    data_type: FieldType::String,
+                    is_indexed: false,
+                    is_stored: true,
+                    is_in_all: false,
+                    .. FieldMapping::default()
+                }
+            ));
+        }
+
+        // Insert _positions field
+        // This holds the positions of the terms in each text field so phrases can be checked
+        if !properties.contains_key("_positions") {
+            properties.insert("_positions".to_string(), MappingProperty::Field(
+                FieldMapping {
+                    data_type: FieldType::String,
+                    is_indexed: false,
+                    is_stored: true,
+                    is_in_all: false,
+                    .. FieldMapping::default()
+                }
+            ));
+        }
+
         Mapping {
             properties: properties,
         }
@@ -226,6 +284,39 @@ mod tests {
                     index_analyzer: Some(get_standard_analyzer()),
                     search_analyzer: Some(get_standard_analyzer()),
                     ..FieldMapping::default()
+                }),
+                "_id".to_string() => MappingProperty::Field(FieldMapping {
+                    data_type: FieldType::String,
+                    is_stored: true,
+                    is_in_all: false,
+                    ..FieldMapping::default()
+                }),
+                "_type".to_string() => MappingProperty::Field(FieldMapping {
+                    data_type: FieldType::String,
+                    is_stored: true,
+                    is_in_all: false,
+                    ..FieldMapping::default()
+                }),
+                "_version".to_string() => MappingProperty::Field(FieldMapping {
+                    data_type: FieldType::Integer,
+                    is_indexed: false,
+                    is_stored: true,
+                    is_in_all: false,
+                    ..FieldMapping::default()
+                }),
+                "_source".to_string() => MappingProperty::Field(FieldMapping {
+                    data_type: FieldType::String,
+                    is_indexed: false,
+                    is_stored: true,
+                    is_in_all: false,
+                    ..FieldMapping::default()
+                }),
+                "_positions".to_string() => MappingProperty::Field(FieldMapping {
+                    data_type: FieldType::String,
+                    is_indexed: false,
+                    is_stored: true,
+                    is_in_all: false,
+                    ..FieldMapping::default()
                 })
             }
         });
@@ -248,6 +339,39 @@ mod tests {
                     index_analyzer: Some(get_standard_analyzer()),
                     search_analyzer: Some(get_standard_analyzer()),
                     ..FieldMapping::default()
+                }),
+                "_id".to_string() => MappingProperty::Field(FieldMapping {
+                    data_type: FieldType::String,
+                    is_stored: true,
+                    is_in_all: false,
+                    ..FieldMapping::default()
+                }),
+                "_type".to_string() => MappingProperty::Field(FieldMapping {
+                    data_type: FieldType::String,
+                    is_stored: true,
+                    is_in_all: false,
+                    ..FieldMapping::default()
+                }),
+                "_version".to_string() => MappingProperty::Field(FieldMapping {
+                    data_type: FieldType::Integer,
+                    is_indexed: false,
+                    is_stored: true,
+                    is_in_all: false,
+                    ..FieldMapping::default()
+                }),
+                "_source".to_string() => MappingProperty::Field(FieldMapping {
+                    data_type: FieldType::String,
+                    is_indexed: false,
+                    is_stored: true,
+                    is_in_all: false,
+                    ..FieldMapping::default()
+                }),
+                "_positions".to_string() => MappingProperty::Field(FieldMapping {
+                    data_type: FieldType::String,
+                    is_indexed: false,
+                    is_stored: true,
+                    is_in_all: false,
+                    ..FieldMapping::default()
                 })
             }
         });
@@ -278,6 +402,39 @@ mod tests {
                     index_analyzer: Some(get_standard_analyzer()),
                     search_analyzer: Some(get_standard_analyzer()),
                     ..FieldMapping::default()
+                }),
+                "_id".to_string() => MappingProperty::Field(FieldMapping {
+                    data_type: FieldType::String,
+                    is_stored: true,
+                    is_in_all: false,
+                    ..FieldMapping::default()
+                }),
+                "_type".to_string() => MappingProperty::Field(FieldMapping {
+                    data_type: FieldType::String,
+                    is_stored: true,
+                    is_in_all: false,
+                    ..FieldMapping::default()
+                }),
+                "_version".to_string() => MappingProperty::Field(FieldMapping {
+                    data_type: FieldType::Integer,
+                    is_indexed: false,
+                    is_stored: true,
+                    is_in_all: false,
+                    ..FieldMapping::default()
+                }),
+                "_source".to_string() => MappingProperty::Field(FieldMapping {
+                    data_type: FieldType::String,
+                    is_indexed: false,
+                    is_stored: true,
+                    is_in_all: false,
+                    ..FieldMapping::default()
+                }),
+                "_positions".to_string() => MappingProperty::Field(FieldMapping {
+                    data_type: FieldType::String,
+                    is_indexed: false,
+                    is_stored: true,
+                    is_in_all: false,
+                    ..FieldMapping::default()
                 })
             }
         });
@@ -82,10 +82,46 @@ impl Default for FieldSearchOptions {
 pub struct FieldValueError;
 
 
+/// Starts the extra terms that integer and date values are indexed with so they can be searched
+/// by range
+///
+/// These are indexed into their own field, but the store keeps one dictionary of terms for
+/// every field so the prefix keeps prefix searches for them from scanning unrelated terms.
+pub const SORTABLE_TERM_PREFIX: &'static str = "_sortable:";
+
+
+/// Names the field that the sortable terms of an integer or date field are indexed into
+///
+/// Keeping these apart from the field's own terms means they don't change its statistics.
+pub fn sortable_field_name(field_name: &str) -> String {
+    format!("{}{}", SORTABLE_TERM_PREFIX, field_name)
+}
+
+
+/// Maps a value to an unsigned number with the same ordering
+pub fn sortable_key(value: i64) -> u64 {
+    (value as u64) ^ (1 << 63)
+}
+
+
+/// Builds the sortable term of an integer or date value
+///
+/// The key is written as fixed width hexadecimal, so these terms sort in the same order as
+/// their values and every range of values can be selected by a bounded number of prefixes.
+pub fn sortable_term(value: i64) -> Term {
+    Term::from_string(&format!("{}{:016x}", SORTABLE_TERM_PREFIX, sortable_key(value)))
+}
+
+
 #[derive(Debug, PartialEq)]
 pub struct FieldMapping {
     pub data_type: FieldType,
     pub index_ref: Option<FieldRef>,
+
+    /// The field that sortable terms are indexed into, None if the field has none or it was
+    /// mapped before they were added
+    pub sortable_ref: Option<FieldRef>,
+
     pub is_indexed: bool,
     pub is_stored: bool,
     pub is_in_all: bool,
@@ -100,6 +136,7 @@ impl Default for FieldMapping {
         FieldMapping {
             data_type: FieldType::default(),
             index_ref: None,
+            sortable_ref: None,
             is_indexed: true,
             is_stored: false,
             is_in_all: true,
@@ -158,6 +195,11 @@ impl FieldMapping {
         }
     }
 
+    /// Checks if values of the field are also indexed as sortable terms for range queries
+    pub fn has_sortable_terms(&self) -> bool {
+        self.is_indexed && (self.data_type == FieldType::Integer || self.data_type == FieldType::Date)
+    }
+
     pub fn get_search_options(&self) -> FieldSearchOptions {
         FieldSearchOptions {
             analyzer: self.search_analyzer().cloned(),
@@ -265,6 +307,27 @@ impl FieldMapping {
         }
     }
 
+    /// Builds the sortable term of an integer or date value, for the field named by
+    /// `sortable_field_name`
+    ///
+    /// Values of other types have no sortable terms.
+    pub fn process_value_for_sortable(&self, value: &serde_json::Value) -> Result<Option<TermVector>, FieldValueError> {
+        let sortable_value = match (self.data_type, value) {
+            (_, &serde_json::Value::Null) => return Ok(None),
+            (FieldType::Integer, &serde_json::Value::Number(ref num)) => num.as_i64().ok_or(FieldValueError)?,
+            (FieldType::Date, &serde_json::Value::String(ref string)) => {
+                let date_parsed = string.parse::<DateTime<Utc>>().map_err(|_| FieldValueError)?;
+
+                // Dates are searched by range in microseconds, like their terms are encoded
+                date_parsed.timestamp() * 1000000 + date_parsed.timestamp_subsec_micros() as i64
+            }
+            (FieldType::Integer, _) | (FieldType::Date, _) => return Err(FieldValueError),
+            _ => return Ok(None),
+        };
+
+        Ok(Some(vec![Token{term: sortable_term(sortable_value), position: 1}].into()))
+    }
+
     pub fn process_value_for_store(&self, value: &serde_json::Value) -> Result<Option<FieldValue>, FieldValueError> {
         if *value == serde_json::Value::Null {
             return Ok(None);
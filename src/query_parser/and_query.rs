@@ -4,7 +4,7 @@ use serde_json::Value as Json;
 use kite::Query;
 use kite::schema::Schema;
 
-use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, parse as parse_query};
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, QueryBuildError, parse as parse_query};
 
 
 #[derive(Debug)]
@@ -14,14 +14,14 @@ struct AndQueryBuilder {
 
 
 impl QueryBuilder for AndQueryBuilder {
-    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Result<Query, QueryBuildError> {
         let mut queries = Vec::new();
 
         for query in self.queries.iter() {
-            queries.push(query.build(context, schema));
+            queries.push(query.build(context, schema)?);
         }
 
-        Query::Conjunction { queries: queries }
+        Ok(Query::Conjunction { queries: queries })
     }
 }
 
@@ -30,8 +30,8 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     let filters = json.as_array().ok_or(QueryParseError::ExpectedArray)?;
 
     let mut queries = Vec::new();
-    for filter in filters.iter() {
-        queries.push(parse_query(filter)?);
+    for (index, filter) in filters.iter().enumerate() {
+        queries.push(parse_query(filter).map_err(|error| error.in_item(index))?);
     }
 
     Ok(Box::new(AndQueryBuilder {
@@ -69,7 +69,7 @@ mod tests {
                 }
             }
         ]
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::Conjunction {
             queries: vec![
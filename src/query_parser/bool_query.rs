@@ -0,0 +1,563 @@
+//! Parses "bool" queries
+//!
+//! The store can't count how many clauses of a query match a document, so when
+//! "minimum_should_match" requires some but not all of the should clauses, matching documents
+//! are found with a disjunction of every combination of that many clauses. This is limited to
+//! MAX_COMBINATION_CLAUSES clauses in total.
+
+use serde_json::Value as Json;
+use kite::Query;
+use kite::schema::Schema;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, QueryBuildError, parse as parse_query};
+use query_parser::utils::{parse_float, MinimumShouldMatch, parse_minimum_should_match};
+
+
+/// The most clauses that may be built to find the documents that match some of the should clauses
+pub const MAX_COMBINATION_CLAUSES: usize = 1024;
+
+
+#[derive(Debug)]
+struct BoolQueryBuilder {
+    must: Vec<Box<QueryBuilder>>,
+    must_not: Vec<Box<QueryBuilder>>,
+    should: Vec<Box<QueryBuilder>>,
+    filter: Vec<Box<QueryBuilder>>,
+    minimum_should_match: Option<MinimumShouldMatch>,
+    boost: f32,
+}
+
+
+fn build_queries(builders: &[Box<QueryBuilder>], context: &QueryBuildContext, schema: &Schema) -> Result<Vec<Query>, QueryBuildError> {
+    builders.iter().map(|builder| builder.build(context, schema)).collect()
+}
+
+
+fn conjunction(mut queries: Vec<Query>) -> Query {
+    if queries.len() == 1 {
+        queries.pop().unwrap()
+    } else {
+        Query::Conjunction { queries: queries }
+    }
+}
+
+
+fn disjunction(mut queries: Vec<Query>) -> Query {
+    if queries.len() == 1 {
+        queries.pop().unwrap()
+    } else {
+        Query::Disjunction { queries: queries }
+    }
+}
+
+
+/// Builds a conjunction of each combination of "minimum" should clauses
+///
+/// Documents that match at least that many of the clauses match one of the conjunctions
+fn build_combinations(builders: &[Box<QueryBuilder>], minimum: usize, context: &QueryBuildContext, schema: &Schema) -> Result<Vec<Query>, QueryBuildError> {
+    // Check the number of clauses before building them as this grows quickly with the number
+    // of should clauses
+    let mut num_combinations = 1usize;
+    for i in 0..minimum {
+        num_combinations = match num_combinations.checked_mul(builders.len() - i) {
+            Some(num_combinations) => num_combinations / (i + 1),
+            None => return Err(QueryBuildError::TooManyClauses(MAX_COMBINATION_CLAUSES)),
+        };
+    }
+
+    match num_combinations.checked_mul(minimum) {
+        Some(num_clauses) if num_clauses <= MAX_COMBINATION_CLAUSES => {}
+        _ => return Err(QueryBuildError::TooManyClauses(MAX_COMBINATION_CLAUSES)),
+    }
+
+    // Step through the combinations in order, each is a list of increasing clause indices
+    let mut combination = (0..minimum).collect::<Vec<usize>>();
+    let mut combinations = Vec::with_capacity(num_combinations);
+    loop {
+        let queries = combination.iter().map(|i| builders[*i].build(context, schema)).collect::<Result<Vec<Query>, QueryBuildError>>()?;
+        combinations.push(conjunction(queries));
+
+        // Find the last index that can be moved along, then reset the ones after it
+        let mut i = minimum;
+        loop {
+            if i == 0 {
+                return Ok(combinations);
+            }
+
+            i -= 1;
+            if combination[i] < builders.len() - minimum + i {
+                break;
+            }
+        }
+
+        combination[i] += 1;
+        for j in i + 1..minimum {
+            combination[j] = combination[j - 1] + 1;
+        }
+    }
+}
+
+
+/// Works out how many of the should clauses must match
+///
+/// Should clauses are optional when there are other clauses that must match
+fn resolve_minimum_should_match(minimum_should_match: Option<&MinimumShouldMatch>, num_should: usize, has_required_clauses: bool) -> usize {
+    match minimum_should_match {
+        Some(minimum_should_match) => minimum_should_match.resolve(num_should),
+        None => {
+            if !has_required_clauses && num_should > 0 { 1 } else { 0 }
+        }
+    }
+}
+
+
+impl QueryBuilder for BoolQueryBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Result<Query, QueryBuildError> {
+        let filter_context = context.clone().no_score();
+        let minimum_should_match = resolve_minimum_should_match(self.minimum_should_match.as_ref(), self.should.len(), !self.must.is_empty() || !self.filter.is_empty());
+
+        if minimum_should_match > self.should.len() {
+            return Ok(Query::None);
+        }
+
+        // Build the part of the query that is scored
+        // Should clauses that aren't required are joined with a query that matches everything
+        // without scoring, so they only add to the score of documents that match the must clauses
+        let mut queries = build_queries(&self.must, context, schema)?;
+        let mut should_queries = build_queries(&self.should, context, schema)?;
+        if !should_queries.is_empty() {
+            if minimum_should_match == should_queries.len() {
+                queries.extend(should_queries);
+            } else if minimum_should_match == 1 {
+                queries.push(disjunction(should_queries));
+            } else if minimum_should_match == 0 {
+                should_queries.push(Query::All { score: 0.0f32 });
+                queries.push(disjunction(should_queries));
+            } else {
+                // The combinations only select the documents, each matching clause is scored once
+                let combinations = build_combinations(&self.should, minimum_should_match, &filter_context, schema)?;
+                queries.push(disjunction(should_queries).filter(disjunction(combinations)));
+            }
+        }
+
+        let mut query = if queries.is_empty() { Query::all() } else { conjunction(queries) };
+
+        if !self.filter.is_empty() {
+            query = query.filter(conjunction(build_queries(&self.filter, &filter_context, schema)?));
+        }
+
+        if !self.must_not.is_empty() {
+            query = query.exclude(disjunction(build_queries(&self.must_not, &filter_context, schema)?));
+        }
+
+        // Add boost
+        Ok(query.boost(self.boost))
+    }
+}
+
+
+/// Parses the clauses of a bool query, these may be a single query or an array of queries
+fn parse_clauses(json: &Json) -> Result<Vec<Box<QueryBuilder>>, QueryParseError> {
+    match *json {
+        Json::Array(ref array) => {
+            let mut clauses = Vec::new();
+            for (index, item) in array.iter().enumerate() {
+                clauses.push(parse_query(item).map_err(|error| error.in_item(index))?);
+            }
+
+            Ok(clauses)
+        }
+        _ => Ok(vec![parse_query(json)?]),
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut must = Vec::new();
+    let mut must_not = Vec::new();
+    let mut should = Vec::new();
+    let mut filter = Vec::new();
+    let mut minimum_should_match = None;
+    let mut boost = 1.0f32;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "must" => {
+                must = parse_clauses(value).map_err(|error| error.in_key("must"))?;
+            }
+            "must_not" => {
+                must_not = parse_clauses(value).map_err(|error| error.in_key("must_not"))?;
+            }
+            "should" => {
+                should = parse_clauses(value).map_err(|error| error.in_key("should"))?;
+            }
+            "filter" => {
+                filter = parse_clauses(value).map_err(|error| error.in_key("filter"))?;
+            }
+            "minimum_should_match" => {
+                minimum_should_match = Some(parse_minimum_should_match(value).map_err(|error| error.in_key("minimum_should_match"))?);
+            }
+            "boost" => {
+                boost = parse_float(value).map_err(|error| error.in_key("boost"))?;
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(BoolQueryBuilder {
+        must: must,
+        must_not: must_not,
+        should: should,
+        filter: filter,
+        minimum_should_match: minimum_should_match,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use kite::{Term, Query, TermScorer};
+    use kite::schema::{Schema, FieldType, FIELD_INDEXED};
+
+    use query_parser::{QueryBuildContext, QueryParseError, QueryBuildError};
+    use query_parser::utils::MinimumShouldMatch;
+
+    use super::{parse, MAX_COMBINATION_CLAUSES};
+
+    #[test]
+    fn test_bool_query() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "must": {
+                "term": {
+                    "test": "foo"
+                }
+            },
+            "filter": [
+                {
+                    "term": {
+                        "test": "bar"
+                    }
+                }
+            ],
+            "must_not": {
+                "term": {
+                    "test": "baz"
+                }
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
+
+        assert_eq!(query, Ok(Query::Exclude {
+            query: Box::new(Query::Filter {
+                query: Box::new(Query::Term {
+                    field: test_field,
+                    term: Term::from_string("foo"),
+                    scorer: TermScorer::default(),
+                }),
+                filter: Box::new(Query::Term {
+                    field: test_field,
+                    term: Term::from_string("bar"),
+                    scorer: TermScorer::default(),
+                }),
+            }),
+            exclude: Box::new(Query::Term {
+                field: test_field,
+                term: Term::from_string("baz"),
+                scorer: TermScorer::default(),
+            }),
+        }))
+    }
+
+    #[test]
+    fn test_should_only() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "should": [
+                {
+                    "term": {
+                        "test": "foo"
+                    }
+                },
+                {
+                    "term": {
+                        "test": "bar"
+                    }
+                }
+            ]
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
+
+        assert_eq!(query, Ok(Query::Disjunction {
+            queries: vec![
+                Query::Term {
+                    field: test_field,
+                    term: Term::from_string("foo"),
+                    scorer: TermScorer::default(),
+                },
+                Query::Term {
+                    field: test_field,
+                    term: Term::from_string("bar"),
+                    scorer: TermScorer::default(),
+                },
+            ],
+        }))
+    }
+
+    #[test]
+    fn test_should_is_optional_with_must() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "must": {
+                "term": {
+                    "test": "foo"
+                }
+            },
+            "should": {
+                "term": {
+                    "test": "bar"
+                }
+            },
+            "boost": 2.0
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
+
+        assert_eq!(query, Ok(Query::Conjunction {
+            queries: vec![
+                Query::Term {
+                    field: test_field,
+                    term: Term::from_string("foo"),
+                    scorer: TermScorer::default_with_boost(2.0f32),
+                },
+                Query::Disjunction {
+                    queries: vec![
+                        Query::Term {
+                            field: test_field,
+                            term: Term::from_string("bar"),
+                            scorer: TermScorer::default_with_boost(2.0f32),
+                        },
+                        Query::All {
+                            score: 0.0f32,
+                        },
+                    ],
+                },
+            ],
+        }))
+    }
+
+    #[test]
+    fn test_minimum_should_match() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "should": [
+                {
+                    "term": {
+                        "test": "foo"
+                    }
+                },
+                {
+                    "term": {
+                        "test": "bar"
+                    }
+                }
+            ],
+            "minimum_should_match": "100%"
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
+
+        assert_eq!(query, Ok(Query::Conjunction {
+            queries: vec![
+                Query::Term {
+                    field: test_field,
+                    term: Term::from_string("foo"),
+                    scorer: TermScorer::default(),
+                },
+                Query::Term {
+                    field: test_field,
+                    term: Term::from_string("bar"),
+                    scorer: TermScorer::default(),
+                },
+            ],
+        }))
+    }
+
+    #[test]
+    fn test_minimum_should_match_with_must() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "must": {
+                "term": {
+                    "test": "foo"
+                }
+            },
+            "should": [
+                {
+                    "term": {
+                        "test": "bar"
+                    }
+                },
+                {
+                    "term": {
+                        "test": "baz"
+                    }
+                }
+            ],
+            "minimum_should_match": 1
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
+
+        assert_eq!(query, Ok(Query::Conjunction {
+            queries: vec![
+                Query::Term {
+                    field: test_field,
+                    term: Term::from_string("foo"),
+                    scorer: TermScorer::default(),
+                },
+                Query::Disjunction {
+                    queries: vec![
+                        Query::Term {
+                            field: test_field,
+                            term: Term::from_string("bar"),
+                            scorer: TermScorer::default(),
+                        },
+                        Query::Term {
+                            field: test_field,
+                            term: Term::from_string("baz"),
+                            scorer: TermScorer::default(),
+                        },
+                    ],
+                },
+            ],
+        }))
+    }
+
+    #[test]
+    fn test_minimum_should_match_too_high() {
+        let mut schema = Schema::new();
+        schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "should": {
+                "term": {
+                    "test": "foo"
+                }
+            },
+            "minimum_should_match": 2
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
+
+        assert_eq!(query, Ok(Query::None))
+    }
+
+    #[test]
+    fn test_resolve_minimum_should_match() {
+        assert_eq!(MinimumShouldMatch::Absolute(2).resolve(3), 2);
+        assert_eq!(MinimumShouldMatch::Absolute(-1).resolve(3), 2);
+        assert_eq!(MinimumShouldMatch::Absolute(-5).resolve(3), 0);
+        assert_eq!(MinimumShouldMatch::Percentage(75.0).resolve(3), 2);
+        assert_eq!(MinimumShouldMatch::Percentage(-25.0).resolve(3), 3);
+    }
+
+    #[test]
+    fn test_minimum_should_match_some_clauses() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "should": [
+                {"term": {"test": "foo"}},
+                {"term": {"test": "bar"}},
+                {"term": {"test": "baz"}}
+            ],
+            "minimum_should_match": 2
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
+
+        let term = |value: &str| {
+            Query::Term {
+                field: test_field,
+                term: Term::from_string(value),
+                scorer: TermScorer::default(),
+            }
+        };
+
+        assert_eq!(query, Ok(Query::Filter {
+            query: Box::new(Query::Disjunction {
+                queries: vec![term("foo"), term("bar"), term("baz")],
+            }),
+            filter: Box::new(Query::Disjunction {
+                queries: vec![
+                    Query::Conjunction { queries: vec![term("foo"), term("bar")] },
+                    Query::Conjunction { queries: vec![term("foo"), term("baz")] },
+                    Query::Conjunction { queries: vec![term("bar"), term("baz")] },
+                ],
+            }),
+        }))
+    }
+
+    #[test]
+    fn test_gives_error_for_too_many_combinations() {
+        let mut schema = Schema::new();
+        schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        // Half of 20 clauses has 184756 combinations
+        let should = (0..20).map(|i| json!({"term": {"test": i.to_string()}})).collect::<Vec<_>>();
+        let query = parse(&json!({
+            "should": should,
+            "minimum_should_match": 10
+        })).unwrap().build(&QueryBuildContext::new(), &schema);
+
+        assert_eq!(query.err(), Some(QueryBuildError::TooManyClauses(MAX_COMBINATION_CLAUSES)));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_clause() {
+        let query = parse(&json!({
+            "must": [
+                {
+                    "term": {
+                        "test": "foo"
+                    }
+                },
+                "bar"
+            ]
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedObject.in_item(1).in_key("must")));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_minimum_should_match() {
+        let query = parse(&json!({
+            "minimum_should_match": "most"
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue.in_key("minimum_should_match")));
+    }
+
+    #[test]
+    fn test_gives_error_for_unexpected_key() {
+        let query = parse(&json!({
+            "foo": "bar"
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("foo".to_string())));
+    }
+
+    #[test]
+    fn test_gives_error_for_incorrect_type() {
+        // String
+        let query = parse(&json!("hello"));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedObject));
+
+        // Array
+        let query = parse(&json!(["hello"]));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedObject));
+    }
+}
@@ -4,7 +4,7 @@ use serde_json::Value as Json;
 use kite::Query;
 use kite::schema::Schema;
 
-use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, parse as parse_query};
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, QueryBuildError, parse as parse_query};
 use query_parser::utils::parse_float;
 
 #[derive(Debug)]
@@ -14,11 +14,11 @@ struct ConstantScoreQueryBuilder {
 }
 
 impl QueryBuilder for ConstantScoreQueryBuilder {
-    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
-        Query::Filter {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Result<Query, QueryBuildError> {
+        Ok(Query::Filter {
             query: Box::new(Query::All{ score: self.score }),
-            filter: Box::new(self.filter.build(&context.clone().no_score(), schema)),
-        }
+            filter: Box::new(self.filter.build(&context.clone().no_score(), schema)?),
+        })
     }
 }
 
@@ -26,12 +26,12 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
 
     let filter = match object.get("filter") {
-        Some(inner) => parse_query(inner)?,
+        Some(inner) => parse_query(inner).map_err(|error| error.in_key("filter"))?,
         None => return Err(QueryParseError::ExpectedKey("filter")),
     };
 
     let boost = match object.get("boost") {
-        Some(inner) => parse_float(inner)?,
+        Some(inner) => parse_float(inner).map_err(|error| error.in_key("boost"))?,
         None => return Err(QueryParseError::ExpectedKey("boost")),
     };
 
@@ -70,7 +70,7 @@ mod tests {
                 },
             },
             "boost": 2.0
-        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::Filter {
             query: Box::new(Query::All { score: 2.0 }),
@@ -89,7 +89,7 @@ mod tests {
 
         let query = parse(&json!({
             "boost": 2.0
-        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Err(QueryParseError::ExpectedKey("filter")));
     }
@@ -105,7 +105,7 @@ mod tests {
                     "test": "foo"
                 },
             }
-        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Err(QueryParseError::ExpectedKey("boost")));
     }
@@ -123,7 +123,7 @@ mod tests {
             },
             "boost": 2.0,
             "foo": "bar"
-        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Err(QueryParseError::UnrecognisedKey("foo".to_string())));
     }
@@ -136,9 +136,9 @@ mod tests {
         let query = parse(&json!({
             "filter": "foo",
             "boost": 2.0
-        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
-       assert_eq!(query, Err(QueryParseError::ExpectedObject));
+       assert_eq!(query, Err(QueryParseError::ExpectedObject.in_key("filter")));
     }
 
     #[test]
@@ -4,7 +4,7 @@ use serde_json::Value as Json;
 use kite::Query;
 use kite::schema::Schema;
 
-use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, parse as parse_query};
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, QueryBuildError, parse as parse_query};
 
 
 #[derive(Debug)]
@@ -15,16 +15,16 @@ struct FilteredQueryBuilder {
 
 
 impl QueryBuilder for FilteredQueryBuilder {
-    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Result<Query, QueryBuildError> {
         let query = match self.query {
-            Some(ref query) => query.build(context, schema),
+            Some(ref query) => query.build(context, schema)?,
             None => Query::all(),
         };
 
-        Query::Filter {
+        Ok(Query::Filter {
             query: Box::new(query),
-            filter: Box::new(self.filter.build(&context.clone().no_score(), schema)),
-        }
+            filter: Box::new(self.filter.build(&context.clone().no_score(), schema)?),
+        })
     }
 }
 
@@ -40,11 +40,11 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     for (key, value) in object.iter() {
         match key.as_ref() {
             "query" => {
-                query = Some(parse_query(value)?);
+                query = Some(parse_query(value).map_err(|error| error.in_key("query"))?);
             }
             "filter" => {
                 has_filter_key = true;
-                filter = Some(parse_query(value)?);
+                filter = Some(parse_query(value).map_err(|error| error.in_key("filter"))?);
             }
             _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
         }
@@ -90,7 +90,7 @@ mod tests {
                 }
             }
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::Filter {
             query: Box::new(Query::Term {
@@ -119,7 +119,7 @@ mod tests {
                 }
             }
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::Filter {
             query: Box::new(Query::all()),
@@ -177,7 +177,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedObject));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedObject.in_key("query")));
     }
 
     #[test]
@@ -208,7 +208,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedObject));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedObject.in_key("filter")));
     }
 
     #[test]
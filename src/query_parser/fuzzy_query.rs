@@ -0,0 +1,414 @@
+//! Parses "fuzzy" queries
+//!
+//! Terms are expanded into the terms of the field in the index's term dictionary that start with
+//! the first "prefix_length" characters of the term. With the default "prefix_length" of 0, every
+//! term of the field is checked. Without a term dictionary in the build context, only the exact
+//! term is searched for.
+
+use std::cmp;
+use std::collections::BTreeSet;
+
+use serde_json::Value as Json;
+use kite::{Term, Query, TermScorer};
+use kite::schema::{Schema, FieldRef};
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, QueryBuildError};
+use query_parser::utils::{parse_string, parse_float, parse_positive_integer, parse_boolean, parse_max_expansions, Fuzziness, parse_fuzziness};
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuzzyOptions {
+    pub fuzziness: Fuzziness,
+    pub prefix_length: usize,
+    pub max_expansions: usize,
+    pub transpositions: bool,
+}
+
+
+impl Default for FuzzyOptions {
+    fn default() -> FuzzyOptions {
+        FuzzyOptions {
+            fuzziness: Fuzziness::Auto(3, 6),
+            prefix_length: 0,
+            max_expansions: 50,
+            transpositions: true,
+        }
+    }
+}
+
+
+/// Counts the insertions, deletions and substitutions of characters needed to turn one string
+/// into the other. If "transpositions" is set, swapping two adjacent characters counts as one edit
+pub fn edit_distance(a: &[char], b: &[char], transpositions: bool) -> u32 {
+    // distances[i][j] is the number of edits between the first i characters of a and the
+    // first j characters of b
+    let mut distances = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i as u32;
+    }
+    for (j, distance) in distances[0].iter_mut().enumerate() {
+        *distance = j as u32;
+    }
+
+    for i in 1..a.len() + 1 {
+        for j in 1..b.len() + 1 {
+            let substitution_cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            let mut distance = cmp::min(
+                cmp::min(distances[i - 1][j] + 1, distances[i][j - 1] + 1),
+                distances[i - 1][j - 1] + substitution_cost
+            );
+
+            if transpositions && i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = cmp::min(distance, distances[i - 2][j - 2] + 1);
+            }
+
+            distances[i][j] = distance;
+        }
+    }
+
+    distances[a.len()][b.len()]
+}
+
+
+/// Builds a query that matches the term and the terms in the field that are within the allowed
+/// number of edits of it
+///
+/// The closest terms are searched for first, up to "max_expansions" terms
+pub fn build_fuzzy_term_query(context: &QueryBuildContext, field_ref: FieldRef, term: Term, options: &FuzzyOptions) -> Result<Query, QueryBuildError> {
+    let term_query = |term: Term| {
+        Query::Term {
+            field: field_ref,
+            term: term,
+            scorer: TermScorer::default(),
+        }
+    };
+
+    let term_chars = String::from_utf8_lossy(term.as_bytes()).chars().collect::<Vec<char>>();
+    let max_edits = options.fuzziness.max_edits(term_chars.len());
+    if max_edits == 0 {
+        return Ok(term_query(term));
+    }
+
+    let term_dictionary = match context.get_term_dictionary()? {
+        Some(term_dictionary) => term_dictionary,
+        None => return Ok(term_query(term)),
+    };
+
+    // Find the terms that are close enough, ordered by how many edits away they are
+    // Expanded terms must start with the same "prefix_length" characters as the term
+    let prefix = term_chars.iter().take(options.prefix_length).cloned().collect::<String>();
+    let mut expansions = BTreeSet::new();
+    term_dictionary.scan_prefix(field_ref, prefix.as_bytes(), |candidate_term| {
+        let candidate_chars = String::from_utf8_lossy(candidate_term.as_bytes()).chars().collect::<Vec<char>>();
+        let length_difference = (candidate_chars.len() as i64 - term_chars.len() as i64).abs();
+        if length_difference > max_edits as i64 {
+            return true;
+        }
+
+        let distance = edit_distance(&term_chars, &candidate_chars, options.transpositions);
+        if distance <= max_edits {
+            expansions.insert((distance, candidate_term.clone()));
+
+            // Only the closest "max_expansions" terms are kept
+            if expansions.len() > options.max_expansions {
+                let furthest = expansions.iter().next_back().cloned().unwrap();
+                expansions.remove(&furthest);
+            }
+        }
+
+        true
+    });
+
+    let mut expansion_queries = expansions.into_iter().map(|(_, expansion)| term_query(expansion)).collect::<Vec<Query>>();
+    Ok(match expansion_queries.len() {
+        0 => Query::None,
+        1 => expansion_queries.pop().unwrap(),
+        _ => Query::Disjunction { queries: expansion_queries },
+    })
+}
+
+
+#[derive(Debug)]
+struct FuzzyQueryBuilder {
+    field: String,
+    term: Term,
+    options: FuzzyOptions,
+    boost: f32,
+}
+
+
+impl QueryBuilder for FuzzyQueryBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Result<Query, QueryBuildError> {
+        let field_ref = match schema.get_field_by_name(&self.field) {
+            Some(field_ref) => field_ref,
+            None => return Ok(Query::None),
+        };
+
+        let query = build_fuzzy_term_query(context, field_ref, self.term.clone(), &self.options)?;
+
+        // Add boost
+        Ok(query.boost(self.boost))
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let field_name = if object.len() == 1 {
+        object.keys().collect::<Vec<_>>()[0]
+    } else {
+        return Err(QueryParseError::ExpectedSingleKey)
+    };
+
+    // Get configuration
+    let mut value = String::new();
+    let mut options = FuzzyOptions::default();
+    let mut boost = 1.0f32;
+
+    match object.get(field_name).unwrap() {
+        s @ &Json::String(_) => value = parse_string(s).map_err(|error| error.in_key(field_name))?,
+        &Json::Object(ref inner_object) => {
+            let mut has_value_key = false;
+
+            for (key, val) in inner_object.iter() {
+                match key.as_ref() {
+                    "value" => {
+                        has_value_key = true;
+                        value = parse_string(val).map_err(|error| error.in_key("value").in_key(field_name))?;
+                    }
+                    "fuzziness" => {
+                        options.fuzziness = parse_fuzziness(val).map_err(|error| error.in_key("fuzziness").in_key(field_name))?;
+                    }
+                    "prefix_length" => {
+                        options.prefix_length = parse_positive_integer(val).map_err(|error| error.in_key("prefix_length").in_key(field_name))? as usize;
+                    }
+                    "max_expansions" => {
+                        options.max_expansions = parse_max_expansions(val).map_err(|error| error.in_key("max_expansions").in_key(field_name))?;
+                    }
+                    "transpositions" => {
+                        options.transpositions = parse_boolean(val).map_err(|error| error.in_key("transpositions").in_key(field_name))?;
+                    }
+                    "boost" => {
+                        boost = parse_float(val).map_err(|error| error.in_key("boost").in_key(field_name))?;
+                    }
+                    _ => return Err(QueryParseError::UnrecognisedKey(key.clone()).in_key(field_name))
+                }
+            }
+
+            if !has_value_key {
+                return Err(QueryParseError::ExpectedKey("value").in_key(field_name))
+            }
+        }
+        _ => return Err(QueryParseError::ExpectedObjectOrString.in_key(field_name)),
+    }
+
+    Ok(Box::new(FuzzyQueryBuilder {
+        field: field_name.clone(),
+        term: Term::from_string(&value),
+        options: options,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use kite::{Term, Token, Document, Query, TermScorer};
+    use kite::schema::{Schema, FieldType, FIELD_INDEXED};
+    use fnv::FnvHashMap;
+    use uuid::Uuid;
+
+    use index::term_dictionary::TermDictionary;
+    use query_parser::{QueryBuildContext, QueryParseError};
+    use query_parser::utils::{Fuzziness, parse_fuzziness};
+
+    use super::{parse, edit_distance};
+
+    fn distance(a: &str, b: &str, transpositions: bool) -> u32 {
+        edit_distance(&a.chars().collect::<Vec<char>>(), &b.chars().collect::<Vec<char>>(), transpositions)
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(distance("iphone", "iphone", true), 0);
+        assert_eq!(distance("iphone", "ipone", true), 1);
+        assert_eq!(distance("iphone", "iphones", true), 1);
+        assert_eq!(distance("iphone", "iphane", true), 1);
+        assert_eq!(distance("", "abc", true), 3);
+        assert_eq!(distance("café", "cafe", true), 1);
+    }
+
+    #[test]
+    fn test_edit_distance_transpositions() {
+        assert_eq!(distance("iphone", "ihpone", true), 1);
+        assert_eq!(distance("iphone", "ihpone", false), 2);
+    }
+
+    #[test]
+    fn test_parse_fuzziness() {
+        assert_eq!(parse_fuzziness(&json!("AUTO")), Ok(Fuzziness::Auto(3, 6)));
+        assert_eq!(parse_fuzziness(&json!("AUTO:2,5")), Ok(Fuzziness::Auto(2, 5)));
+        assert_eq!(parse_fuzziness(&json!(1)), Ok(Fuzziness::Edits(1)));
+        assert_eq!(parse_fuzziness(&json!("2")), Ok(Fuzziness::Edits(2)));
+        assert_eq!(parse_fuzziness(&json!(3)), Err(QueryParseError::InvalidValue));
+        assert_eq!(parse_fuzziness(&json!("AUTO:6,3")), Err(QueryParseError::InvalidValue));
+        assert_eq!(parse_fuzziness(&json!("lots")), Err(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_auto_fuzziness() {
+        let fuzziness = Fuzziness::Auto(3, 6);
+
+        assert_eq!(fuzziness.max_edits(2), 0);
+        assert_eq!(fuzziness.max_edits(3), 1);
+        assert_eq!(fuzziness.max_edits(5), 1);
+        assert_eq!(fuzziness.max_edits(6), 2);
+    }
+
+    #[test]
+    fn test_fuzzy_query() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        // Without a term dictionary, only the exact term is searched for
+        let query = parse(&json!({
+            "test": {
+                "value": "iphnoe",
+                "fuzziness": 2,
+                "boost": 2.0
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
+
+        assert_eq!(query, Ok(Query::Term {
+            field: test_field,
+            term: Term::from_string("iphnoe"),
+            scorer: TermScorer::default_with_boost(2.0f32),
+        }))
+    }
+
+    #[test]
+    fn test_expands_term() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let mut indexed_fields = FnvHashMap::default();
+        indexed_fields.insert(test_field, ["iphone", "iphones", "ipad", "phone"].iter().enumerate().map(|(index, word)| Token {term: Term::from_string(word), position: index as u32 + 1}).collect::<Vec<Token>>().into());
+
+        let path = env::temp_dir().join(format!("rusticsearch-test-terms-{}", Uuid::new_v4()));
+        let term_dictionary = TermDictionary::create(&path).unwrap();
+        term_dictionary.add_document(&Document {
+            key: "1".to_string(),
+            indexed_fields: indexed_fields,
+            stored_fields: FnvHashMap::default(),
+        }).unwrap();
+
+        let term_query = |word: &str| Query::Term {
+            field: test_field,
+            term: Term::from_string(word),
+            scorer: TermScorer::default_with_boost(2.0f32),
+        };
+
+        // The closest terms are kept, terms that are as close as each other are kept in term order
+        let query = parse(&json!({
+            "test": {
+                "value": "iphnoe",
+                "fuzziness": 2,
+                "max_expansions": 2,
+                "boost": 2.0
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new().set_term_dictionary(&term_dictionary), &schema).unwrap()));
+
+        assert_eq!(query, Ok(Query::Disjunction {
+            queries: vec![term_query("iphone"), term_query("iphones")],
+        }));
+
+        // Terms that don't start with the prefix aren't expanded to
+        let query = parse(&json!({
+            "test": {
+                "value": "hpone",
+                "fuzziness": 2,
+                "prefix_length": 1,
+                "boost": 2.0
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new().set_term_dictionary(&term_dictionary), &schema).unwrap()));
+
+        assert_eq!(query, Ok(Query::None));
+
+        let query = parse(&json!({
+            "test": {
+                "value": "hpone",
+                "fuzziness": 2,
+                "boost": 2.0
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new().set_term_dictionary(&term_dictionary), &schema).unwrap()));
+
+        assert_eq!(query, Ok(Query::Disjunction {
+            queries: vec![term_query("phone"), term_query("iphone")],
+        }));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_fuzziness() {
+        let query = parse(&json!({
+            "test": {
+                "value": "iphnoe",
+                "fuzziness": "lots"
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue.in_key("fuzziness").in_key("test")));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_prefix_length() {
+        let query = parse(&json!({
+            "test": {
+                "value": "iphnoe",
+                "prefix_length": -1
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedPositiveInteger.in_key("prefix_length").in_key("test")));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_max_expansions() {
+        let query = parse(&json!({
+            "test": {
+                "value": "iphnoe",
+                "max_expansions": 1025
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue.in_key("max_expansions").in_key("test")));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_transpositions() {
+        let query = parse(&json!({
+            "test": {
+                "value": "iphnoe",
+                "transpositions": "yes"
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedBoolean.in_key("transpositions").in_key("test")));
+    }
+
+    #[test]
+    fn test_gives_error_for_missing_value() {
+        let query = parse(&json!({
+            "test": {
+                "fuzziness": "AUTO"
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("value").in_key("test")));
+    }
+}
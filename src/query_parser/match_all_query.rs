@@ -4,7 +4,7 @@ use serde_json::Value as Json;
 use kite::Query;
 use kite::schema::Schema;
 
-use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, QueryBuildError};
 use query_parser::utils::parse_float;
 
 
@@ -15,8 +15,8 @@ struct MatchAllQueryBuilder {
 
 
 impl QueryBuilder for MatchAllQueryBuilder {
-    fn build(&self, _context: &QueryBuildContext, _schema: &Schema) -> Query {
-        Query::all().boost(self.boost)
+    fn build(&self, _context: &QueryBuildContext, _schema: &Schema) -> Result<Query, QueryBuildError> {
+        Ok(Query::all().boost(self.boost))
     }
 }
 
@@ -30,7 +30,7 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     for (key, value) in object.iter() {
         match &key[..] {
             "boost" => {
-                boost = parse_float(value)?;
+                boost = parse_float(value).map_err(|error| error.in_key("boost"))?;
             }
             _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
         }
@@ -60,7 +60,7 @@ mod tests {
         let query = parse(&serde_json::from_str("
         {
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::All {score: 1.0f32}))
     }
@@ -73,7 +73,7 @@ mod tests {
         {
             \"boost\": 2.0
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::All {score: 2.0f32}))
     }
@@ -86,7 +86,7 @@ mod tests {
         {
             \"boost\": 2
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::All {score: 2.0f32}))
     }
@@ -126,7 +126,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat.in_key("boost")));
 
         // Array
         let query = parse(&serde_json::from_str("
@@ -135,7 +135,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat.in_key("boost")));
 
         // Object
         let query = parse(&serde_json::from_str("
@@ -146,7 +146,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat.in_key("boost")));
     }
 
     #[test]
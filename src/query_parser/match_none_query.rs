@@ -4,7 +4,7 @@ use serde_json::Value as Json;
 use kite::Query;
 use kite::schema::Schema;
 
-use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, QueryBuildError};
 
 
 #[derive(Debug)]
@@ -12,8 +12,8 @@ struct MatchNoneQueryBuilder;
 
 
 impl QueryBuilder for MatchNoneQueryBuilder {
-    fn build(&self, _context: &QueryBuildContext, _schema: &Schema) -> Query {
-        Query::None
+    fn build(&self, _context: &QueryBuildContext, _schema: &Schema) -> Result<Query, QueryBuildError> {
+        Ok(Query::None)
     }
 }
 
@@ -50,7 +50,7 @@ mod tests {
         let query = parse(&serde_json::from_str("
         {
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::None))
     }
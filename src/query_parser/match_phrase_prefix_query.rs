@@ -0,0 +1,415 @@
+//! Parses "match_phrase_prefix" queries
+//!
+//! All terms but the last must match as a phrase and the last term is used as a prefix. The
+//! prefix is expanded into the first "max_expansions" terms (in term order) of the field that
+//! start with it, from the index's term dictionary. Without a term dictionary in the build
+//! context, the prefix matches any number of terms. Without an index reader, the positions of the
+//! terms aren't checked.
+
+use serde_json::Value as Json;
+use kite::{Term, Query, TermScorer};
+use kite::document::DocRef;
+use kite::schema::{Schema, FieldRef};
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, QueryBuildError};
+use query_parser::utils::{parse_string, parse_float, parse_slop, parse_max_expansions};
+use query_parser::prefix_query::PrefixQueryBuilder;
+use query_parser::match_phrase_query::{analyze_query, read_candidate_terms, documents_by_key, find_phrase_positions, matches_phrase_positions};
+
+
+#[derive(Debug)]
+struct MatchPhrasePrefixQueryBuilder {
+    field: String,
+    query: String,
+    slop: u32,
+    max_expansions: usize,
+    analyzer: Option<String>,
+    boost: f32,
+}
+
+
+fn conjunction(mut queries: Vec<Query>) -> Query {
+    if queries.len() == 1 {
+        queries.pop().unwrap()
+    } else {
+        Query::Conjunction { queries: queries }
+    }
+}
+
+
+/// Finds the first "max_expansions" terms of the field that start with the prefix
+///
+/// Returns None if there's no term dictionary in the build context.
+fn expand_prefix(context: &QueryBuildContext, field_ref: FieldRef, prefix: &[u8], max_expansions: usize) -> Result<Option<Vec<Term>>, QueryBuildError> {
+    let term_dictionary = match context.get_term_dictionary()? {
+        Some(term_dictionary) => term_dictionary,
+        None => return Ok(None),
+    };
+
+    let mut expansions = Vec::new();
+    term_dictionary.scan_prefix(field_ref, prefix, |term| {
+        if expansions.len() >= max_expansions {
+            return false;
+        }
+
+        expansions.push(term.clone());
+        true
+    });
+
+    Ok(Some(expansions))
+}
+
+
+impl QueryBuilder for MatchPhrasePrefixQueryBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Result<Query, QueryBuildError> {
+        let field_ref = match schema.get_field_by_name(&self.field) {
+            Some(field_ref) => field_ref,
+            None => return Ok(Query::None),
+        };
+
+        let tokens = analyze_query(context, &self.field, &self.query, self.analyzer.as_ref().map(|analyzer| analyzer.as_str()));
+        let (prefix_token, phrase) = match tokens.split_last() {
+            Some((prefix_token, phrase)) => (prefix_token, phrase),
+            None => return Ok(Query::None),
+        };
+
+        let term_query = |term: Term| {
+            Query::Term {
+                field: field_ref,
+                term: term,
+                scorer: TermScorer::default(),
+            }
+        };
+
+        let expansions = match expand_prefix(context, field_ref, prefix_token.term.as_bytes(), self.max_expansions)? {
+            Some(expansions) => expansions,
+            None => {
+                // Documents must contain every term of the phrase and a term that starts with the prefix
+                let prefix = String::from_utf8_lossy(prefix_token.term.as_bytes()).into_owned();
+                let mut queries = phrase.iter().map(|token| term_query(token.term.clone())).collect::<Vec<Query>>();
+                queries.push(PrefixQueryBuilder::new(self.field.clone(), prefix).build(context, schema)?);
+
+                return Ok(conjunction(queries).boost(self.boost));
+            }
+        };
+
+        if expansions.is_empty() {
+            return Ok(Query::None);
+        }
+
+        // Documents must contain every term of the phrase and one of the expansions
+        let phrase_query = || {
+            let mut queries = phrase.iter().map(|token| term_query(token.term.clone())).collect::<Vec<Query>>();
+            let mut expansion_queries = expansions.iter().cloned().map(&term_query).collect::<Vec<Query>>();
+            if expansion_queries.len() == 1 {
+                queries.push(expansion_queries.pop().unwrap());
+            } else {
+                queries.push(Query::Disjunction { queries: expansion_queries });
+            }
+
+            conjunction(queries)
+        };
+
+        // There are no positions to check when the query is just the prefix
+        if phrase.is_empty() {
+            return Ok(phrase_query().boost(self.boost));
+        }
+
+        let candidates = match read_candidate_terms(context, &self.field, &phrase_query())? {
+            Some(candidates) => candidates,
+            None => return Ok(phrase_query().boost(self.boost)),
+        };
+
+        // Find the documents that contain the phrase followed by any of the expansions
+        let doc_refs = candidates.iter().filter(|&&(_, ref term_vector)| {
+            let mut groups = match find_phrase_positions(phrase, term_vector) {
+                Some(groups) => groups,
+                None => return false,
+            };
+
+            let mut prefix_positions = expansions.iter().filter_map(|expansion| term_vector.get(expansion)).flat_map(|positions| positions.iter().cloned()).collect::<Vec<u32>>();
+            prefix_positions.sort();
+            prefix_positions.dedup();
+            groups.push((prefix_token.position, prefix_positions));
+
+            matches_phrase_positions(&groups, self.slop)
+        }).map(|&(doc_ref, _)| doc_ref).collect::<Vec<DocRef>>();
+
+        match documents_by_key(context, &doc_refs) {
+            Query::None => Ok(Query::None),
+            phrase_documents => Ok(phrase_query().boost(self.boost).filter(phrase_documents)),
+        }
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let field_name = if object.len() == 1 {
+        object.keys().collect::<Vec<_>>()[0]
+    } else {
+        return Err(QueryParseError::ExpectedSingleKey)
+    };
+
+    // Get configuration
+    let mut query = String::new();
+    let mut slop = 0;
+    let mut max_expansions = 50;
+    let mut analyzer = None;
+    let mut boost = 1.0f32;
+
+    match object.get(field_name).unwrap() {
+        s @ &Json::String(_) => query = parse_string(s).map_err(|error| error.in_key(field_name))?,
+        &Json::Object(ref inner_object) => {
+            let mut has_query_key = false;
+
+            for (key, value) in inner_object.iter() {
+                match key.as_ref() {
+                    "query" => {
+                        has_query_key = true;
+                        query = parse_string(value).map_err(|error| error.in_key("query").in_key(field_name))?;
+                    }
+                    "slop" => {
+                        slop = parse_slop(value).map_err(|error| error.in_key("slop").in_key(field_name))?;
+                    }
+                    "max_expansions" => {
+                        max_expansions = parse_max_expansions(value).map_err(|error| error.in_key("max_expansions").in_key(field_name))?;
+                    }
+                    "analyzer" => {
+                        analyzer = Some(parse_string(value).map_err(|error| error.in_key("analyzer").in_key(field_name))?);
+                    }
+                    "boost" => {
+                        boost = parse_float(value).map_err(|error| error.in_key("boost").in_key(field_name))?;
+                    }
+                    _ => return Err(QueryParseError::UnrecognisedKey(key.clone()).in_key(field_name))
+                }
+            }
+
+            if !has_query_key {
+                return Err(QueryParseError::ExpectedKey("query").in_key(field_name))
+            }
+        }
+        _ => return Err(QueryParseError::ExpectedObjectOrString.in_key(field_name)),
+    }
+
+    Ok(Box::new(MatchPhrasePrefixQueryBuilder {
+        field: field_name.clone(),
+        query: query,
+        slop: slop,
+        max_expansions: max_expansions,
+        analyzer: analyzer,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use kite::{Term, Token, Document, Query, MultiTermSelector, TermScorer};
+    use kite::schema::{Schema, FieldType, FieldRef, FIELD_INDEXED};
+    use fnv::FnvHashMap;
+    use uuid::Uuid;
+
+    use index::term_dictionary::TermDictionary;
+    use query_parser::{QueryBuildContext, QueryParseError, QueryBuildError};
+
+    use super::parse;
+
+    /// Runs the test with a term dictionary that has the given terms in the field
+    fn with_term_dictionary<F: FnOnce(&TermDictionary)>(field_ref: FieldRef, words: &[&str], test: F) {
+        let mut indexed_fields = FnvHashMap::default();
+        indexed_fields.insert(field_ref, words.iter().enumerate().map(|(index, word)| Token {term: Term::from_string(word), position: index as u32 + 1}).collect::<Vec<Token>>().into());
+
+        let path = env::temp_dir().join(format!("rusticsearch-test-terms-{}", Uuid::new_v4()));
+        let term_dictionary = TermDictionary::create(&path).unwrap();
+        term_dictionary.add_document(&Document {
+            key: "1".to_string(),
+            indexed_fields: indexed_fields,
+            stored_fields: FnvHashMap::default(),
+        }).unwrap();
+
+        test(&term_dictionary);
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn term_query(field_ref: FieldRef, word: &str, boost: f32) -> Query {
+        Query::Term {
+            field: field_ref,
+            term: Term::from_string(word),
+            scorer: TermScorer::default_with_boost(boost),
+        }
+    }
+
+    #[test]
+    fn test_match_phrase_prefix_query() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        // Without an index reader, the prefix isn't expanded and positions aren't checked
+        let query = parse(&json!({
+            "test": "quick brown f"
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
+
+        assert_eq!(query, Ok(Query::Conjunction {
+            queries: vec![
+                Query::Term {
+                    field: test_field,
+                    term: Term::from_string("quick"),
+                    scorer: TermScorer::default(),
+                },
+                Query::Term {
+                    field: test_field,
+                    term: Term::from_string("brown"),
+                    scorer: TermScorer::default(),
+                },
+                Query::MultiTerm {
+                    field: test_field,
+                    term_selector: MultiTermSelector::Prefix("f".to_string()),
+                    scorer: TermScorer::default(),
+                },
+            ],
+        }))
+    }
+
+    #[test]
+    fn test_single_term() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "test": {
+                "query": "qui",
+                "max_expansions": 10,
+                "boost": 2.0
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
+
+        assert_eq!(query, Ok(Query::MultiTerm {
+            field: test_field,
+            term_selector: MultiTermSelector::Prefix("qui".to_string()),
+            scorer: TermScorer::default_with_boost(2.0f32),
+        }))
+    }
+
+    #[test]
+    fn test_expands_prefix() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        with_term_dictionary(test_field, &["quick", "brown", "fun", "fox", "fast"], |term_dictionary| {
+            let query = parse(&json!({
+                "test": {
+                    "query": "quick brown f",
+                    "max_expansions": 2
+                }
+            })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new().set_term_dictionary(term_dictionary), &schema).unwrap()));
+
+            assert_eq!(query, Ok(Query::Conjunction {
+                queries: vec![
+                    term_query(test_field, "quick", 1.0f32),
+                    term_query(test_field, "brown", 1.0f32),
+                    Query::Disjunction {
+                        queries: vec![
+                            term_query(test_field, "fast", 1.0f32),
+                            term_query(test_field, "fox", 1.0f32),
+                        ],
+                    },
+                ],
+            }));
+        });
+    }
+
+    #[test]
+    fn test_expands_single_term() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        with_term_dictionary(test_field, &["quiet", "quick", "quilt", "quit"], |term_dictionary| {
+            let query = parse(&json!({
+                "test": {
+                    "query": "qui",
+                    "max_expansions": 3,
+                    "boost": 2.0
+                }
+            })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new().set_term_dictionary(term_dictionary), &schema).unwrap()));
+
+            assert_eq!(query, Ok(Query::Disjunction {
+                queries: vec![
+                    term_query(test_field, "quick", 2.0f32),
+                    term_query(test_field, "quiet", 2.0f32),
+                    term_query(test_field, "quilt", 2.0f32),
+                ],
+            }));
+
+            // Prefixes that don't start any terms match nothing
+            let query = parse(&json!({
+                "test": "brow"
+            })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new().set_term_dictionary(term_dictionary), &schema).unwrap()));
+
+            assert_eq!(query, Ok(Query::None));
+        });
+    }
+
+    #[test]
+    fn test_gives_error_for_incomplete_term_dictionary() {
+        let mut schema = Schema::new();
+        schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        // Indices created before terms were listed have no dictionary file
+        let term_dictionary = TermDictionary::open(env::temp_dir().join(format!("rusticsearch-test-terms-{}", Uuid::new_v4()))).unwrap();
+        let query = parse(&json!({
+            "test": "qui"
+        })).unwrap().build(&QueryBuildContext::new().set_term_dictionary(&term_dictionary), &schema);
+
+        assert_eq!(query, Err(QueryBuildError::IncompleteTermDictionary));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_max_expansions() {
+        let query = parse(&json!({
+            "test": {
+                "query": "quick brown f",
+                "max_expansions": "lots"
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedPositiveInteger.in_key("max_expansions").in_key("test")));
+
+        let query = parse(&json!({
+            "test": {
+                "query": "quick brown f",
+                "max_expansions": 1025
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue.in_key("max_expansions").in_key("test")));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_slop() {
+        let query = parse(&json!({
+            "test": {
+                "query": "quick brown f",
+                "slop": 4294967296u64
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue.in_key("slop").in_key("test")));
+    }
+
+    #[test]
+    fn test_gives_error_for_extra_inner_key() {
+        let query = parse(&json!({
+            "test": {
+                "query": "quick brown f",
+                "foo": "bar"
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("foo".to_string()).in_key("test")));
+    }
+}
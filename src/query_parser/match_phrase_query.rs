@@ -0,0 +1,467 @@
+//! Parses "match_phrase" queries
+//!
+//! The positions of the terms are checked with `index::lookup::read_field_terms`, which requires
+//! an index reader in the build context. Without one, the query matches documents that contain
+//! all of the terms in any order.
+//!
+//! The documents that contain every term are all checked, so phrases of common terms are slower
+//! to build but still find every match.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::btree_map::Entry;
+
+use serde_json::Value as Json;
+use kite::{Term, Token, Query, TermScorer};
+use kite::term_vector::TermVector;
+use kite::document::DocRef;
+use kite::schema::Schema;
+
+use mapping::FieldSearchOptions;
+use index::lookup::{can_read_field_terms, read_field_terms, read_stored_string};
+use collectors::doc_ids::DocIdCollector;
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, QueryBuildError};
+use query_parser::utils::{parse_string, parse_float, parse_slop};
+
+
+#[derive(Debug)]
+struct MatchPhraseQueryBuilder {
+    field: String,
+    query: String,
+    slop: u32,
+    analyzer: Option<String>,
+    boost: f32,
+}
+
+
+/// Looks for a position for each group of tokens that no other group is using, moving the groups
+/// that are in the way to their other positions if they have any
+fn assign_position(group: usize, candidates: &[Vec<u32>], assigned: &mut HashMap<u32, usize>, visited: &mut HashSet<u32>) -> bool {
+    for position in candidates[group].iter() {
+        if !visited.insert(*position) {
+            continue;
+        }
+
+        let is_free = match assigned.get(position).cloned() {
+            Some(other_group) => assign_position(other_group, candidates, assigned, visited),
+            None => true,
+        };
+
+        if is_free {
+            assigned.insert(*position, group);
+            return true;
+        }
+    }
+
+    false
+}
+
+
+/// Finds the positions in the terms of a field that each position in the phrase could be at
+///
+/// Tokens at the same position in the phrase (such as synonyms) must be at the same position in
+/// the document. Returns None if any of the tokens are missing.
+pub fn find_phrase_positions(phrase: &[Token], term_vector: &TermVector) -> Option<Vec<(u32, Vec<u32>)>> {
+    let mut groups: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+    for token in phrase.iter() {
+        let term_positions = match term_vector.get(&token.term) {
+            Some(term_positions) => term_positions,
+            None => return None,
+        };
+
+        match groups.entry(token.position) {
+            Entry::Vacant(entry) => {
+                entry.insert(term_positions.clone());
+            }
+            Entry::Occupied(mut entry) => {
+                entry.get_mut().retain(|position| term_positions.contains(position));
+            }
+        }
+    }
+
+    Some(groups.into_iter().collect())
+}
+
+
+/// Checks if each position in a phrase can be given a position in the document, allowing them to
+/// be "slop" moves out of place (swapping two adjacent terms takes two moves)
+///
+/// Positions in the phrase can't share a position in the document.
+pub fn matches_phrase_positions(groups: &[(u32, Vec<u32>)], slop: u32) -> bool {
+    // The tokens match if they can be given distinct positions that have all moved by amounts
+    // within "slop" of each other. The smallest of these amounts is one that a position of a
+    // token could have, so each of those is tried as the start of the window of allowed moves.
+    let mut window_starts = groups.iter().flat_map(|&(phrase_position, ref positions)| {
+        positions.iter().map(move |position| *position as i64 - phrase_position as i64)
+    }).collect::<Vec<i64>>();
+    window_starts.sort();
+    window_starts.dedup();
+
+    window_starts.into_iter().any(|window_start| {
+        let window_end = window_start + slop as i64;
+        let candidates = groups.iter().map(|&(phrase_position, ref positions)| {
+            positions.iter().cloned().filter(|position| {
+                let offset = *position as i64 - phrase_position as i64;
+                offset >= window_start && offset <= window_end
+            }).collect::<Vec<u32>>()
+        }).collect::<Vec<Vec<u32>>>();
+
+        let mut assigned = HashMap::new();
+        (0..candidates.len()).all(|group| assign_position(group, &candidates, &mut assigned, &mut HashSet::new()))
+    })
+}
+
+
+/// Checks if the tokens of a phrase are in the terms of a field, allowing them to be "slop" moves
+/// out of place
+pub fn matches_phrase(phrase: &[Token], term_vector: &TermVector, slop: u32) -> bool {
+    match find_phrase_positions(phrase, term_vector) {
+        Some(groups) => matches_phrase_positions(&groups, slop),
+        None => false,
+    }
+}
+
+
+/// Reads the terms of the field from each document that matches the candidates query
+///
+/// Returns None if the documents can't be read. Gives an error if the terms of the field can't be
+/// read.
+pub fn read_candidate_terms(context: &QueryBuildContext, field: &str, candidates: &Query) -> Result<Option<Vec<(DocRef, TermVector)>>, QueryBuildError> {
+    if !can_read_field_terms(field) {
+        return Err(QueryBuildError::UnsupportedFieldType(field.to_string()));
+    }
+
+    let field_mapping = match context.index_metadata.and_then(|index_metadata| index_metadata.get_field_mapping(field)) {
+        Some(field_mapping) => field_mapping,
+        None => return Ok(None),
+    };
+
+    let index_reader = match context.index_reader {
+        Some(index_reader) => index_reader,
+        None => return Ok(None),
+    };
+
+    let mut collector = DocIdCollector::new();
+    if index_reader.search(&mut collector, candidates).is_err() {
+        return Ok(None);
+    }
+
+    Ok(Some(collector.into_vec().into_iter().filter_map(|doc_id| {
+        let doc_ref = DocRef::from_u64(doc_id);
+        read_field_terms(index_reader, doc_ref, field, field_mapping).map(|term_vector| (doc_ref, term_vector))
+    }).collect()))
+}
+
+
+/// Builds a query that matches the given documents by their keys
+pub fn documents_by_key(context: &QueryBuildContext, doc_refs: &[DocRef]) -> Query {
+    let index_reader = match context.index_reader {
+        Some(index_reader) => index_reader,
+        None => return Query::None,
+    };
+
+    let id_field = match index_reader.schema().get_field_by_name("_id") {
+        Some(id_field) => id_field,
+        None => return Query::None,
+    };
+
+    let key_queries = doc_refs.iter().filter_map(|doc_ref| read_stored_string(index_reader, Some(id_field), *doc_ref)).map(|doc_key| {
+        Query::Term {
+            field: id_field,
+            term: Term::from_string(&doc_key),
+            scorer: TermScorer::default(),
+        }
+    }).collect::<Vec<Query>>();
+
+    if key_queries.is_empty() {
+        Query::None
+    } else {
+        Query::Disjunction { queries: key_queries }
+    }
+}
+
+
+/// Converts the query string into tokens with the search analyzer of the field
+///
+/// The analyzer may be overridden by naming one of the index's analyzers
+pub fn analyze_query(context: &QueryBuildContext, field: &str, query: &str, analyzer_name: Option<&str>) -> Vec<Token> {
+    // Get search options for field
+    let mut field_search_options = match context.index_metadata {
+        Some(index_metadata) => {
+            match index_metadata.get_field_mapping(field) {
+                Some(field_mapping) => field_mapping.get_search_options(),
+                None => FieldSearchOptions::default(),  // TODO: error?
+            }
+        }
+        None => FieldSearchOptions::default(),  // TODO: error?
+    };
+
+    if let Some(analyzer_name) = analyzer_name {
+        if let Some(analyzer) = context.index_metadata.and_then(|index_metadata| index_metadata.analyzers().get(analyzer_name)) {
+            field_search_options.analyzer = Some(analyzer.clone());
+        }
+    }
+
+    // Tokenise query string
+    match field_search_options.analyzer {
+        Some(ref analyzer) => {
+            let token_stream = analyzer.initialise(query);
+            token_stream.collect::<Vec<Token>>()
+        }
+        None => {
+            vec![Token {term: Term::from_string(query), position: 1}]
+        }
+    }
+}
+
+
+impl QueryBuilder for MatchPhraseQueryBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Result<Query, QueryBuildError> {
+        let field_ref = match schema.get_field_by_name(&self.field) {
+            Some(field_ref) => field_ref,
+            None => return Ok(Query::None),
+        };
+
+        let tokens = analyze_query(context, &self.field, &self.query, self.analyzer.as_ref().map(|analyzer| analyzer.as_str()));
+
+        // Documents must contain every term of the phrase
+        let term_queries = |scorer: TermScorer| {
+            let mut term_queries = tokens.iter().map(|token| {
+                Query::Term {
+                    field: field_ref,
+                    term: token.term.clone(),
+                    scorer: scorer.clone(),
+                }
+            }).collect::<Vec<Query>>();
+
+            match term_queries.len() {
+                0 => Query::None,
+                1 => term_queries.pop().unwrap(),
+                _ => Query::Conjunction { queries: term_queries },
+            }
+        };
+
+        let query = term_queries(TermScorer::default_with_boost(self.boost));
+        if tokens.len() < 2 {
+            return Ok(query);
+        }
+
+        // Check the positions of the terms
+        let candidates = match read_candidate_terms(context, &self.field, &term_queries(TermScorer::default()))? {
+            Some(candidates) => candidates,
+            None => return Ok(query),
+        };
+
+        let doc_refs = candidates.iter().filter(|&&(_, ref term_vector)| matches_phrase(&tokens, term_vector, self.slop)).map(|&(doc_ref, _)| doc_ref).collect::<Vec<DocRef>>();
+        match documents_by_key(context, &doc_refs) {
+            Query::None => Ok(Query::None),
+            phrase_documents => Ok(query.filter(phrase_documents)),
+        }
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let field_name = if object.len() == 1 {
+        object.keys().collect::<Vec<_>>()[0]
+    } else {
+        return Err(QueryParseError::ExpectedSingleKey)
+    };
+
+    // Get configuration
+    let mut query = String::new();
+    let mut slop = 0;
+    let mut analyzer = None;
+    let mut boost = 1.0f32;
+
+    match object.get(field_name).unwrap() {
+        s @ &Json::String(_) => query = parse_string(s).map_err(|error| error.in_key(field_name))?,
+        &Json::Object(ref inner_object) => {
+            let mut has_query_key = false;
+
+            for (key, value) in inner_object.iter() {
+                match key.as_ref() {
+                    "query" => {
+                        has_query_key = true;
+                        query = parse_string(value).map_err(|error| error.in_key("query").in_key(field_name))?;
+                    }
+                    "slop" => {
+                        slop = parse_slop(value).map_err(|error| error.in_key("slop").in_key(field_name))?;
+                    }
+                    "analyzer" => {
+                        analyzer = Some(parse_string(value).map_err(|error| error.in_key("analyzer").in_key(field_name))?);
+                    }
+                    "boost" => {
+                        boost = parse_float(value).map_err(|error| error.in_key("boost").in_key(field_name))?;
+                    }
+                    _ => return Err(QueryParseError::UnrecognisedKey(key.clone()).in_key(field_name))
+                }
+            }
+
+            if !has_query_key {
+                return Err(QueryParseError::ExpectedKey("query").in_key(field_name))
+            }
+        }
+        _ => return Err(QueryParseError::ExpectedObjectOrString.in_key(field_name)),
+    }
+
+    Ok(Box::new(MatchPhraseQueryBuilder {
+        field: field_name.clone(),
+        query: query,
+        slop: slop,
+        analyzer: analyzer,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use kite::{Term, Token, Query, TermScorer};
+    use kite::term_vector::TermVector;
+    use kite::schema::{Schema, FieldType, FIELD_INDEXED};
+
+    use query_parser::{QueryBuildContext, QueryParseError, QueryBuildError};
+
+    use super::{parse, matches_phrase, read_candidate_terms};
+
+    fn tokens(words: &[&str]) -> Vec<Token> {
+        words.iter().enumerate().map(|(index, word)| Token {term: Term::from_string(word), position: index as u32 + 1}).collect()
+    }
+
+    fn term_vector(words: &[&str]) -> TermVector {
+        tokens(words).into()
+    }
+
+    #[test]
+    fn test_matches_phrase() {
+        let document = term_vector(&["the", "new", "york", "times"]);
+
+        assert!(matches_phrase(&tokens(&["new", "york"]), &document, 0));
+        assert!(matches_phrase(&tokens(&["york", "times"]), &document, 0));
+        assert!(!matches_phrase(&tokens(&["york", "new"]), &document, 0));
+        assert!(!matches_phrase(&tokens(&["new", "times"]), &document, 0));
+        assert!(!matches_phrase(&tokens(&["new", "jersey"]), &document, 0));
+    }
+
+    #[test]
+    fn test_matches_phrase_with_slop() {
+        let document = term_vector(&["the", "new", "york", "times"]);
+
+        assert!(matches_phrase(&tokens(&["new", "times"]), &document, 1));
+        assert!(!matches_phrase(&tokens(&["york", "new"]), &document, 1));
+        assert!(matches_phrase(&tokens(&["york", "new"]), &document, 2));
+    }
+
+    #[test]
+    fn test_matches_phrase_with_repeated_term() {
+        let document = term_vector(&["to", "be", "or", "not"]);
+
+        assert!(!matches_phrase(&tokens(&["to", "be", "or", "not", "to", "be"]), &document, 0));
+        assert!(matches_phrase(&tokens(&["be", "or", "not"]), &document, 0));
+    }
+
+    #[test]
+    fn test_matches_phrase_with_synonyms() {
+        let mut document = tokens(&["the", "new", "york", "times"]);
+        document.push(Token {term: Term::from_string("ny"), position: 2});
+        let document: TermVector = document.into();
+
+        let mut phrase = tokens(&["new", "york"]);
+        phrase.push(Token {term: Term::from_string("ny"), position: 1});
+        assert!(matches_phrase(&phrase, &document, 0));
+
+        // Tokens at the same position in the phrase must be at the same position in the document
+        let mut phrase = tokens(&["new", "york"]);
+        phrase.push(Token {term: Term::from_string("ny"), position: 2});
+        assert!(!matches_phrase(&phrase, &document, 2));
+    }
+
+    #[test]
+    fn test_matches_long_phrase_of_repeated_term() {
+        let words = vec!["a"; 40];
+        let document = term_vector(&words);
+
+        assert!(matches_phrase(&tokens(&words), &document, 0));
+        assert!(!matches_phrase(&tokens(&vec!["a"; 41]), &document, 100));
+    }
+
+    #[test]
+    fn test_gives_error_for_field_that_cant_be_read() {
+        assert_eq!(read_candidate_terms(&QueryBuildContext::new(), "_all", &Query::all()).err(), Some(QueryBuildError::UnsupportedFieldType("_all".to_string())));
+        assert_eq!(read_candidate_terms(&QueryBuildContext::new(), "user.name", &Query::all()).err(), Some(QueryBuildError::UnsupportedFieldType("user.name".to_string())));
+    }
+
+    #[test]
+    fn test_match_phrase_query() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        // Without an index reader, the positions of the terms can't be checked
+        let query = parse(&json!({
+            "test": {
+                "query": "new york",
+                "boost": 2.0
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
+
+        assert_eq!(query, Ok(Query::Conjunction {
+            queries: vec![
+                Query::Term {
+                    field: test_field,
+                    term: Term::from_string("new"),
+                    scorer: TermScorer::default_with_boost(2.0f32),
+                },
+                Query::Term {
+                    field: test_field,
+                    term: Term::from_string("york"),
+                    scorer: TermScorer::default_with_boost(2.0f32),
+                },
+            ],
+        }))
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_slop() {
+        let query = parse(&json!({
+            "test": {
+                "query": "new york",
+                "slop": -1
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedPositiveInteger.in_key("slop").in_key("test")));
+
+        let query = parse(&json!({
+            "test": {
+                "query": "new york",
+                "slop": 4294967296u64
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue.in_key("slop").in_key("test")));
+    }
+
+    #[test]
+    fn test_gives_error_for_missing_query() {
+        let query = parse(&json!({
+            "test": {
+                "slop": 1
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("query").in_key("test")));
+    }
+
+    #[test]
+    fn test_gives_error_for_incorrect_type() {
+        // String
+        let query = parse(&json!("hello"));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedObject));
+
+        // Field value is an array
+        let query = parse(&json!({"test": ["hello"]}));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedObjectOrString.in_key("test")));
+    }
+}
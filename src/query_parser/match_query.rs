@@ -1,13 +1,16 @@
 //! Parses "match" queries
 
 use serde_json::Value as Json;
+use serde_json::Map;
 use kite::{Term, Token, Query, TermScorer};
 use kite::schema::Schema;
 
 use mapping::FieldSearchOptions;
 
-use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
-use query_parser::utils::{parse_string, parse_float, Operator, parse_operator};
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, QueryBuildError};
+use query_parser::utils::{parse_string, parse_float, parse_positive_integer, parse_boolean, parse_max_expansions, Operator, parse_operator, parse_fuzziness};
+use query_parser::{match_phrase_query, match_phrase_prefix_query};
+use query_parser::fuzzy_query::{FuzzyOptions, build_fuzzy_term_query};
 
 
 #[derive(Debug)]
@@ -15,12 +18,13 @@ struct MatchQueryBuilder {
     field: String,
     query: String,
     operator: Operator,
+    fuzzy: Option<FuzzyOptions>,
     boost: f32,
 }
 
 
 impl QueryBuilder for MatchQueryBuilder {
-    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Result<Query, QueryBuildError> {
         // Get search options for field
         let field_search_options = match context.index_metadata {
             Some(index_metadata) => {
@@ -44,12 +48,18 @@ impl QueryBuilder for MatchQueryBuilder {
         };
 
         // Create a term query for each token
+        let field_ref = schema.get_field_by_name(&self.field).unwrap();
         let mut sub_queries = Vec::new();
         for token in tokens {
-            sub_queries.push(Query::Term {
-                field: schema.get_field_by_name(&self.field).unwrap(),
-                term: token.term,
-                scorer: TermScorer::default(),
+            sub_queries.push(match self.fuzzy {
+                Some(ref fuzzy) => build_fuzzy_term_query(context, field_ref, token.term, fuzzy)?,
+                None => {
+                    Query::Term {
+                        field: field_ref,
+                        term: token.term,
+                        scorer: TermScorer::default(),
+                    }
+                }
             });
         }
 
@@ -70,7 +80,7 @@ impl QueryBuilder for MatchQueryBuilder {
         };
 
         // Add boost
-        query.boost(self.boost)
+        Ok(query.boost(self.boost))
     }
 }
 
@@ -88,39 +98,76 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     let mut query = String::new();
     let mut boost = 1.0f32;
     let mut operator = Operator::Or;
+    let mut fuzziness = None;
+    let mut fuzzy = FuzzyOptions::default();
 
     match object.get(field_name).unwrap() {
-        s @ &Json::String(_) => query = parse_string(s)?,
+        s @ &Json::String(_) => query = parse_string(s).map_err(|error| error.in_key(field_name))?,
         &Json::Object(ref inner_object) => {
+            // Phrase matches are handled by the "match_phrase" and "match_phrase_prefix" queries
+            if let Some(type_json) = inner_object.get("type") {
+                match parse_string(type_json).map_err(|error| error.in_key("type").in_key(field_name))?.as_str() {
+                    "boolean" => {}
+                    match_type @ "phrase" | match_type @ "phrase_prefix" => {
+                        let mut phrase_object = inner_object.clone();
+                        phrase_object.remove("type");
+
+                        let mut phrase_json = Map::new();
+                        phrase_json.insert(field_name.clone(), Json::Object(phrase_object));
+
+                        if match_type == "phrase" {
+                            return match_phrase_query::parse(&Json::Object(phrase_json));
+                        } else {
+                            return match_phrase_prefix_query::parse(&Json::Object(phrase_json));
+                        }
+                    }
+                    _ => return Err(QueryParseError::InvalidValue.in_key("type").in_key(field_name)),
+                }
+            }
+
             let mut has_query_key = false;
 
             for (key, value) in inner_object.iter() {
                 match key.as_ref() {
+                    "type" => {}
                     "query" => {
                         has_query_key = true;
-                        query = parse_string(value)?;
+                        query = parse_string(value).map_err(|error| error.in_key("query").in_key(field_name))?;
                     }
                     "boost" => {
-                        boost = parse_float(value)?;
+                        boost = parse_float(value).map_err(|error| error.in_key("boost").in_key(field_name))?;
                     }
                     "operator" => {
-                        operator = parse_operator(value)?;
+                        operator = parse_operator(value).map_err(|error| error.in_key("operator").in_key(field_name))?;
                     }
-                    _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+                    "fuzziness" => {
+                        fuzziness = Some(parse_fuzziness(value).map_err(|error| error.in_key("fuzziness").in_key(field_name))?);
+                    }
+                    "prefix_length" => {
+                        fuzzy.prefix_length = parse_positive_integer(value).map_err(|error| error.in_key("prefix_length").in_key(field_name))? as usize;
+                    }
+                    "max_expansions" => {
+                        fuzzy.max_expansions = parse_max_expansions(value).map_err(|error| error.in_key("max_expansions").in_key(field_name))?;
+                    }
+                    "fuzzy_transpositions" => {
+                        fuzzy.transpositions = parse_boolean(value).map_err(|error| error.in_key("fuzzy_transpositions").in_key(field_name))?;
+                    }
+                    _ => return Err(QueryParseError::UnrecognisedKey(key.clone()).in_key(field_name))
                 }
             }
 
             if !has_query_key {
-                return Err(QueryParseError::ExpectedKey("query"))
+                return Err(QueryParseError::ExpectedKey("query").in_key(field_name))
             }
         }
-        _ => return Err(QueryParseError::ExpectedObjectOrString),
+        _ => return Err(QueryParseError::ExpectedObjectOrString.in_key(field_name)),
     }
 
     Ok(Box::new(MatchQueryBuilder {
         field: field_name.clone(),
         query: query,
         operator: operator,
+        fuzzy: fuzziness.map(|fuzziness| FuzzyOptions { fuzziness: fuzziness, ..fuzzy }),
         boost: boost,
     }))
 }
@@ -148,7 +195,7 @@ mod tests {
                 \"query\": \"bar\"
             }
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::Term {
             field: foo_field,
@@ -168,7 +215,7 @@ mod tests {
                 \"query\": \"bar baz\"
             }
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::Disjunction {
             queries: vec![
@@ -195,7 +242,7 @@ mod tests {
         {
             \"foo\": \"bar baz\"
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::Disjunction {
             queries: vec![
@@ -225,7 +272,7 @@ mod tests {
                 \"boost\": 2.0
             }
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::Term {
             field: foo_field,
@@ -246,7 +293,7 @@ mod tests {
                 \"boost\": 2
             }
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::Term {
             field: foo_field,
@@ -267,7 +314,7 @@ mod tests {
                 \"operator\": \"and\"
             }
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::Conjunction {
             queries: vec![
@@ -323,7 +370,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat.in_key("boost").in_key("foo")));
 
         // Array
         let query = parse(&serde_json::from_str("
@@ -335,7 +382,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat.in_key("boost").in_key("foo")));
 
         // Object
         let query = parse(&serde_json::from_str("
@@ -349,7 +396,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat.in_key("boost").in_key("foo")));
     }
 
     #[test]
@@ -361,7 +408,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("query")));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("query").in_key("foo")));
     }
 
     #[test]
@@ -389,6 +436,83 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("hello".to_string())));
+        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("hello".to_string()).in_key("foo")));
+    }
+
+    #[test]
+    fn test_phrase_type() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "test": {
+                "query": "foo bar",
+                "type": "phrase",
+                "slop": 1
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
+
+        // Without an index reader, the positions of the terms can't be checked
+        assert_eq!(query, Ok(Query::Conjunction {
+            queries: vec![
+                Query::Term {
+                    field: test_field,
+                    term: Term::from_string("foo"),
+                    scorer: TermScorer::default(),
+                },
+                Query::Term {
+                    field: test_field,
+                    term: Term::from_string("bar"),
+                    scorer: TermScorer::default(),
+                },
+            ],
+        }))
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_type() {
+        let query = parse(&json!({
+            "test": {
+                "query": "foo bar",
+                "type": "fuzzy"
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue.in_key("type").in_key("test")));
+    }
+
+    #[test]
+    fn test_fuzziness() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "test": {
+                "query": "iphnoe",
+                "fuzziness": "AUTO",
+                "prefix_length": 1,
+                "max_expansions": 10,
+                "fuzzy_transpositions": false
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
+
+        // Without a term dictionary, terms aren't expanded
+        assert_eq!(query, Ok(Query::Term {
+            field: test_field,
+            term: Term::from_string("iphnoe"),
+            scorer: TermScorer::default(),
+        }))
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_fuzziness() {
+        let query = parse(&json!({
+            "test": {
+                "query": "iphnoe",
+                "fuzziness": 3
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue.in_key("fuzziness").in_key("test")));
     }
 }
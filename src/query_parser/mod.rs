@@ -13,27 +13,54 @@ pub mod and_query;
 pub mod or_query;
 pub mod not_query;
 pub mod constant_score_query;
+pub mod bool_query;
+pub mod range_query;
+pub mod match_phrase_query;
+pub mod match_phrase_prefix_query;
+pub mod fuzzy_query;
 
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
 
 use serde_json::Value as Json;
 use kite::Query;
 use kite::schema::Schema;
+use kite_rocksdb::RocksDBReader;
 
 use index::metadata::IndexMetadata;
+use index::term_dictionary::TermDictionary;
 
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct QueryBuildContext<'a> {
     pub index_metadata: Option<&'a IndexMetadata>,
+
+    /// Used by queries that need to read documents to work out which terms to search for
+    pub index_reader: Option<&'a RocksDBReader<'a>>,
+
+    /// Used by queries that expand a term into the terms of a field
+    pub term_dictionary: Option<&'a TermDictionary>,
     score_required: bool,
 }
 
 
+impl<'a> fmt::Debug for QueryBuildContext<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("QueryBuildContext")
+            .field("index_metadata", &self.index_metadata)
+            .field("has_index_reader", &self.index_reader.is_some())
+            .field("has_term_dictionary", &self.term_dictionary.is_some())
+            .field("score_required", &self.score_required)
+            .finish()
+    }
+}
+
+
 impl<'a> QueryBuildContext<'a> {
     pub fn new() -> QueryBuildContext<'a> {
         QueryBuildContext {
             index_metadata: None,
+            index_reader: None,
+            term_dictionary: None,
             score_required: true
         }
     }
@@ -44,6 +71,28 @@ impl<'a> QueryBuildContext<'a> {
         self
     }
 
+    #[inline]
+    pub fn set_index_reader(mut self, index_reader: &'a RocksDBReader<'a>) -> QueryBuildContext<'a> {
+        self.index_reader = Some(index_reader);
+        self
+    }
+
+    #[inline]
+    pub fn set_term_dictionary(mut self, term_dictionary: &'a TermDictionary) -> QueryBuildContext<'a> {
+        self.term_dictionary = Some(term_dictionary);
+        self
+    }
+
+    /// Gets the term dictionary to expand terms with, if there is one
+    ///
+    /// Gives an error if the index was created before its terms were listed.
+    pub fn get_term_dictionary(&self) -> Result<Option<&'a TermDictionary>, QueryBuildError> {
+        match self.term_dictionary {
+            Some(term_dictionary) if !term_dictionary.is_complete() => Err(QueryBuildError::IncompleteTermDictionary),
+            term_dictionary => Ok(term_dictionary),
+        }
+    }
+
     #[inline]
     pub fn no_score(mut self) -> QueryBuildContext<'a> {
         self.score_required = false;
@@ -62,21 +111,157 @@ pub enum QueryParseError {
     ExpectedArray,
     ExpectedString,
     ExpectedFloat,
+    ExpectedPositiveInteger,
+    ExpectedBoolean,
     ExpectedObjectOrString,
     InvalidValue,
     ExpectedSingleKey,
     InvalidOperator,
+
+    /// The error occurred in the value of a key or in an item of an array
+    InKey(String, Box<QueryParseError>),
+}
+
+
+impl QueryParseError {
+    /// Records the key of the value that the error occurred in
+    pub fn in_key(self, key: &str) -> QueryParseError {
+        QueryParseError::InKey(key.to_string(), Box::new(self))
+    }
+
+    /// Records the index of the array item that the error occurred in
+    pub fn in_item(self, index: usize) -> QueryParseError {
+        self.in_key(&index.to_string())
+    }
+
+    /// Returns the error without its location
+    pub fn kind(&self) -> &QueryParseError {
+        match *self {
+            QueryParseError::InKey(_, ref error) => error.kind(),
+            _ => self,
+        }
+    }
+
+    fn push_path(&self, path: &mut Vec<String>) {
+        match *self {
+            QueryParseError::InKey(ref key, ref error) => {
+                path.push(key.clone());
+                error.push_path(path);
+            }
+            QueryParseError::UnrecognisedKey(ref key) | QueryParseError::UnrecognisedQueryType(ref key) => {
+                path.push(key.clone());
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the path of keys to the value that caused the error
+    ///
+    /// Array items are represented by their index. Unrecognised keys are included at the end.
+    pub fn path(&self) -> Vec<String> {
+        let mut path = Vec::new();
+        self.push_path(&mut path);
+        path
+    }
+
+    /// Describes the type of value that was expected
+    pub fn expected(&self) -> Option<String> {
+        match *self.kind() {
+            QueryParseError::ExpectedKey(key) => Some(format!("key {:?}", key)),
+            QueryParseError::ExpectedObject => Some("object".to_string()),
+            QueryParseError::ExpectedArray => Some("array".to_string()),
+            QueryParseError::ExpectedString => Some("string".to_string()),
+            QueryParseError::ExpectedFloat => Some("number".to_string()),
+            QueryParseError::ExpectedPositiveInteger => Some("positive integer".to_string()),
+            QueryParseError::ExpectedBoolean => Some("boolean".to_string()),
+            QueryParseError::ExpectedObjectOrString => Some("object or string".to_string()),
+            QueryParseError::ExpectedSingleKey => Some("object with a single key".to_string()),
+            QueryParseError::InvalidOperator => Some("\"and\" or \"or\"".to_string()),
+            _ => None,
+        }
+    }
+
+    pub fn reason(&self) -> String {
+        if let Some(expected) = self.expected() {
+            return format!("expected {}", expected);
+        }
+
+        match *self.kind() {
+            QueryParseError::UnrecognisedQueryType(ref query_type) => format!("unrecognised query type {:?}", query_type),
+            QueryParseError::FieldDoesntExist(ref field_name) => format!("field {:?} doesn't exist", field_name),
+            QueryParseError::UnrecognisedKey(ref key) => format!("unrecognised key {:?}", key),
+            _ => "invalid value".to_string(),
+        }
+    }
+
+    /// Describes the error, reading the value that caused it from the JSON that was parsed
+    pub fn details(&self, json: &Json) -> Json {
+        let path = self.path();
+
+        let mut actual = Some(json);
+        for key in path.iter() {
+            actual = match actual {
+                Some(&Json::Object(ref object)) => object.get(key),
+                Some(&Json::Array(ref array)) => key.parse::<usize>().ok().and_then(|index| array.get(index)),
+                _ => None,
+            };
+        }
+
+        json!({
+            "type": "query_parsing_exception",
+            "reason": self.reason(),
+            "path": path.join("."),
+            "expected": self.expected(),
+            "actual": actual,
+        })
+    }
+}
+
+
+/// An error that stops a parsed query from being built against an index
+#[derive(Debug, PartialEq)]
+pub enum QueryBuildError {
+    /// The type of the field can't be searched by the query
+    UnsupportedFieldType(String),
+
+    /// A value in the query can't be converted into the type of the field
+    InvalidFieldValue(String),
+
+    /// The query would be built from more than the maximum number of clauses
+    TooManyClauses(usize),
+
+    /// The field was mapped before it had sortable terms, so it can't be searched by range
+    NoSortableTerms(String),
+
+    /// The index was created before its terms were listed, so terms can't be expanded
+    IncompleteTermDictionary,
+}
+
+
+impl QueryBuildError {
+    pub fn reason(&self) -> String {
+        match *self {
+            QueryBuildError::UnsupportedFieldType(ref field_name) => format!("field {:?} has a type that this query can't search", field_name),
+            QueryBuildError::InvalidFieldValue(ref field_name) => format!("value can't be used with the type of field {:?}", field_name),
+            QueryBuildError::TooManyClauses(max_clauses) => format!("too many clauses, the maximum is {}", max_clauses),
+            QueryBuildError::NoSortableTerms(ref field_name) => format!("field {:?} can't be searched by range, reindex it into a new index", field_name),
+            QueryBuildError::IncompleteTermDictionary => "the terms of the index can't be listed to expand terms with, reindex it into a new index".to_string(),
+        }
+    }
 }
 
 
 pub trait QueryBuilder: Debug {
-    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query;
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Result<Query, QueryBuildError>;
 }
 
 
 fn get_query_parser(query_name: &str) -> Option<fn(&Json) -> Result<Box<QueryBuilder>, QueryParseError>> {
     match query_name {
         "match" => Some(match_query::parse),
+        "match_phrase" => Some(match_phrase_query::parse),
+        "match_phrase_prefix" => Some(match_phrase_prefix_query::parse),
+        "fuzzy" => Some(fuzzy_query::parse),
         "multi_match" => Some(multi_match_query::parse),
         "match_all" => Some(match_all_query::parse),
         "match_none" => Some(match_none_query::parse),
@@ -89,6 +274,8 @@ fn get_query_parser(query_name: &str) -> Option<fn(&Json) -> Result<Box<QueryBui
         "or" => Some(or_query::parse),
         "not" => Some(not_query::parse),
         "constant_score" => Some(constant_score_query::parse),
+        "bool" => Some(bool_query::parse),
+        "range" => Some(range_query::parse),
         _ => None
     }
 }
@@ -104,7 +291,63 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     };
 
     match get_query_parser(&query_type) {
-        Some(parse) => parse(object.get(query_type).unwrap()),
+        Some(parse) => parse(object.get(query_type).unwrap()).map_err(|error| error.in_key(query_type)),
         None => Err(QueryParseError::UnrecognisedQueryType(query_type.clone())),
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::{QueryParseError, parse};
+
+    #[test]
+    fn test_error_path() {
+        let query_json = json!({
+            "filtered": {
+                "filter": {
+                    "and": [
+                        {"term": {"title": "hello"}},
+                        {"term": {"title": {"value": "world", "boost": "high"}}}
+                    ]
+                }
+            }
+        });
+
+        let error = parse(&query_json).err().unwrap();
+
+        assert_eq!(error, QueryParseError::ExpectedFloat.in_key("boost").in_key("title").in_key("term").in_item(1).in_key("and").in_key("filter").in_key("filtered"));
+        assert_eq!(error.kind(), &QueryParseError::ExpectedFloat);
+        assert_eq!(error.path(), vec!["filtered", "filter", "and", "1", "term", "title", "boost"]);
+        assert_eq!(error.details(&query_json), json!({
+            "type": "query_parsing_exception",
+            "reason": "expected number",
+            "path": "filtered.filter.and.1.term.title.boost",
+            "expected": "number",
+            "actual": "high",
+        }));
+    }
+
+    #[test]
+    fn test_error_path_unrecognised_key() {
+        let query_json = json!({
+            "match": {
+                "title": {
+                    "query": "hello",
+                    "foo": "bar"
+                }
+            }
+        });
+
+        let error = parse(&query_json).err().unwrap();
+
+        assert_eq!(error.path(), vec!["match", "title", "foo"]);
+        assert_eq!(error.details(&query_json), json!({
+            "type": "query_parsing_exception",
+            "reason": "unrecognised key \"foo\"",
+            "path": "match.title.foo",
+            "expected": null,
+            "actual": "bar",
+        }));
+    }
+}
@@ -6,8 +6,9 @@ use kite::schema::Schema;
 
 use mapping::FieldSearchOptions;
 
-use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
-use query_parser::utils::{parse_string, parse_float, Operator, parse_operator, parse_field_and_boost};
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, QueryBuildError};
+use query_parser::utils::{parse_string, parse_float, parse_positive_integer, parse_boolean, parse_max_expansions, Operator, parse_operator, parse_field_and_boost, parse_fuzziness};
+use query_parser::fuzzy_query::{FuzzyOptions, build_fuzzy_term_query};
 
 
 #[derive(Debug)]
@@ -15,12 +16,13 @@ struct MultiMatchQueryBuilder {
     fields: Vec<(String, f32)>,
     query: String,
     operator: Operator,
+    fuzzy: Option<FuzzyOptions>,
     boost: f32,
 }
 
 
 impl QueryBuilder for MultiMatchQueryBuilder {
-    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Result<Query, QueryBuildError> {
         // Convert query string into term query objects
         let mut field_queries = Vec::new();
         for &(ref field_name, field_boost) in self.fields.iter() {
@@ -46,12 +48,18 @@ impl QueryBuilder for MultiMatchQueryBuilder {
                 }
             };
 
+            let field_ref = schema.get_field_by_name(field_name).unwrap();
             let mut term_queries = Vec::new();
             for token in tokens {
-                term_queries.push(Query::Term {
-                    field: schema.get_field_by_name(field_name).unwrap(),
-                    term: token.term,
-                    scorer: TermScorer::default(),
+                term_queries.push(match self.fuzzy {
+                    Some(ref fuzzy) => build_fuzzy_term_query(context, field_ref, token.term, fuzzy)?,
+                    None => {
+                        Query::Term {
+                            field: field_ref,
+                            term: token.term,
+                            scorer: TermScorer::default(),
+                        }
+                    }
                 });
             }
 
@@ -86,7 +94,7 @@ impl QueryBuilder for MultiMatchQueryBuilder {
         };
 
         // Add boost
-        query.boost(self.boost)
+        Ok(query.boost(self.boost))
     }
 }
 
@@ -99,6 +107,8 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     let mut query = String::new();
     let mut boost = 1.0f32;
     let mut operator = Operator::Or;
+    let mut fuzziness = None;
+    let mut fuzzy = FuzzyOptions::default();
 
     let mut has_fields_key = false;
     let mut has_query_key = false;
@@ -110,22 +120,34 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
 
                 match *val {
                     Json::Array(ref array) => {
-                        for field in array.iter() {
-                            fields_with_boosts.push(parse_field_and_boost(field)?);
+                        for (index, field) in array.iter().enumerate() {
+                            fields_with_boosts.push(parse_field_and_boost(field).map_err(|error| error.in_item(index).in_key("fields"))?);
                         }
                     }
-                    _ => return Err(QueryParseError::ExpectedArray)
+                    _ => return Err(QueryParseError::ExpectedArray.in_key("fields"))
                 }
             }
             "query" => {
                 has_query_key = true;
-                query = parse_string(val)?;
+                query = parse_string(val).map_err(|error| error.in_key("query"))?;
             }
             "boost" => {
-                boost = parse_float(val)?;
+                boost = parse_float(val).map_err(|error| error.in_key("boost"))?;
             }
             "operator" => {
-                operator = parse_operator(val)?;
+                operator = parse_operator(val).map_err(|error| error.in_key("operator"))?;
+            }
+            "fuzziness" => {
+                fuzziness = Some(parse_fuzziness(val).map_err(|error| error.in_key("fuzziness"))?);
+            }
+            "prefix_length" => {
+                fuzzy.prefix_length = parse_positive_integer(val).map_err(|error| error.in_key("prefix_length"))? as usize;
+            }
+            "max_expansions" => {
+                fuzzy.max_expansions = parse_max_expansions(val).map_err(|error| error.in_key("max_expansions"))?;
+            }
+            "fuzzy_transpositions" => {
+                fuzzy.transpositions = parse_boolean(val).map_err(|error| error.in_key("fuzzy_transpositions"))?;
             }
             _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
         }
@@ -143,6 +165,7 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
         fields: fields_with_boosts,
         query: query,
         operator: operator,
+        fuzzy: fuzziness.map(|fuzziness| FuzzyOptions { fuzziness: fuzziness, ..fuzzy }),
         boost: boost,
     }))
 }
@@ -170,7 +193,7 @@ mod tests {
             \"query\": \"foo\",
             \"fields\": [\"bar\", \"baz\"]
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::DisjunctionMax {
             queries: vec![
@@ -199,7 +222,7 @@ mod tests {
             \"query\": \"hello world\",
             \"fields\": [\"bar\", \"baz\"]
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::DisjunctionMax {
             queries: vec![
@@ -247,7 +270,7 @@ mod tests {
             \"fields\": [\"bar\", \"baz\"],
             \"boost\": 2.0
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::DisjunctionMax {
             queries: vec![
@@ -277,7 +300,7 @@ mod tests {
             \"fields\": [\"bar\", \"baz\"],
             \"boost\": 2
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::DisjunctionMax {
             queries: vec![
@@ -306,7 +329,7 @@ mod tests {
             \"query\": \"foo\",
             \"fields\": [\"bar^2\", \"baz^1.0\"]
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::DisjunctionMax {
             queries: vec![
@@ -336,7 +359,7 @@ mod tests {
             \"fields\": [\"bar^2\", \"baz^1.0\"],
             \"boost\": 2.0
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::DisjunctionMax {
             queries: vec![
@@ -366,7 +389,7 @@ mod tests {
             \"fields\": [\"baz\", \"quux\"],
             \"operator\": \"and\"
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::DisjunctionMax {
             queries: vec![
@@ -447,7 +470,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedString));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedString.in_key("query")));
 
         // Array
         let query = parse(&serde_json::from_str("
@@ -457,7 +480,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedString));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedString.in_key("query")));
 
         // Integer
         let query = parse(&serde_json::from_str("
@@ -467,7 +490,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedString));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedString.in_key("query")));
 
         // Float
         let query = parse(&serde_json::from_str("
@@ -476,7 +499,7 @@ mod tests {
             \"fields\": [\"bar\", \"baz\"]
         }        ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedString));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedString.in_key("query")));
     }
 
     #[test]
@@ -489,7 +512,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedArray));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedArray.in_key("fields")));
 
         // Object
         let query = parse(&serde_json::from_str("
@@ -501,7 +524,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedArray));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedArray.in_key("fields")));
 
         // Integer
         let query = parse(&serde_json::from_str("
@@ -511,7 +534,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedArray));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedArray.in_key("fields")));
 
         // Float
         let query = parse(&serde_json::from_str("
@@ -521,7 +544,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedArray));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedArray.in_key("fields")));
     }
 
     #[test]
@@ -535,7 +558,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat.in_key("boost")));
 
         // Array
         let query = parse(&serde_json::from_str("
@@ -546,7 +569,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat.in_key("boost")));
 
         // Object
         let query = parse(&serde_json::from_str("
@@ -559,7 +582,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat.in_key("boost")));
     }
 
     #[test]
@@ -596,4 +619,16 @@ mod tests {
 
         assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("hello".to_string())));
     }
+
+    #[test]
+    fn test_gives_error_for_invalid_fuzzy_transpositions() {
+        let query = parse(&json!({
+            "query": "iphnoe",
+            "fields": ["bar", "baz"],
+            "fuzziness": 1,
+            "fuzzy_transpositions": "no"
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedBoolean.in_key("fuzzy_transpositions")));
+    }
 }
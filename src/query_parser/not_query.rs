@@ -4,7 +4,7 @@ use serde_json::Value as Json;
 use kite::Query;
 use kite::schema::Schema;
 
-use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, parse as parse_query};
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, QueryBuildError, parse as parse_query};
 
 
 #[derive(Debug)]
@@ -14,11 +14,11 @@ struct NotQueryBuilder {
 
 
 impl QueryBuilder for NotQueryBuilder {
-    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
-        Query::Exclude {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Result<Query, QueryBuildError> {
+        Ok(Query::Exclude {
             query: Box::new(Query::all()),
-            exclude: Box::new(self.query.build(&context.clone().no_score(), schema)),
-        }
+            exclude: Box::new(self.query.build(&context.clone().no_score(), schema)?),
+        })
     }
 }
 
@@ -52,7 +52,7 @@ mod tests {
                 \"test\":  \"foo\"
             }
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::Exclude {
             query: Box::new(Query::all()),
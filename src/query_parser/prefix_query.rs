@@ -4,20 +4,31 @@ use serde_json::Value as Json;
 use kite::{Query, MultiTermSelector, TermScorer};
 use kite::schema::Schema;
 
-use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, QueryBuildError};
 use query_parser::utils::parse_float;
 
 
 #[derive(Debug)]
-struct PrefixQueryBuilder {
+pub struct PrefixQueryBuilder {
     field: String,
     prefix: String,
     boost: f32,
 }
 
 
+impl PrefixQueryBuilder {
+    pub fn new(field: String, prefix: String) -> PrefixQueryBuilder {
+        PrefixQueryBuilder {
+            field: field,
+            prefix: prefix,
+            boost: 1.0f32,
+        }
+    }
+}
+
+
 impl QueryBuilder for PrefixQueryBuilder {
-    fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Query {
+    fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Result<Query, QueryBuildError> {
         let query = Query::MultiTerm {
             field: schema.get_field_by_name(&self.field).unwrap(),
             term_selector: MultiTermSelector::Prefix(self.prefix.clone()),
@@ -25,7 +36,7 @@ impl QueryBuilder for PrefixQueryBuilder {
         };
 
         // Add boost
-        query.boost(self.boost)
+        Ok(query.boost(self.boost))
     }
 }
 
@@ -57,13 +68,13 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
                         value = Some(val);
                     }
                     "boost" => {
-                        boost = parse_float(val)?;
+                        boost = parse_float(val).map_err(|error| error.in_key("boost").in_key(field_name))?;
                     }
-                    _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+                    _ => return Err(QueryParseError::UnrecognisedKey(key.clone()).in_key(field_name))
                 }
             }
         }
-        _ => return Err(QueryParseError::ExpectedObjectOrString),
+        _ => return Err(QueryParseError::ExpectedObjectOrString.in_key(field_name)),
     }
 
     match value {
@@ -75,10 +86,10 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
                     boost: boost,
                 }))
             } else {
-                Err(QueryParseError::ExpectedString)
+                Err(QueryParseError::ExpectedString.in_key(field_name))
             }
         }
-        None => Err(QueryParseError::ExpectedKey("value"))
+        None => Err(QueryParseError::ExpectedKey("value").in_key(field_name))
     }
 }
 
@@ -105,7 +116,7 @@ mod tests {
                 \"value\": \"bar\"
             }
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::MultiTerm {
             field: foo_field,
@@ -123,7 +134,7 @@ mod tests {
         {
             \"foo\": \"bar\"
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::MultiTerm {
             field: foo_field,
@@ -143,7 +154,7 @@ mod tests {
                 \"prefix\": \"bar\"
             }
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::MultiTerm {
             field: foo_field,
@@ -164,7 +175,7 @@ mod tests {
                 \"boost\": 2.0
             }
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::MultiTerm {
             field: foo_field,
@@ -185,7 +196,7 @@ mod tests {
                 \"boost\": 2
             }
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::MultiTerm {
             field: foo_field,
@@ -232,7 +243,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat.in_key("boost").in_key("foo")));
 
         // Array
         let query = parse(&serde_json::from_str("
@@ -244,7 +255,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat.in_key("boost").in_key("foo")));
 
         // Object
         let query = parse(&serde_json::from_str("
@@ -258,7 +269,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat.in_key("boost").in_key("foo")));
     }
 
     #[test]
@@ -270,7 +281,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("value")));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("value").in_key("foo")));
     }
 
     #[test]
@@ -298,6 +309,6 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("hello".to_string())));
+        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("hello".to_string()).in_key("foo")));
     }
 }
@@ -0,0 +1,811 @@
+//! Parses "range" queries
+//!
+//! The store can only look terms up by their exact value or by a prefix. Integer and date values
+//! are also indexed as sortable terms into a separate field (see `mapping::sortable_field_name`),
+//! so the range is split into the prefixes of the sortable terms that are in it. This needs at
+//! most 15 prefixes for each of the 16 hexadecimal digits at each end of the range. Fields that
+//! were mapped before sortable terms were added have no such field and give an error, their
+//! documents must be reindexed into a new index to be searched by range.
+//!
+//! Date bounds may use date math (eg, "now-7d/d").
+
+use std::i32;
+use std::i64;
+
+use serde_json::Value as Json;
+use chrono::{DateTime, Utc, FixedOffset, NaiveDate, NaiveDateTime, Datelike};
+use kite::{Term, Query, TermScorer, MultiTermSelector};
+use kite::schema::{Schema, FieldRef};
+
+use mapping::{FieldType, SORTABLE_TERM_PREFIX, sortable_key, sortable_field_name};
+use search::aggregations::date_histogram::{DateFormat, DateInterval, DateTimeZone, CalendarUnit, SECOND, MINUTE, HOUR, DAY,
+                                           datetime_to_millis, millis_to_naive, naive_to_millis, parse_time_zone, parse_format};
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, QueryBuildError};
+use query_parser::utils::{parse_string, parse_float};
+
+
+#[derive(Debug)]
+struct Bound {
+    value: Json,
+    inclusive: bool,
+}
+
+
+#[derive(Debug)]
+struct RangeQueryBuilder {
+    field: String,
+    lower: Option<Bound>,
+    upper: Option<Bound>,
+    formats: Vec<DateFormat>,
+    time_zone: DateTimeZone,
+    boost: f32,
+}
+
+
+/// Splits a range of sortable keys into the fewest prefixes (of their hexadecimal digits)
+/// that select it
+///
+/// Both ends of the range are inclusive
+fn sortable_prefixes(start: u64, end: u64) -> Vec<String> {
+    let mut prefixes = Vec::new();
+    let mut start = start;
+
+    while start <= end {
+        // Drop as many trailing digits as possible, the block of keys that share the remaining
+        // digits must start at "start" and not go past "end"
+        let mut dropped_digits = 0;
+        while dropped_digits < 16 {
+            let mask = if dropped_digits == 15 { !0u64 } else { (1u64 << (4 * (dropped_digits + 1))) - 1 };
+            if start & mask != 0 || start | mask > end {
+                break;
+            }
+
+            dropped_digits += 1;
+        }
+
+        prefixes.push(format!("{:016x}", start)[..16 - dropped_digits].to_string());
+
+        let block_end = if dropped_digits == 16 { !0u64 } else { start | ((1u64 << (4 * dropped_digits)) - 1) };
+        if block_end >= end {
+            break;
+        }
+
+        start = block_end + 1;
+    }
+
+    prefixes
+}
+
+
+/// Builds a query that matches the sortable terms of a field that are between two values
+///
+/// Both ends of the range are inclusive
+fn build_sortable_range_query(field_ref: FieldRef, lower: i64, upper: i64) -> Query {
+    let mut queries = Vec::new();
+    if lower <= upper {
+        for prefix in sortable_prefixes(sortable_key(lower), sortable_key(upper)) {
+            let prefix = format!("{}{}", SORTABLE_TERM_PREFIX, prefix);
+
+            // Prefixes with every digit select a single term
+            if prefix.len() == SORTABLE_TERM_PREFIX.len() + 16 {
+                queries.push(Query::Term {
+                    field: field_ref,
+                    term: Term::from_string(&prefix),
+                    scorer: TermScorer::default(),
+                });
+            } else {
+                queries.push(Query::MultiTerm {
+                    field: field_ref,
+                    term_selector: MultiTermSelector::Prefix(prefix),
+                    scorer: TermScorer::default(),
+                });
+            }
+        }
+    }
+
+    match queries.len() {
+        0 => Query::None,
+        1 => queries.pop().unwrap(),
+        _ => Query::Disjunction { queries: queries },
+    }
+}
+
+
+/// Moves a date by a number of months, days past the end of the month are moved back to the last day
+fn add_months(datetime: NaiveDateTime, months: i64) -> Option<NaiveDateTime> {
+    let total_months = match months.checked_add(datetime.year() as i64 * 12 + datetime.month0() as i64) {
+        Some(total_months) if total_months >= 0 && total_months / 12 <= i32::MAX as i64 => total_months,
+        _ => return None,
+    };
+
+    let year = (total_months / 12) as i32;
+    let month = (total_months % 12) as u32 + 1;
+    let date = (1..datetime.day() + 1).rev().filter_map(|day| NaiveDate::from_ymd_opt(year, month, day)).next();
+
+    date.map(|date| date.and_time(datetime.time()))
+}
+
+
+/// Checks that a date (in milliseconds since epoch) is in the range of calendar dates
+///
+/// Dates in this range are far enough from the limits of i64 that time zone offsets can be
+/// applied to them and they can be converted into microseconds.
+fn check_date_range(millis: i64) -> Option<i64> {
+    millis_to_naive(millis).map(|_| millis)
+}
+
+
+/// Adds a number of fixed length units to a date, returns None if it overflows
+fn add_units(millis: i64, amount: i64, unit: i64) -> Option<i64> {
+    amount.checked_mul(unit).and_then(|offset| millis.checked_add(offset))
+}
+
+
+/// Parses a date into milliseconds since epoch, trying each format in turn
+///
+/// Dates without a time zone are in the given time zone
+fn parse_date(string: &str, formats: &[DateFormat], time_zone: DateTimeZone) -> Option<i64> {
+    let default_formats = [DateFormat::Default];
+    let formats = if formats.is_empty() { &default_formats[..] } else { formats };
+
+    for format in formats.iter() {
+        let local_datetime = match *format {
+            DateFormat::Default => {
+                if let Ok(datetime) = string.parse::<DateTime<FixedOffset>>() {
+                    return Some(datetime.timestamp() * SECOND + datetime.timestamp_subsec_millis() as i64);
+                }
+
+                NaiveDateTime::parse_from_str(string, "%Y-%m-%dT%H:%M:%S%.f").ok()
+                    .or_else(|| NaiveDate::parse_from_str(string, "%Y-%m-%d").ok().map(|date| date.and_hms(0, 0, 0)))
+            }
+            DateFormat::EpochMillis => {
+                if let Ok(millis) = string.parse::<i64>() {
+                    if let Some(millis) = check_date_range(millis) {
+                        return Some(millis);
+                    }
+                }
+
+                None
+            }
+            DateFormat::Pattern(ref pattern) => {
+                if let Ok(datetime) = DateTime::parse_from_str(string, pattern) {
+                    return Some(datetime.timestamp() * SECOND + datetime.timestamp_subsec_millis() as i64);
+                }
+
+                NaiveDateTime::parse_from_str(string, pattern).ok()
+                    .or_else(|| NaiveDate::parse_from_str(string, pattern).ok().map(|date| date.and_hms(0, 0, 0)))
+            }
+        };
+
+        if let Some(local_datetime) = local_datetime {
+            if let Some(millis) = check_date_range(time_zone.to_utc(naive_to_millis(local_datetime))) {
+                return Some(millis);
+            }
+        }
+    }
+
+    None
+}
+
+
+/// Parses a date with optional date math into microseconds since epoch (as dates are indexed)
+///
+/// For example: "now-7d/d" or "2017-01-01||+1M". If round_up is set, rounding gives the last
+/// microsecond of the unit rather than the first. Returns None if the date is invalid or the
+/// math moves it out of the range of calendar dates.
+fn parse_date_math(expression: &str, formats: &[DateFormat], time_zone: DateTimeZone, now: i64, round_up: bool) -> Option<i64> {
+    let (mut millis, math) = if expression.starts_with("now") {
+        (now, &expression[3..])
+    } else {
+        let (date, math) = match expression.find("||") {
+            Some(split_at) => (&expression[..split_at], &expression[split_at + 2..]),
+            None => (expression, ""),
+        };
+
+        match parse_date(date, formats, time_zone) {
+            Some(millis) => (millis, math),
+            None => return None,
+        }
+    };
+
+    let chars = math.chars().collect::<Vec<char>>();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '+' | '-' => {
+                let sign = if chars[i] == '+' { 1 } else { -1 };
+                i += 1;
+
+                // The number defaults to 1, eg "+d"
+                let digits_start = i;
+                while i < chars.len() && chars[i].is_digit(10) {
+                    i += 1;
+                }
+
+                let amount = if i == digits_start {
+                    sign
+                } else {
+                    match chars[digits_start..i].iter().cloned().collect::<String>().parse::<i64>() {
+                        Ok(amount) => amount * sign,
+                        Err(_) => return None,
+                    }
+                };
+
+                let unit = match chars.get(i) {
+                    Some(unit) => *unit,
+                    None => return None,
+                };
+                i += 1;
+
+                let moved_millis = match unit {
+                    'y' | 'M' => {
+                        // Calendar units are added in the requested time zone
+                        let months = if unit == 'y' { amount.checked_mul(12) } else { Some(amount) };
+                        match (millis_to_naive(time_zone.to_local(millis)), months) {
+                            (Some(datetime), Some(months)) => add_months(datetime, months).map(|datetime| time_zone.to_utc(naive_to_millis(datetime))),
+                            _ => None,
+                        }
+                    }
+                    'w' => add_units(millis, amount, 7 * DAY),
+                    'd' => add_units(millis, amount, DAY),
+                    'h' | 'H' => add_units(millis, amount, HOUR),
+                    'm' => add_units(millis, amount, MINUTE),
+                    's' => add_units(millis, amount, SECOND),
+                    _ => return None,
+                };
+
+                millis = match moved_millis.and_then(check_date_range) {
+                    Some(millis) => millis,
+                    None => return None,
+                };
+            }
+            '/' => {
+                // Rounding must come last
+                if i + 2 != chars.len() {
+                    return None;
+                }
+
+                let unit = match chars[i + 1] {
+                    'y' => CalendarUnit::Year,
+                    'M' => CalendarUnit::Month,
+                    'w' => CalendarUnit::Week,
+                    'd' => CalendarUnit::Day,
+                    'h' | 'H' => CalendarUnit::Hour,
+                    'm' => CalendarUnit::Minute,
+                    's' => CalendarUnit::Second,
+                    _ => return None,
+                };
+
+                let interval = DateInterval::Calendar(unit);
+                let start = match interval.round_down(time_zone.to_local(millis)) {
+                    Some(start) => start,
+                    None => return None,
+                };
+
+                if round_up {
+                    return interval.next(start).and_then(|next| time_zone.to_utc(next).checked_mul(1000)).and_then(|micros| micros.checked_sub(1));
+                } else {
+                    return time_zone.to_utc(start).checked_mul(1000);
+                }
+            }
+            _ => return None,
+        }
+    }
+
+    millis.checked_mul(1000)
+}
+
+
+impl RangeQueryBuilder {
+    /// Converts a bound into the number that terms of the field are encoded from
+    fn resolve_bound(&self, bound: &Bound, data_type: &FieldType, round_up: bool) -> Option<i64> {
+        match *data_type {
+            FieldType::Integer => {
+                match bound.value {
+                    Json::Number(ref number) => number.as_i64(),
+                    Json::String(ref string) => string.parse::<i64>().ok(),
+                    _ => None,
+                }
+            }
+            FieldType::Date => {
+                match bound.value {
+                    // Numbers are milliseconds since epoch
+                    Json::Number(ref number) => number.as_i64().and_then(|millis| millis.checked_mul(1000)),
+                    Json::String(ref string) => {
+                        let now = datetime_to_millis(&Utc::now());
+                        parse_date_math(string, &self.formats, self.time_zone, now, round_up)
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+
+impl QueryBuilder for RangeQueryBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Result<Query, QueryBuildError> {
+        let field_mapping = match context.index_metadata.and_then(|index_metadata| index_metadata.get_field_mapping(&self.field)) {
+            Some(field_mapping) => field_mapping,
+            None => return Ok(Query::None),
+        };
+
+        // Only integers and dates are indexed with sortable terms
+        match field_mapping.data_type {
+            FieldType::Integer | FieldType::Date => {}
+            _ => return Err(QueryBuildError::UnsupportedFieldType(self.field.clone())),
+        }
+
+        if schema.get_field_by_name(&self.field).is_none() {
+            return Ok(Query::None);
+        }
+
+        // Fields mapped before sortable terms were added don't have a field for them
+        let field_ref = match schema.get_field_by_name(&sortable_field_name(&self.field)) {
+            Some(field_ref) => field_ref,
+            None => return Err(QueryBuildError::NoSortableTerms(self.field.clone())),
+        };
+
+        // Work out the range of values to find, both ends are inclusive
+        // Rounded dates are rounded up when the end of the unit is what's being compared (gt and lte)
+        let lower = match self.lower {
+            Some(ref bound) => {
+                match self.resolve_bound(bound, &field_mapping.data_type, !bound.inclusive) {
+                    Some(value) if bound.inclusive => value,
+                    Some(value) => {
+                        match value.checked_add(1) {
+                            Some(value) => value,
+                            None => return Ok(Query::None),
+                        }
+                    }
+                    None => return Err(QueryBuildError::InvalidFieldValue(self.field.clone())),
+                }
+            }
+            None => i64::MIN,
+        };
+
+        let upper = match self.upper {
+            Some(ref bound) => {
+                match self.resolve_bound(bound, &field_mapping.data_type, bound.inclusive) {
+                    Some(value) if bound.inclusive => value,
+                    Some(value) => {
+                        match value.checked_sub(1) {
+                            Some(value) => value,
+                            None => return Ok(Query::None),
+                        }
+                    }
+                    None => return Err(QueryBuildError::InvalidFieldValue(self.field.clone())),
+                }
+            }
+            None => i64::MAX,
+        };
+
+        // Every match gets the same score
+        match build_sortable_range_query(field_ref, lower, upper) {
+            Query::None => Ok(Query::None),
+            range_query => {
+                Ok(Query::Filter {
+                    query: Box::new(Query::All { score: self.boost }),
+                    filter: Box::new(range_query),
+                })
+            }
+        }
+    }
+}
+
+
+fn parse_bound_value(json: &Json) -> Result<Json, QueryParseError> {
+    match *json {
+        Json::Number(ref number) if number.is_i64() => Ok(json.clone()),
+        Json::String(_) => Ok(json.clone()),
+        _ => Err(QueryParseError::InvalidValue),
+    }
+}
+
+
+/// Checks that a string bound is either an integer or a date
+///
+/// Which of these it must be depends on the type of the field, which isn't known until the
+/// query is built
+fn check_string_bound(bound: &Bound, formats: &[DateFormat], time_zone: DateTimeZone) -> Result<(), QueryParseError> {
+    match bound.value {
+        Json::String(ref string) => {
+            if string.parse::<i64>().is_ok() || parse_date_math(string, formats, time_zone, 0, false).is_some() {
+                Ok(())
+            } else {
+                Err(QueryParseError::InvalidValue)
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let field_name = if object.len() == 1 {
+        object.keys().collect::<Vec<_>>()[0]
+    } else {
+        return Err(QueryParseError::ExpectedSingleKey)
+    };
+
+    let inner_object = object.get(field_name).unwrap().as_object().ok_or(QueryParseError::ExpectedObject.in_key(field_name))?;
+
+    let mut lower = None;
+    let mut upper = None;
+    let mut formats = Vec::new();
+    let mut time_zone = DateTimeZone::Fixed(0);
+    let mut boost = 1.0f32;
+
+    for (key, value) in inner_object.iter() {
+        match key.as_ref() {
+            "gt" | "gte" => {
+                lower = Some(Bound {
+                    value: parse_bound_value(value).map_err(|error| error.in_key(key).in_key(field_name))?,
+                    inclusive: key == "gte",
+                });
+            }
+            "lt" | "lte" => {
+                upper = Some(Bound {
+                    value: parse_bound_value(value).map_err(|error| error.in_key(key).in_key(field_name))?,
+                    inclusive: key == "lte",
+                });
+            }
+            "format" => {
+                let format = parse_string(value).map_err(|error| error.in_key("format").in_key(field_name))?;
+
+                // Multiple formats may be given, eg "dd/MM/yyyy||yyyy"
+                for format in format.split("||") {
+                    formats.push(parse_format(format).map_err(|_| QueryParseError::InvalidValue.in_key("format").in_key(field_name))?);
+                }
+            }
+            "time_zone" => {
+                let time_zone_name = parse_string(value).map_err(|error| error.in_key("time_zone").in_key(field_name))?;
+                time_zone = parse_time_zone(&time_zone_name).map_err(|_| QueryParseError::InvalidValue.in_key("time_zone").in_key(field_name))?;
+            }
+            "boost" => {
+                boost = parse_float(value).map_err(|error| error.in_key("boost").in_key(field_name))?;
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()).in_key(field_name))
+        }
+    }
+
+    // Formats may come after the bounds, so these are checked once everything has been read
+    if let Some(ref bound) = lower {
+        check_string_bound(bound, &formats, time_zone).map_err(|error| error.in_key(if bound.inclusive { "gte" } else { "gt" }).in_key(field_name))?;
+    }
+
+    if let Some(ref bound) = upper {
+        check_string_bound(bound, &formats, time_zone).map_err(|error| error.in_key(if bound.inclusive { "lte" } else { "lt" }).in_key(field_name))?;
+    }
+
+    Ok(Box::new(RangeQueryBuilder {
+        field: field_name.clone(),
+        lower: lower,
+        upper: upper,
+        formats: formats,
+        time_zone: time_zone,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::i64;
+
+    use chrono::{DateTime, Utc};
+    use kite::{Term, Query, TermScorer, MultiTermSelector};
+    use kite::schema::{Schema, FieldType, FIELD_INDEXED};
+
+    use chrono_tz::Europe::London;
+
+    use mapping::{self, Mapping, MappingProperty, FieldMapping, sortable_key, sortable_term};
+    use index::metadata::IndexMetadata;
+    use search::aggregations::date_histogram::{DateFormat, DateTimeZone, HOUR, datetime_to_millis};
+    use query_parser::{QueryBuildContext, QueryParseError, QueryBuildError};
+
+    use super::{parse, sortable_prefixes, parse_date_math};
+
+    const UTC: DateTimeZone = DateTimeZone::Fixed(0);
+
+    fn micros(date: &str) -> i64 {
+        datetime_to_millis(&date.parse::<DateTime<Utc>>().unwrap()) * 1000
+    }
+
+    #[test]
+    fn test_sortable_terms_are_ordered() {
+        let values = [i64::MIN, -100, -1, 0, 1, 15, 16, 100, i64::MAX];
+
+        for pair in values.windows(2) {
+            assert!(sortable_key(pair[0]) < sortable_key(pair[1]));
+            assert!(sortable_term(pair[0]).as_bytes() < sortable_term(pair[1]).as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_sortable_prefixes() {
+        let key = sortable_key(0);
+
+        assert_eq!(sortable_prefixes(key, key), vec!["8000000000000000".to_string()]);
+        assert_eq!(sortable_prefixes(key, key + 15), vec!["800000000000000".to_string()]);
+        assert_eq!(sortable_prefixes(key + 14, key + 33), vec![
+            "800000000000000e".to_string(),
+            "800000000000000f".to_string(),
+            "800000000000001".to_string(),
+            "8000000000000020".to_string(),
+            "8000000000000021".to_string(),
+        ]);
+        assert_eq!(sortable_prefixes(0, !0), vec!["".to_string()]);
+        assert_eq!(sortable_prefixes(key, !0), vec!["8".to_string(), "9".to_string(), "a".to_string(), "b".to_string(),
+                                                    "c".to_string(), "d".to_string(), "e".to_string(), "f".to_string()]);
+    }
+
+    #[test]
+    fn test_sortable_prefixes_are_bounded() {
+        assert!(sortable_prefixes(1, !0 - 1).len() <= 2 * 15 * 16);
+        assert!(sortable_prefixes(sortable_key(-123456789), sortable_key(987654321)).len() <= 2 * 15 * 16);
+    }
+
+    #[test]
+    fn test_parse_date_math() {
+        let now = micros("2017-03-15T12:30:00Z") / 1000;
+
+        assert_eq!(parse_date_math("now", &[], UTC, now, false), Some(micros("2017-03-15T12:30:00Z")));
+        assert_eq!(parse_date_math("now-7d", &[], UTC, now, false), Some(micros("2017-03-08T12:30:00Z")));
+        assert_eq!(parse_date_math("now-1M/d", &[], UTC, now, false), Some(micros("2017-02-15T00:00:00Z")));
+        assert_eq!(parse_date_math("now/d", &[], UTC, now, true), Some(micros("2017-03-16T00:00:00Z") - 1));
+        assert_eq!(parse_date_math("2017-01-31||+1M", &[], UTC, now, false), Some(micros("2017-02-28T00:00:00Z")));
+        assert_eq!(parse_date_math("2017-01-01T10:00:00+02:00", &[], UTC, now, false), Some(micros("2017-01-01T08:00:00Z")));
+        assert_eq!(parse_date_math("now-1d/", &[], UTC, now, false), None);
+        assert_eq!(parse_date_math("yesterday", &[], UTC, now, false), None);
+    }
+
+    #[test]
+    fn test_parse_date_math_overflow() {
+        let now = micros("2017-03-15T12:30:00Z") / 1000;
+        let formats = vec![DateFormat::EpochMillis];
+
+        assert_eq!(parse_date_math("now+9999999999999d", &[], UTC, now, false), None);
+        assert_eq!(parse_date_math("now-9223372036854775807s", &[], UTC, now, false), None);
+        assert_eq!(parse_date_math("now+999999999999999999y", &[], UTC, now, false), None);
+        assert_eq!(parse_date_math("now+99999999999M/d", &[], UTC, now, true), None);
+        assert_eq!(parse_date_math("9223372036854775807", &formats, UTC, now, false), None);
+        assert_eq!(parse_date_math("-9223372036854775808||/d", &formats, DateTimeZone::Fixed(-HOUR), now, false), None);
+    }
+
+    #[test]
+    fn test_parse_date_math_time_zone() {
+        let now = micros("2017-03-15T23:30:00Z") / 1000;
+
+        assert_eq!(parse_date_math("2017-03-15", &[], DateTimeZone::Fixed(HOUR), now, false), Some(micros("2017-03-14T23:00:00Z")));
+        assert_eq!(parse_date_math("now/d", &[], DateTimeZone::Fixed(HOUR), now, false), Some(micros("2017-03-15T23:00:00Z")));
+
+        // British Summer Time starts on 2017-03-26
+        let london = DateTimeZone::Named(London);
+        assert_eq!(parse_date_math("2017-03-25", &[], london, now, false), Some(micros("2017-03-25T00:00:00Z")));
+        assert_eq!(parse_date_math("2017-03-27", &[], london, now, false), Some(micros("2017-03-26T23:00:00Z")));
+    }
+
+    #[test]
+    fn test_parse_date_math_format() {
+        let formats = vec![DateFormat::Pattern("%d/%m/%Y".to_string()), DateFormat::EpochMillis];
+
+        assert_eq!(parse_date_math("15/03/2017", &formats, UTC, 0, false), Some(micros("2017-03-15T00:00:00Z")));
+        assert_eq!(parse_date_math("1489536000000", &formats, UTC, 0, false), Some(micros("2017-03-15T00:00:00Z")));
+        assert_eq!(parse_date_math("2017-03-15", &formats, UTC, 0, false), None);
+    }
+
+    #[test]
+    fn test_range_query() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::I64, FIELD_INDEXED).unwrap();
+        let sortable_field = schema.add_field("_sortable:test".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+
+        let mut field_mapping = FieldMapping::default();
+        field_mapping.data_type = mapping::FieldType::Integer;
+        field_mapping.index_ref = Some(test_field);
+        field_mapping.sortable_ref = Some(sortable_field);
+
+        let mut index_metadata = IndexMetadata::default();
+        index_metadata.mappings.insert("test".to_string(), Mapping {
+            properties: hashmap! {
+                "test".to_string() => MappingProperty::Field(field_mapping),
+            },
+        });
+
+        let query = parse(&json!({
+            "test": {
+                "gte": 14,
+                "lt": 34,
+                "boost": 2.0
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new().set_index_metadata(&index_metadata), &schema).unwrap()));
+
+        let term_query = |suffix: &str| Query::Term {
+            field: sortable_field,
+            term: Term::from_string(&format!("_sortable:{}", suffix)),
+            scorer: TermScorer::default(),
+        };
+
+        assert_eq!(query, Ok(Query::Filter {
+            query: Box::new(Query::All { score: 2.0f32 }),
+            filter: Box::new(Query::Disjunction {
+                queries: vec![
+                    term_query("800000000000000e"),
+                    term_query("800000000000000f"),
+                    Query::MultiTerm {
+                        field: sortable_field,
+                        term_selector: MultiTermSelector::Prefix("_sortable:800000000000001".to_string()),
+                        scorer: TermScorer::default(),
+                    },
+                    term_query("8000000000000020"),
+                    term_query("8000000000000021"),
+                ],
+            }),
+        }));
+    }
+
+    #[test]
+    fn test_unmapped_field() {
+        let mut schema = Schema::new();
+        schema.add_field("test".to_string(), FieldType::I64, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "test": {
+                "gte": 10,
+                "lt": 50
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
+
+        assert_eq!(query, Ok(Query::None))
+    }
+
+    #[test]
+    fn test_gives_error_for_unsupported_field_type() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let mut field_mapping = FieldMapping::default();
+        field_mapping.data_type = mapping::FieldType::String;
+        field_mapping.index_ref = Some(test_field);
+
+        let mut index_metadata = IndexMetadata::default();
+        index_metadata.mappings.insert("test".to_string(), Mapping {
+            properties: hashmap! {
+                "test".to_string() => MappingProperty::Field(field_mapping),
+            },
+        });
+
+        let builder = parse(&json!({
+            "test": {
+                "gte": 10
+            }
+        })).unwrap();
+
+        let query = builder.build(&QueryBuildContext::new().set_index_metadata(&index_metadata), &schema);
+
+        assert_eq!(query, Err(QueryBuildError::UnsupportedFieldType("test".to_string())));
+    }
+
+    #[test]
+    fn test_gives_error_for_field_without_sortable_terms() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::I64, FIELD_INDEXED).unwrap();
+
+        let mut field_mapping = FieldMapping::default();
+        field_mapping.data_type = mapping::FieldType::Integer;
+        field_mapping.index_ref = Some(test_field);
+
+        let mut index_metadata = IndexMetadata::default();
+        index_metadata.mappings.insert("test".to_string(), Mapping {
+            properties: hashmap! {
+                "test".to_string() => MappingProperty::Field(field_mapping),
+            },
+        });
+
+        let builder = parse(&json!({
+            "test": {
+                "gte": 10
+            }
+        })).unwrap();
+
+        let query = builder.build(&QueryBuildContext::new().set_index_metadata(&index_metadata), &schema);
+
+        assert_eq!(query, Err(QueryBuildError::NoSortableTerms("test".to_string())));
+    }
+
+    #[test]
+    fn test_gives_error_for_date_bound_on_integer_field() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::I64, FIELD_INDEXED).unwrap();
+        let sortable_field = schema.add_field("_sortable:test".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+
+        let mut field_mapping = FieldMapping::default();
+        field_mapping.data_type = mapping::FieldType::Integer;
+        field_mapping.index_ref = Some(test_field);
+        field_mapping.sortable_ref = Some(sortable_field);
+
+        let mut index_metadata = IndexMetadata::default();
+        index_metadata.mappings.insert("test".to_string(), Mapping {
+            properties: hashmap! {
+                "test".to_string() => MappingProperty::Field(field_mapping),
+            },
+        });
+
+        let builder = parse(&json!({
+            "test": {
+                "gte": "now-1d"
+            }
+        })).unwrap();
+
+        let query = builder.build(&QueryBuildContext::new().set_index_metadata(&index_metadata), &schema);
+
+        assert_eq!(query, Err(QueryBuildError::InvalidFieldValue("test".to_string())));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_bound() {
+        let query = parse(&json!({
+            "test": {
+                "gte": [10]
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue.in_key("gte").in_key("test")));
+
+        // Bounds must be integers or dates
+        let query = parse(&json!({
+            "test": {
+                "lt": 10.5
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue.in_key("lt").in_key("test")));
+
+        let query = parse(&json!({
+            "test": {
+                "gt": "yesterday"
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue.in_key("gt").in_key("test")));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_time_zone() {
+        let query = parse(&json!({
+            "test": {
+                "gte": "now-1d",
+                "time_zone": "Mars/Olympus_Mons"
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue.in_key("time_zone").in_key("test")));
+    }
+
+    #[test]
+    fn test_gives_error_for_unexpected_key() {
+        let query = parse(&json!({
+            "test": {
+                "gte": 10,
+                "foo": "bar"
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("foo".to_string()).in_key("test")));
+    }
+
+    #[test]
+    fn test_gives_error_for_incorrect_type() {
+        // String
+        let query = parse(&json!("hello"));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedObject));
+
+        // Field value isn't an object
+        let query = parse(&json!({"test": 10}));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedObject.in_key("test")));
+    }
+}
@@ -4,7 +4,7 @@ use serde_json::Value as Json;
 use kite::{Term, Query, TermScorer};
 use kite::schema::Schema;
 
-use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, QueryBuildError};
 use query_parser::utils::{parse_float, json_value_to_term};
 
 
@@ -17,7 +17,7 @@ struct TermQueryBuilder {
 
 
 impl QueryBuilder for TermQueryBuilder {
-    fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Query {
+    fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Result<Query, QueryBuildError> {
         let query = Query::Term {
             field: schema.get_field_by_name(&self.field).unwrap(),
             term: self.term.clone(),
@@ -25,7 +25,7 @@ impl QueryBuilder for TermQueryBuilder {
         };
 
         // Add boost
-        query.boost(self.boost)
+        Ok(query.boost(self.boost))
     }
 }
 
@@ -53,13 +53,13 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
                         term = json_value_to_term(val);
 
                         if term == None {
-                            return Err(QueryParseError::InvalidValue);
+                            return Err(QueryParseError::InvalidValue.in_key("value").in_key(field_name));
                         }
                     }
                     "boost" => {
-                        boost = parse_float(val)?;
+                        boost = parse_float(val).map_err(|error| error.in_key("boost").in_key(field_name))?;
                     }
-                    _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+                    _ => return Err(QueryParseError::UnrecognisedKey(key.clone()).in_key(field_name))
                 }
             }
         }
@@ -74,7 +74,7 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
                 boost: boost,
             }))
         }
-        None => Err(QueryParseError::ExpectedKey("value"))
+        None => Err(QueryParseError::ExpectedKey("value").in_key(field_name))
     }
 }
 
@@ -101,7 +101,7 @@ mod tests {
                 \"value\": \"bar\"
             }
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::Term {
             field: foo_field,
@@ -121,7 +121,7 @@ mod tests {
                 \"value\": 123
             }
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::Term {
             field: foo_field,
@@ -139,7 +139,7 @@ mod tests {
         {
             \"foo\": \"bar\"
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::Term {
             field: foo_field,
@@ -160,7 +160,7 @@ mod tests {
                 \"boost\": 2.0
             }
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::Term {
             field: foo_field,
@@ -181,7 +181,7 @@ mod tests {
                 \"boost\": 2
             }
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::Term {
             field: foo_field,
@@ -228,7 +228,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat.in_key("boost").in_key("foo")));
 
         // Array
         let query = parse(&serde_json::from_str("
@@ -240,7 +240,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat.in_key("boost").in_key("foo")));
 
         // Object
         let query = parse(&serde_json::from_str("
@@ -254,7 +254,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat.in_key("boost").in_key("foo")));
     }
 
     #[test]
@@ -266,7 +266,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("value")));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("value").in_key("foo")));
     }
 
     #[test]
@@ -294,6 +294,6 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("hello".to_string())));
+        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("hello".to_string()).in_key("foo")));
     }
 }
@@ -4,7 +4,7 @@ use serde_json::Value as Json;
 use kite::{Term, Query, TermScorer};
 use kite::schema::Schema;
 
-use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, QueryBuildError};
 use query_parser::utils::json_value_to_term;
 
 #[derive(Debug)]
@@ -15,7 +15,7 @@ struct TermsQueryBuilder {
 
 
 impl QueryBuilder for TermsQueryBuilder {
-    fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Query {
+    fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Result<Query, QueryBuildError> {
         // Create a term query for each token
         let mut queries = Vec::new();
         for term in self.terms.iter() {
@@ -26,7 +26,7 @@ impl QueryBuilder for TermsQueryBuilder {
             });
         }
 
-        Query::Disjunction { queries: queries }
+        Ok(Query::Disjunction { queries: queries })
     }
 }
 
@@ -44,7 +44,7 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     let terms: Vec<Term> = if let &Json::Array(ref arr) = object.get(field_name).unwrap() {
         arr.iter().filter_map(|term| json_value_to_term(&term)).collect()
     } else {
-        return Err(QueryParseError::ExpectedArray);
+        return Err(QueryParseError::ExpectedArray.in_key(field_name));
     };
 
     Ok(Box::new(TermsQueryBuilder {
@@ -74,7 +74,7 @@ mod tests {
         {
             \"foo\": [\"bar\", \"baz\"]
         }
-        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema).unwrap()));
 
         assert_eq!(query, Ok(Query::Disjunction {
             queries: vec![
@@ -129,7 +129,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedArray));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedArray.in_key("foo")));
 
         // String
         let query = parse(&serde_json::from_str("
@@ -138,7 +138,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedArray));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedArray.in_key("foo")));
     }
 
     #[test]
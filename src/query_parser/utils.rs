@@ -1,3 +1,5 @@
+use std::u32;
+
 use serde_json::Value as Json;
 use kite::term::Term;
 
@@ -25,6 +27,51 @@ pub fn parse_float(json: &Json) -> Result<f32, QueryParseError> {
 }
 
 
+pub fn parse_positive_integer(json: &Json) -> Result<u64, QueryParseError> {
+    match *json {
+        Json::Number(ref number) => number.as_u64().ok_or(QueryParseError::ExpectedPositiveInteger),
+        _ => Err(QueryParseError::ExpectedPositiveInteger),
+    }
+}
+
+
+/// Parses the number of moves that the terms of a phrase may be out of place by
+pub fn parse_slop(json: &Json) -> Result<u32, QueryParseError> {
+    let slop = parse_positive_integer(json)?;
+    if slop > u32::MAX as u64 {
+        return Err(QueryParseError::InvalidValue);
+    }
+
+    Ok(slop as u32)
+}
+
+
+/// The most terms that a query may be expanded into
+///
+/// This matches Elasticsearch's default limit on the number of clauses in a query
+pub const MAX_EXPANSIONS: usize = 1024;
+
+
+/// Parses the number of terms that a query may be expanded into, up to MAX_EXPANSIONS
+pub fn parse_max_expansions(json: &Json) -> Result<usize, QueryParseError> {
+    let max_expansions = parse_positive_integer(json)?;
+    if max_expansions > MAX_EXPANSIONS as u64 {
+        return Err(QueryParseError::InvalidValue);
+    }
+
+    Ok(max_expansions as usize)
+}
+
+
+pub fn parse_boolean(json: &Json) -> Result<bool, QueryParseError> {
+    match *json {
+        Json::Bool(value) => Ok(value),
+        _ => Err(QueryParseError::ExpectedBoolean),
+    }
+}
+
+
+
 #[derive(Debug)]
 pub enum Operator {
     Or,
@@ -75,3 +122,130 @@ pub fn json_value_to_term(json: &Json) -> Option<Term> {
         &Json::Object(_) => None,
     }
 }
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MinimumShouldMatch {
+    Absolute(i64),
+    Percentage(f32),
+}
+
+
+impl MinimumShouldMatch {
+    /// Works out how many of the optional clauses must match
+    ///
+    /// Negative values give the number of clauses that may be missing
+    pub fn resolve(&self, num_clauses: usize) -> usize {
+        let num_clauses = num_clauses as i64;
+
+        let required = match *self {
+            MinimumShouldMatch::Absolute(value) => {
+                if value < 0 { num_clauses + value } else { value }
+            }
+            MinimumShouldMatch::Percentage(percentage) => {
+                let value = (num_clauses as f32 * percentage.abs() / 100.0).floor() as i64;
+                if percentage < 0.0 { num_clauses - value } else { value }
+            }
+        };
+
+        if required < 0 { 0 } else { required as usize }
+    }
+}
+
+
+pub fn parse_minimum_should_match(json: &Json) -> Result<MinimumShouldMatch, QueryParseError> {
+    match *json {
+        Json::Number(ref number) => {
+            match number.as_i64() {
+                Some(value) => Ok(MinimumShouldMatch::Absolute(value)),
+                None => Err(QueryParseError::InvalidValue),
+            }
+        }
+        Json::String(ref string) => {
+            let string = string.trim();
+
+            if string.ends_with('%') {
+                match string[..string.len() - 1].parse::<f32>() {
+                    Ok(percentage) => Ok(MinimumShouldMatch::Percentage(percentage)),
+                    Err(_) => Err(QueryParseError::InvalidValue),
+                }
+            } else {
+                match string.parse::<i64>() {
+                    Ok(value) => Ok(MinimumShouldMatch::Absolute(value)),
+                    Err(_) => Err(QueryParseError::InvalidValue),
+                }
+            }
+        }
+        _ => Err(QueryParseError::InvalidValue),
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fuzziness {
+    /// Number of edits allowed depends on the length of the term. Terms shorter than the first
+    /// value must match exactly, terms shorter than the second value may have one edit and longer
+    /// terms may have two
+    Auto(usize, usize),
+    Edits(u32),
+}
+
+
+impl Fuzziness {
+    /// Works out how many edits are allowed for a term of the given length (in characters)
+    pub fn max_edits(&self, term_length: usize) -> u32 {
+        match *self {
+            Fuzziness::Auto(low, high) => {
+                if term_length < low {
+                    0
+                } else if term_length < high {
+                    1
+                } else {
+                    2
+                }
+            }
+            Fuzziness::Edits(edits) => edits,
+        }
+    }
+}
+
+
+pub fn parse_fuzziness(json: &Json) -> Result<Fuzziness, QueryParseError> {
+    let edits = match *json {
+        Json::Number(ref number) => {
+            match number.as_u64() {
+                Some(edits) => edits,
+                None => return Err(QueryParseError::InvalidValue),
+            }
+        }
+        Json::String(ref string) => {
+            let string = string.trim();
+
+            if string == "AUTO" {
+                return Ok(Fuzziness::Auto(3, 6));
+            }
+
+            if string.starts_with("AUTO:") {
+                let mut bounds = string[5..].split(',').map(|bound| bound.trim().parse::<usize>());
+
+                return match (bounds.next(), bounds.next(), bounds.next()) {
+                    (Some(Ok(low)), Some(Ok(high)), None) if low <= high => Ok(Fuzziness::Auto(low, high)),
+                    _ => Err(QueryParseError::InvalidValue),
+                };
+            }
+
+            match string.parse::<u64>() {
+                Ok(edits) => edits,
+                Err(_) => return Err(QueryParseError::InvalidValue),
+            }
+        }
+        _ => return Err(QueryParseError::InvalidValue),
+    };
+
+    // Like Elasticsearch, no more than two edits are allowed
+    if edits > 2 {
+        return Err(QueryParseError::InvalidValue);
+    }
+
+    Ok(Fuzziness::Edits(edits as u32))
+}
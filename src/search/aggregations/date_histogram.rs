@@ -0,0 +1,777 @@
+//! Parses and runs "date_histogram" aggregations
+//!
+//! Groups the values of a date field into buckets of either a calendar unit (month, day, etc)
+//! or a fixed length of time. Time zones may be fixed offsets (eg, "+01:00") or names from the
+//! tz database (eg, "Europe/London"), in which case buckets follow daylight saving changes.
+//!
+//! Bucket keys are milliseconds since epoch (UTC) of the start of the bucket in the requested
+//! time zone.
+
+use std::collections::BTreeMap;
+
+use serde_json::Value as Json;
+use chrono::{DateTime, Utc, FixedOffset, NaiveDate, NaiveDateTime, Datelike, TimeZone, Offset};
+use chrono::naive::{MIN_DATE, MAX_DATE};
+use chrono_tz::Tz;
+use kite::document::{DocRef, FieldValue};
+use kite::schema::{Schema, FieldRef};
+
+use index::metadata::IndexMetadata;
+use mapping::FieldType;
+use search::aggregations::{AggregationBuilder, Aggregator, StoredFieldReader, AggregationParseError, Bucket, SubAggregations, resolve_field};
+use search::aggregations::histogram::{round_down, bucket_keys, parse_extended_bounds};
+
+
+pub const SECOND: i64 = 1000;
+pub const MINUTE: i64 = 60 * SECOND;
+pub const HOUR: i64 = 60 * MINUTE;
+pub const DAY: i64 = 24 * HOUR;
+
+/// The longest fixed interval, about a million years
+///
+/// This is longer than the range of dates that can be represented, and keeps bucket keys far
+/// enough from the limits of i64 that time zone offsets can be applied to them.
+pub const MAX_FIXED_INTERVAL: i64 = 1000000 * 365 * DAY;
+
+
+/// Returns None if the date is out of the range of calendar dates
+pub fn millis_to_naive(millis: i64) -> Option<NaiveDateTime> {
+    round_down(millis, SECOND, 0).and_then(|seconds| {
+        NaiveDateTime::from_timestamp_opt(seconds / SECOND, ((millis - seconds) * 1000000) as u32)
+    })
+}
+
+
+pub fn naive_to_millis(datetime: NaiveDateTime) -> i64 {
+    datetime.timestamp() * SECOND + datetime.timestamp_subsec_millis() as i64
+}
+
+
+pub fn datetime_to_millis(datetime: &DateTime<Utc>) -> i64 {
+    datetime.timestamp() * SECOND + datetime.timestamp_subsec_millis() as i64
+}
+
+
+fn add_months(date: NaiveDate, months: i32) -> Option<NaiveDate> {
+    let total_months = date.year() * 12 + date.month0() as i32 + months;
+    NaiveDate::from_ymd_opt(total_months / 12, (total_months % 12) as u32 + 1, 1)
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DateTimeZone {
+    /// Offset from UTC in milliseconds
+    Fixed(i64),
+    Named(Tz),
+}
+
+
+impl DateTimeZone {
+    /// Finds the offset from UTC (in milliseconds) at the given time
+    pub fn offset(&self, millis: i64) -> i64 {
+        match *self {
+            DateTimeZone::Fixed(offset) => offset,
+            DateTimeZone::Named(tz) => {
+                // Dates out of range take the offset of the nearest date in range
+                let datetime = millis_to_naive(millis).unwrap_or_else(|| {
+                    if millis < 0 { MIN_DATE.and_hms(0, 0, 0) } else { MAX_DATE.and_hms(23, 59, 59) }
+                });
+
+                tz.offset_from_utc_datetime(&datetime).fix().local_minus_utc() as i64 * SECOND
+            }
+        }
+    }
+
+    /// Converts milliseconds since epoch into the local time
+    pub fn to_local(self, millis: i64) -> i64 {
+        millis + self.offset(millis)
+    }
+
+    /// Converts a local time back into milliseconds since epoch
+    ///
+    /// Local times that are skipped when the clocks go forward are moved forward by the same amount
+    pub fn to_utc(self, local_millis: i64) -> i64 {
+        let estimate = local_millis - self.offset(local_millis);
+        local_millis - self.offset(estimate)
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CalendarUnit {
+    Year,
+    Quarter,
+    Month,
+    Week,
+    Day,
+    Hour,
+    Minute,
+    Second,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DateInterval {
+    Calendar(CalendarUnit),
+
+    /// A fixed number of milliseconds
+    Fixed(i64),
+}
+
+
+impl DateInterval {
+    /// Rounds a local time (in milliseconds since epoch) down to the start of its bucket
+    ///
+    /// Returns None if the start of the bucket is out of range
+    pub fn round_down(&self, millis: i64) -> Option<i64> {
+        let unit = match *self {
+            DateInterval::Calendar(unit) => unit,
+            DateInterval::Fixed(interval) => return round_down(millis, interval, 0),
+        };
+
+        let date = match millis_to_naive(millis) {
+            Some(datetime) => datetime.date(),
+            None => return None,
+        };
+
+        match unit {
+            CalendarUnit::Year => NaiveDate::from_ymd_opt(date.year(), 1, 1).map(|date| naive_to_millis(date.and_hms(0, 0, 0))),
+            CalendarUnit::Quarter => NaiveDate::from_ymd_opt(date.year(), (date.month0() / 3) * 3 + 1, 1).map(|date| naive_to_millis(date.and_hms(0, 0, 0))),
+            CalendarUnit::Month => NaiveDate::from_ymd_opt(date.year(), date.month(), 1).map(|date| naive_to_millis(date.and_hms(0, 0, 0))),
+            CalendarUnit::Week => round_down(millis, DAY, 0).and_then(|day| day.checked_sub(date.weekday().num_days_from_monday() as i64 * DAY)),
+            CalendarUnit::Day => round_down(millis, DAY, 0),
+            CalendarUnit::Hour => round_down(millis, HOUR, 0),
+            CalendarUnit::Minute => round_down(millis, MINUTE, 0),
+            CalendarUnit::Second => round_down(millis, SECOND, 0),
+        }
+    }
+
+    /// Finds the start of the bucket after the one that starts at the given local time
+    ///
+    /// Returns None if the next bucket is out of range
+    pub fn next(&self, key: i64) -> Option<i64> {
+        let unit = match *self {
+            DateInterval::Calendar(unit) => unit,
+            DateInterval::Fixed(interval) => return key.checked_add(interval),
+        };
+
+        let months = match unit {
+            CalendarUnit::Year => 12,
+            CalendarUnit::Quarter => 3,
+            CalendarUnit::Month => 1,
+            CalendarUnit::Week => return key.checked_add(7 * DAY),
+            CalendarUnit::Day => return key.checked_add(DAY),
+            CalendarUnit::Hour => return key.checked_add(HOUR),
+            CalendarUnit::Minute => return key.checked_add(MINUTE),
+            CalendarUnit::Second => return key.checked_add(SECOND),
+        };
+
+        millis_to_naive(key)
+            .and_then(|datetime| add_months(datetime.date(), months))
+            .map(|date| naive_to_millis(date.and_hms(0, 0, 0)))
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DateFormat {
+    /// ISO 8601 with milliseconds, the Elasticsearch default
+    Default,
+    EpochMillis,
+
+    /// A strftime pattern converted from a Joda-Time pattern
+    Pattern(String),
+}
+
+
+/// Formats milliseconds since epoch as a date with the given offset from UTC (in milliseconds)
+///
+/// Dates that are out of the range of calendar dates are formatted as milliseconds since epoch
+pub fn format_millis(millis: i64, offset: i64, format: &DateFormat) -> String {
+    let in_range = millis.checked_add(offset).and_then(millis_to_naive).is_some();
+    let naive = match millis_to_naive(millis) {
+        Some(naive) if in_range => naive,
+        _ => return millis.to_string(),
+    };
+
+    let datetime = DateTime::<FixedOffset>::from_utc(naive, FixedOffset::east((offset / SECOND) as i32));
+
+    match *format {
+        DateFormat::Default => {
+            if offset == 0 {
+                datetime.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+            } else {
+                datetime.format("%Y-%m-%dT%H:%M:%S%.3f%:z").to_string()
+            }
+        }
+        DateFormat::EpochMillis => millis.to_string(),
+        DateFormat::Pattern(ref pattern) => datetime.format(pattern).to_string(),
+    }
+}
+
+
+#[derive(Debug)]
+struct DateHistogramAggregationBuilder {
+    field: String,
+    interval: DateInterval,
+    time_zone: DateTimeZone,
+    format: DateFormat,
+    min_doc_count: u64,
+    extended_bounds: Option<(i64, i64)>,
+    sub_aggregations: Vec<(String, Box<AggregationBuilder>)>,
+}
+
+
+impl AggregationBuilder for DateHistogramAggregationBuilder {
+    fn build(&self, index_metadata: &IndexMetadata, schema: &Schema) -> Result<Box<Aggregator>, AggregationParseError> {
+        let field_ref = resolve_field(index_metadata, schema, &self.field, &[FieldType::Date])?;
+
+        Ok(Box::new(DateHistogramAggregator {
+            field_ref: field_ref,
+            interval: self.interval,
+            time_zone: self.time_zone,
+            format: self.format.clone(),
+            min_doc_count: self.min_doc_count,
+            extended_bounds: self.extended_bounds,
+            buckets: BTreeMap::new(),
+            out_of_range: false,
+            sub_aggregations: SubAggregations::build(&self.sub_aggregations, index_metadata, schema)?,
+        }))
+    }
+
+    fn set_sub_aggregations(&mut self, sub_aggregations: Vec<(String, Box<AggregationBuilder>)>) -> Result<(), AggregationParseError> {
+        self.sub_aggregations = sub_aggregations;
+        Ok(())
+    }
+}
+
+
+struct DateHistogramAggregator {
+    field_ref: FieldRef,
+    interval: DateInterval,
+    time_zone: DateTimeZone,
+    format: DateFormat,
+    min_doc_count: u64,
+    extended_bounds: Option<(i64, i64)>,
+
+    /// Keyed by the start of each bucket in UTC
+    buckets: BTreeMap<i64, Bucket>,
+
+    /// Set when a date falls into a bucket that starts out of range
+    out_of_range: bool,
+    sub_aggregations: SubAggregations,
+}
+
+
+impl DateHistogramAggregator {
+    /// Returns None if the start of the bucket is out of range
+    fn bucket_key(&self, millis: i64) -> Option<i64> {
+        self.interval.round_down(self.time_zone.to_local(millis)).map(|key| self.time_zone.to_utc(key))
+    }
+
+    /// Finds the bucket with the given key, creating it if it doesn't exist yet
+    fn bucket(&mut self, key: i64) -> &mut Bucket {
+        let sub_aggregations = &self.sub_aggregations;
+        self.buckets.entry(key).or_insert_with(|| sub_aggregations.new_bucket())
+    }
+}
+
+
+impl Aggregator for DateHistogramAggregator {
+    fn collect(&mut self, reader: &StoredFieldReader, doc_ref: DocRef) {
+        if let Some(FieldValue::DateTime(value)) = reader.read_stored_field(self.field_ref, doc_ref) {
+            match self.bucket_key(datetime_to_millis(&value)) {
+                Some(key) => self.bucket(key).collect(reader, doc_ref),
+                None => self.out_of_range = true,
+            }
+        }
+    }
+
+    fn as_json(&self) -> Result<Json, AggregationParseError> {
+        if self.out_of_range {
+            return Err(AggregationParseError::BucketOutOfRange);
+        }
+
+        let extended_bounds = match self.extended_bounds {
+            Some((min, max)) => {
+                match (self.bucket_key(min), self.bucket_key(max)) {
+                    (Some(min), Some(max)) => Some((min, max)),
+                    _ => return Err(AggregationParseError::BucketOutOfRange),
+                }
+            }
+            None => None,
+        };
+
+        let interval = self.interval;
+        let time_zone = self.time_zone;
+        let keys = bucket_keys(&self.buckets, self.min_doc_count, extended_bounds, |key| {
+            interval.next(time_zone.to_local(key)).map(|next_key| time_zone.to_utc(next_key))
+        })?;
+
+        // Used for the gaps between buckets
+        let empty_bucket = self.sub_aggregations.new_bucket();
+
+        let mut buckets_json = Vec::with_capacity(keys.len());
+        for key in keys.iter() {
+            let mut json = json!({
+                "key_as_string": format_millis(*key, self.time_zone.offset(*key), &self.format),
+                "key": key,
+            });
+
+            self.buckets.get(key).unwrap_or(&empty_bucket).add_to_json(&mut json)?;
+            buckets_json.push(json);
+        }
+
+        Ok(json!({
+            "buckets": buckets_json,
+        }))
+    }
+
+    fn new_empty(&self) -> Box<Aggregator> {
+        Box::new(DateHistogramAggregator {
+            field_ref: self.field_ref,
+            interval: self.interval,
+            time_zone: self.time_zone,
+            format: self.format.clone(),
+            min_doc_count: self.min_doc_count,
+            extended_bounds: self.extended_bounds,
+            buckets: BTreeMap::new(),
+            out_of_range: false,
+            sub_aggregations: self.sub_aggregations.new_empty(),
+        })
+    }
+}
+
+
+fn parse_interval(interval: &str) -> Result<DateInterval, AggregationParseError> {
+    let calendar_unit = match interval {
+        "year" | "1y" => Some(CalendarUnit::Year),
+        "quarter" | "1q" => Some(CalendarUnit::Quarter),
+        "month" | "1M" => Some(CalendarUnit::Month),
+        "week" | "1w" => Some(CalendarUnit::Week),
+        "day" | "1d" => Some(CalendarUnit::Day),
+        "hour" | "1h" => Some(CalendarUnit::Hour),
+        "minute" | "1m" => Some(CalendarUnit::Minute),
+        "second" | "1s" => Some(CalendarUnit::Second),
+        _ => None,
+    };
+
+    if let Some(calendar_unit) = calendar_unit {
+        return Ok(DateInterval::Calendar(calendar_unit));
+    }
+
+    // Fixed intervals, eg "90m" or "2d"
+    let unit_start = interval.find(|c: char| !c.is_digit(10)).unwrap_or(interval.len());
+    let (number, unit) = interval.split_at(unit_start);
+    let number: i64 = match number.parse() {
+        Ok(number) if number > 0 => number,
+        _ => return Err(AggregationParseError::InvalidInterval(interval.to_string())),
+    };
+
+    let unit = match unit {
+        "d" => DAY,
+        "h" => HOUR,
+        "m" => MINUTE,
+        "s" => SECOND,
+        "ms" => 1,
+        _ => return Err(AggregationParseError::InvalidInterval(interval.to_string())),
+    };
+
+    match number.checked_mul(unit) {
+        Some(interval) if interval <= MAX_FIXED_INTERVAL => Ok(DateInterval::Fixed(interval)),
+        _ => Err(AggregationParseError::InvalidInterval(interval.to_string())),
+    }
+}
+
+
+/// Parses a time zone, eg "+01:00", "-0530", "UTC" or "Europe/London"
+pub fn parse_time_zone(time_zone: &str) -> Result<DateTimeZone, AggregationParseError> {
+    match time_zone {
+        "Z" | "UTC" | "Etc/UTC" | "GMT" => return Ok(DateTimeZone::Fixed(0)),
+        _ => {}
+    }
+
+    let sign = match time_zone.chars().next() {
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => {
+            return time_zone.parse::<Tz>()
+                .map(DateTimeZone::Named)
+                .map_err(|_| AggregationParseError::InvalidTimeZone(time_zone.to_string()));
+        }
+    };
+
+    let digits = time_zone[1..].replace(":", "");
+    if !digits.chars().all(|c| c.is_digit(10)) {
+        return Err(AggregationParseError::InvalidTimeZone(time_zone.to_string()));
+    }
+
+    let (hours, minutes) = match digits.len() {
+        2 => (digits.parse::<i64>().unwrap(), 0),
+        4 => (digits[..2].parse::<i64>().unwrap(), digits[2..].parse::<i64>().unwrap()),
+        _ => return Err(AggregationParseError::InvalidTimeZone(time_zone.to_string())),
+    };
+
+    if hours > 18 || minutes >= 60 {
+        return Err(AggregationParseError::InvalidTimeZone(time_zone.to_string()));
+    }
+
+    Ok(DateTimeZone::Fixed(sign * (hours * HOUR + minutes * MINUTE)))
+}
+
+
+/// Converts a Joda-Time date pattern (as used by Elasticsearch) into a strftime pattern
+pub fn parse_format(format: &str) -> Result<DateFormat, AggregationParseError> {
+    match format {
+        "date_optional_time" | "strict_date_optional_time" => return Ok(DateFormat::Default),
+        "epoch_millis" => return Ok(DateFormat::EpochMillis),
+        _ => {}
+    }
+
+    let chars = format.chars().collect::<Vec<char>>();
+    let mut pattern = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        // Quoted text is copied as is
+        if c == '\'' {
+            i += 1;
+            while i < chars.len() && chars[i] != '\'' {
+                if chars[i] == '%' {
+                    pattern.push_str("%%");
+                } else {
+                    pattern.push(chars[i]);
+                }
+                i += 1;
+            }
+            i += 1;
+            continue;
+        }
+
+        if !c.is_alphabetic() {
+            if c == '%' {
+                pattern.push_str("%%");
+            } else {
+                pattern.push(c);
+            }
+            i += 1;
+            continue;
+        }
+
+        // Count the number of times the letter is repeated
+        let mut length = 1;
+        while i + length < chars.len() && chars[i + length] == c {
+            length += 1;
+        }
+
+        let specifier = match (c, length) {
+            ('y', 2) | ('Y', 2) | ('u', 2) => "%y",
+            ('y', _) | ('Y', _) | ('u', _) => "%Y",
+            ('M', 1) => "%-m",
+            ('M', 2) => "%m",
+            ('M', 3) => "%b",
+            ('M', _) => "%B",
+            ('d', 1) => "%-d",
+            ('d', _) => "%d",
+            ('D', _) => "%j",
+            ('H', 1) => "%-H",
+            ('H', _) => "%H",
+            ('h', 1) => "%-I",
+            ('h', _) => "%I",
+            ('m', 1) => "%-M",
+            ('m', _) => "%M",
+            ('s', 1) => "%-S",
+            ('s', _) => "%S",
+            ('a', _) => "%p",
+            ('E', length) if length <= 3 => "%a",
+            ('E', _) => "%A",
+            ('Z', 1) => "%z",
+            ('Z', _) => "%:z",
+            ('S', _) => {
+                // Fractions of a second can only be formatted along with the separator before them
+                if !pattern.ends_with('.') {
+                    return Err(AggregationParseError::InvalidFormat(format.to_string()));
+                }
+
+                pattern.pop();
+                "%.3f"
+            }
+            _ => return Err(AggregationParseError::InvalidFormat(format.to_string())),
+        };
+
+        pattern.push_str(specifier);
+        i += length;
+    }
+
+    Ok(DateFormat::Pattern(pattern))
+}
+
+
+fn parse_bound(json: &Json) -> Result<i64, AggregationParseError> {
+    match *json {
+        Json::Number(ref number) => {
+            let millis = number.as_i64().ok_or(AggregationParseError::ExpectedInteger)?;
+
+            // Must be a date that can be converted into a calendar date
+            match millis_to_naive(millis) {
+                Some(_) => Ok(millis),
+                None => Err(AggregationParseError::InvalidValue),
+            }
+        }
+        Json::String(ref string) => {
+            match string.parse::<DateTime<Utc>>() {
+                Ok(datetime) => Ok(datetime_to_millis(&datetime)),
+                Err(_) => Err(AggregationParseError::InvalidValue),
+            }
+        }
+        _ => Err(AggregationParseError::InvalidValue),
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<AggregationBuilder>, AggregationParseError> {
+    let object = json.as_object().ok_or(AggregationParseError::ExpectedObject)?;
+
+    // Get configuration
+    let mut field = None;
+    let mut interval = None;
+    let mut time_zone = DateTimeZone::Fixed(0);
+    let mut format = DateFormat::Default;
+    let mut min_doc_count = 0;
+    let mut extended_bounds = None;
+
+    for (key, value) in object.iter() {
+        match &key[..] {
+            "field" => {
+                field = Some(value.as_str().ok_or(AggregationParseError::ExpectedString)?.to_string());
+            }
+            "interval" => {
+                interval = Some(parse_interval(value.as_str().ok_or(AggregationParseError::ExpectedString)?)?);
+            }
+            "time_zone" => {
+                time_zone = parse_time_zone(value.as_str().ok_or(AggregationParseError::ExpectedString)?)?;
+            }
+            "format" => {
+                format = parse_format(value.as_str().ok_or(AggregationParseError::ExpectedString)?)?;
+            }
+            "min_doc_count" => {
+                min_doc_count = value.as_u64().ok_or(AggregationParseError::ExpectedInteger)?;
+            }
+            "extended_bounds" => {
+                extended_bounds = Some(parse_extended_bounds(value, parse_bound)?);
+            }
+            _ => return Err(AggregationParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(DateHistogramAggregationBuilder {
+        field: field.ok_or(AggregationParseError::ExpectedKey("field"))?,
+        interval: interval.ok_or(AggregationParseError::ExpectedKey("interval"))?,
+        time_zone: time_zone,
+        format: format,
+        min_doc_count: min_doc_count,
+        extended_bounds: extended_bounds,
+        sub_aggregations: Vec::new(),
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value as Json;
+    use chrono::{DateTime, Utc};
+    use kite::document::FieldValue;
+
+    use mapping::FieldType;
+    use search::aggregations::AggregationParseError;
+    use search::aggregations::testing::run_aggregation;
+
+    use chrono_tz::Europe::London;
+
+    use super::{DateInterval, DateTimeZone, CalendarUnit, DateFormat, HOUR, MINUTE, DAY,
+                datetime_to_millis, millis_to_naive, format_millis, parse_interval, parse_time_zone, parse_format};
+
+    fn millis(date: &str) -> i64 {
+        datetime_to_millis(&date.parse::<DateTime<Utc>>().unwrap())
+    }
+
+    fn run_date_histogram(date_histogram: Json, dates: &[&str]) -> Result<Json, AggregationParseError> {
+        let values = dates.iter().map(|date| FieldValue::DateTime(date.parse::<DateTime<Utc>>().unwrap())).collect();
+        run_aggregation(json!({"date_histogram": date_histogram}), "date", FieldType::Date, values)
+    }
+
+    #[test]
+    fn test_month_interval() {
+        let result = run_date_histogram(json!({
+            "field": "date",
+            "interval": "month"
+        }), &[
+            "2017-01-15T10:00:00Z",
+            "2017-01-31T23:59:59Z",
+            "2017-03-01T00:00:00Z",
+        ]);
+
+        assert_eq!(result, Ok(json!({
+            "buckets": [
+                {"key_as_string": "2017-01-01T00:00:00.000Z", "key": 1483228800000i64, "doc_count": 2},
+                {"key_as_string": "2017-02-01T00:00:00.000Z", "key": 1485907200000i64, "doc_count": 0},
+                {"key_as_string": "2017-03-01T00:00:00.000Z", "key": 1488326400000i64, "doc_count": 1},
+            ]
+        })));
+    }
+
+    #[test]
+    fn test_week_interval() {
+        // 2017-01-04 is a Wednesday, weeks start on Monday
+        let result = run_date_histogram(json!({
+            "field": "date",
+            "interval": "week"
+        }), &[
+            "2017-01-04T10:00:00Z",
+        ]);
+
+        assert_eq!(result, Ok(json!({
+            "buckets": [
+                {"key_as_string": "2017-01-02T00:00:00.000Z", "key": millis("2017-01-02T00:00:00Z"), "doc_count": 1},
+            ]
+        })));
+    }
+
+    #[test]
+    fn test_time_zone() {
+        // 23:30 UTC is the next day in +01:00
+        let result = run_date_histogram(json!({
+            "field": "date",
+            "interval": "day",
+            "time_zone": "+01:00"
+        }), &[
+            "2017-01-01T23:30:00Z",
+        ]);
+
+        assert_eq!(result, Ok(json!({
+            "buckets": [
+                {"key_as_string": "2017-01-02T00:00:00.000+01:00", "key": millis("2017-01-01T23:00:00Z"), "doc_count": 1},
+            ]
+        })));
+    }
+
+    #[test]
+    fn test_named_time_zone() {
+        // British Summer Time starts on 2017-03-26, so days start an hour earlier in UTC after that
+        let result = run_date_histogram(json!({
+            "field": "date",
+            "interval": "day",
+            "time_zone": "Europe/London"
+        }), &[
+            "2017-03-25T12:00:00Z",
+            "2017-03-26T23:30:00Z",
+        ]);
+
+        assert_eq!(result, Ok(json!({
+            "buckets": [
+                {"key_as_string": "2017-03-25T00:00:00.000Z", "key": millis("2017-03-25T00:00:00Z"), "doc_count": 1},
+                {"key_as_string": "2017-03-26T00:00:00.000Z", "key": millis("2017-03-26T00:00:00Z"), "doc_count": 0},
+                {"key_as_string": "2017-03-27T00:00:00.000+01:00", "key": millis("2017-03-26T23:00:00Z"), "doc_count": 1},
+            ]
+        })));
+    }
+
+    #[test]
+    fn test_too_many_buckets() {
+        let result = run_date_histogram(json!({
+            "field": "date",
+            "interval": "1ms"
+        }), &[
+            "2016-01-01T00:00:00Z",
+            "2017-01-01T00:00:00Z",
+        ]);
+
+        assert_eq!(result, Err(AggregationParseError::TooManyBuckets));
+    }
+
+    #[test]
+    fn test_next_overflow() {
+        assert_eq!(DateInterval::Fixed(DAY).next(i64::max_value() - 1), None);
+        assert_eq!(DateInterval::Calendar(CalendarUnit::Month).next(0), Some(millis("1970-02-01T00:00:00Z")));
+    }
+
+    #[test]
+    fn test_round_down_underflow() {
+        assert_eq!(DateInterval::Fixed(DAY).round_down(i64::min_value()), None);
+        assert_eq!(DateInterval::Calendar(CalendarUnit::Month).round_down(i64::min_value()), None);
+        assert_eq!(DateInterval::Calendar(CalendarUnit::Week).round_down(i64::min_value()), None);
+        assert_eq!(millis_to_naive(i64::min_value()), None);
+    }
+
+    #[test]
+    fn test_long_interval_before_epoch() {
+        // The bucket starts before the earliest calendar date
+        let result = run_date_histogram(json!({
+            "field": "date",
+            "interval": "300000000d"
+        }), &[
+            "1969-12-31T00:00:00Z",
+        ]);
+
+        assert_eq!(result, Ok(json!({
+            "buckets": [
+                {"key_as_string": "-25920000000000000", "key": -25920000000000000i64, "doc_count": 1},
+            ]
+        })));
+    }
+
+    #[test]
+    fn test_format_out_of_range() {
+        assert_eq!(format_millis(i64::min_value(), 0, &DateFormat::Default), i64::min_value().to_string());
+        assert_eq!(format_millis(i64::max_value(), -HOUR, &DateFormat::Default), i64::max_value().to_string());
+        assert_eq!(format_millis(0, 0, &DateFormat::Default), "1970-01-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn test_format() {
+        let result = run_date_histogram(json!({
+            "field": "date",
+            "interval": "day",
+            "format": "yyyy-MM-dd"
+        }), &[
+            "2017-01-01T10:00:00Z",
+        ]);
+
+        assert_eq!(result, Ok(json!({
+            "buckets": [
+                {"key_as_string": "2017-01-01", "key": millis("2017-01-01T00:00:00Z"), "doc_count": 1},
+            ]
+        })));
+    }
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("month"), Ok(DateInterval::Calendar(CalendarUnit::Month)));
+        assert_eq!(parse_interval("1M"), Ok(DateInterval::Calendar(CalendarUnit::Month)));
+        assert_eq!(parse_interval("1m"), Ok(DateInterval::Calendar(CalendarUnit::Minute)));
+        assert_eq!(parse_interval("90m"), Ok(DateInterval::Fixed(90 * MINUTE)));
+        assert_eq!(parse_interval("2d"), Ok(DateInterval::Fixed(2 * DAY)));
+        assert_eq!(parse_interval("fortnight"), Err(AggregationParseError::InvalidInterval("fortnight".to_string())));
+        assert_eq!(parse_interval("0d"), Err(AggregationParseError::InvalidInterval("0d".to_string())));
+        assert_eq!(parse_interval("100000000000d"), Err(AggregationParseError::InvalidInterval("100000000000d".to_string())));
+        assert_eq!(parse_interval("9223372036854775807ms"), Err(AggregationParseError::InvalidInterval("9223372036854775807ms".to_string())));
+    }
+
+    #[test]
+    fn test_parse_time_zone() {
+        assert_eq!(parse_time_zone("UTC"), Ok(DateTimeZone::Fixed(0)));
+        assert_eq!(parse_time_zone("+01:00"), Ok(DateTimeZone::Fixed(HOUR)));
+        assert_eq!(parse_time_zone("-0530"), Ok(DateTimeZone::Fixed(-(5 * HOUR + 30 * MINUTE))));
+        assert_eq!(parse_time_zone("Europe/London"), Ok(DateTimeZone::Named(London)));
+        assert_eq!(parse_time_zone("Mars/Olympus_Mons"), Err(AggregationParseError::InvalidTimeZone("Mars/Olympus_Mons".to_string())));
+    }
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!(parse_format("yyyy-MM-dd'T'HH:mm:ss.SSSZZ"), Ok(DateFormat::Pattern("%Y-%m-%dT%H:%M:%S%.3f%:z".to_string())));
+        assert_eq!(parse_format("epoch_millis"), Ok(DateFormat::EpochMillis));
+        assert_eq!(parse_format("yyyy-qq"), Err(AggregationParseError::InvalidFormat("yyyy-qq".to_string())));
+    }
+}
@@ -0,0 +1,515 @@
+//! Parses and runs "histogram" aggregations
+//!
+//! Groups the values of an integer field into fixed size buckets. When "min_doc_count" is 0,
+//! empty buckets are filled in between the lowest and highest values (or "extended_bounds").
+//! Aggregations that would return more than MAX_BUCKETS buckets give an error.
+
+use std::collections::BTreeMap;
+
+use serde_json::Value as Json;
+use kite::document::{DocRef, FieldValue};
+use kite::schema::{Schema, FieldRef};
+
+use index::metadata::IndexMetadata;
+use mapping::FieldType;
+use search::aggregations::{AggregationBuilder, Aggregator, StoredFieldReader, AggregationParseError, Bucket, SubAggregations, MAX_BUCKETS, resolve_field};
+
+
+/// Finds the key of the bucket that a value falls into
+///
+/// Values are rounded towards negative infinity so that negative values are placed into
+/// the correct bucket. Returns None if the start of the bucket is out of range.
+pub fn round_down(value: i64, interval: i64, offset: i64) -> Option<i64> {
+    let value = match value.checked_sub(offset) {
+        Some(value) => value,
+        None => return None,
+    };
+    let mut bucket = value / interval;
+
+    if value % interval < 0 {
+        bucket -= 1;
+    }
+
+    bucket.checked_mul(interval).and_then(|key| key.checked_add(offset))
+}
+
+
+/// Finds the keys of the buckets to return
+///
+/// Buckets with too few documents are removed or, if "min_doc_count" is 0, keys are added to fill
+/// the gaps between buckets. The extended bounds must already be rounded to bucket keys.
+/// "next_key" returns the key of the bucket that follows the given one or None if it overflows.
+pub fn bucket_keys<F>(buckets: &BTreeMap<i64, Bucket>, min_doc_count: u64, extended_bounds: Option<(i64, i64)>, next_key: F) -> Result<Vec<i64>, AggregationParseError>
+    where F: Fn(i64) -> Option<i64>
+{
+    if min_doc_count > 0 {
+        let keys = buckets.iter().filter(|&(_, bucket)| bucket.doc_count >= min_doc_count).map(|(key, _)| *key).collect::<Vec<i64>>();
+
+        if keys.len() > MAX_BUCKETS {
+            return Err(AggregationParseError::TooManyBuckets);
+        }
+
+        return Ok(keys);
+    }
+
+    let mut first = buckets.keys().next().cloned();
+    let mut last = buckets.keys().next_back().cloned();
+
+    if let Some((min, max)) = extended_bounds {
+        first = Some(first.map_or(min, |first| if min < first { min } else { first }));
+        last = Some(last.map_or(max, |last| if max > last { max } else { last }));
+    }
+
+    let mut keys = Vec::new();
+    if let (Some(first), Some(last)) = (first, last) {
+        let mut key = first;
+        loop {
+            if keys.len() == MAX_BUCKETS {
+                return Err(AggregationParseError::TooManyBuckets);
+            }
+
+            keys.push(key);
+            key = match next_key(key) {
+                Some(next_key) if next_key <= last => next_key,
+                _ => break,
+            };
+        }
+    }
+
+    Ok(keys)
+}
+
+
+#[derive(Debug)]
+struct HistogramAggregationBuilder {
+    field: String,
+    interval: i64,
+    offset: i64,
+    min_doc_count: u64,
+    extended_bounds: Option<(i64, i64)>,
+    sub_aggregations: Vec<(String, Box<AggregationBuilder>)>,
+}
+
+
+impl AggregationBuilder for HistogramAggregationBuilder {
+    fn build(&self, index_metadata: &IndexMetadata, schema: &Schema) -> Result<Box<Aggregator>, AggregationParseError> {
+        let field_ref = resolve_field(index_metadata, schema, &self.field, &[FieldType::Integer])?;
+
+        Ok(Box::new(HistogramAggregator {
+            field_ref: field_ref,
+            interval: self.interval,
+            offset: self.offset,
+            min_doc_count: self.min_doc_count,
+            extended_bounds: self.extended_bounds,
+            buckets: BTreeMap::new(),
+            out_of_range: false,
+            sub_aggregations: SubAggregations::build(&self.sub_aggregations, index_metadata, schema)?,
+        }))
+    }
+
+    fn set_sub_aggregations(&mut self, sub_aggregations: Vec<(String, Box<AggregationBuilder>)>) -> Result<(), AggregationParseError> {
+        self.sub_aggregations = sub_aggregations;
+        Ok(())
+    }
+}
+
+
+struct HistogramAggregator {
+    field_ref: FieldRef,
+    interval: i64,
+    offset: i64,
+    min_doc_count: u64,
+    extended_bounds: Option<(i64, i64)>,
+    buckets: BTreeMap<i64, Bucket>,
+
+    /// Set when a value falls into a bucket that starts before the smallest integer
+    out_of_range: bool,
+    sub_aggregations: SubAggregations,
+}
+
+
+impl HistogramAggregator {
+    /// Finds the bucket with the given key, creating it if it doesn't exist yet
+    fn bucket(&mut self, key: i64) -> &mut Bucket {
+        let sub_aggregations = &self.sub_aggregations;
+        self.buckets.entry(key).or_insert_with(|| sub_aggregations.new_bucket())
+    }
+}
+
+
+impl Aggregator for HistogramAggregator {
+    fn collect(&mut self, reader: &StoredFieldReader, doc_ref: DocRef) {
+        if let Some(FieldValue::Integer(value)) = reader.read_stored_field(self.field_ref, doc_ref) {
+            match round_down(value, self.interval, self.offset) {
+                Some(key) => self.bucket(key).collect(reader, doc_ref),
+                None => self.out_of_range = true,
+            }
+        }
+    }
+
+    fn as_json(&self) -> Result<Json, AggregationParseError> {
+        if self.out_of_range {
+            return Err(AggregationParseError::BucketOutOfRange);
+        }
+
+        // Checked when parsed
+        let extended_bounds = self.extended_bounds.map(|(min, max)| {
+            (round_down(min, self.interval, self.offset).unwrap(), round_down(max, self.interval, self.offset).unwrap())
+        });
+
+        let interval = self.interval;
+        let keys = bucket_keys(&self.buckets, self.min_doc_count, extended_bounds, |key| key.checked_add(interval))?;
+
+        // Used for the gaps between buckets
+        let empty_bucket = self.sub_aggregations.new_bucket();
+
+        let mut buckets_json = Vec::with_capacity(keys.len());
+        for key in keys.iter() {
+            let mut json = json!({
+                "key": key,
+            });
+
+            self.buckets.get(key).unwrap_or(&empty_bucket).add_to_json(&mut json)?;
+            buckets_json.push(json);
+        }
+
+        Ok(json!({
+            "buckets": buckets_json,
+        }))
+    }
+
+    fn new_empty(&self) -> Box<Aggregator> {
+        Box::new(HistogramAggregator {
+            field_ref: self.field_ref,
+            interval: self.interval,
+            offset: self.offset,
+            min_doc_count: self.min_doc_count,
+            extended_bounds: self.extended_bounds,
+            buckets: BTreeMap::new(),
+            out_of_range: false,
+            sub_aggregations: self.sub_aggregations.new_empty(),
+        })
+    }
+}
+
+
+/// Parses the "extended_bounds" setting of histogram aggregations
+pub fn parse_extended_bounds<F>(json: &Json, parse_bound: F) -> Result<(i64, i64), AggregationParseError>
+    where F: Fn(&Json) -> Result<i64, AggregationParseError>
+{
+    let object = json.as_object().ok_or(AggregationParseError::ExpectedObject)?;
+
+    let mut min = None;
+    let mut max = None;
+
+    for (key, value) in object.iter() {
+        match &key[..] {
+            "min" => min = Some(parse_bound(value)?),
+            "max" => max = Some(parse_bound(value)?),
+            _ => return Err(AggregationParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok((min.ok_or(AggregationParseError::ExpectedKey("min"))?, max.ok_or(AggregationParseError::ExpectedKey("max"))?))
+}
+
+
+fn parse_integer(json: &Json) -> Result<i64, AggregationParseError> {
+    json.as_i64().ok_or(AggregationParseError::ExpectedInteger)
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<AggregationBuilder>, AggregationParseError> {
+    let object = json.as_object().ok_or(AggregationParseError::ExpectedObject)?;
+
+    // Get configuration
+    let mut field = None;
+    let mut interval = None;
+    let mut offset = 0;
+    let mut min_doc_count = 0;
+    let mut extended_bounds = None;
+
+    for (key, value) in object.iter() {
+        match &key[..] {
+            "field" => {
+                field = Some(value.as_str().ok_or(AggregationParseError::ExpectedString)?.to_string());
+            }
+            "interval" => {
+                let value = parse_integer(value)?;
+
+                if value <= 0 {
+                    return Err(AggregationParseError::InvalidInterval(value.to_string()));
+                }
+
+                interval = Some(value);
+            }
+            "offset" => {
+                offset = parse_integer(value)?;
+            }
+            "min_doc_count" => {
+                min_doc_count = value.as_u64().ok_or(AggregationParseError::ExpectedInteger)?;
+            }
+            "extended_bounds" => {
+                extended_bounds = Some(parse_extended_bounds(value, parse_integer)?);
+            }
+            _ => return Err(AggregationParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    let interval = interval.ok_or(AggregationParseError::ExpectedKey("interval"))?;
+
+    // Offsets of a whole number of intervals give the same buckets, so the offset is brought
+    // into the range 0 to interval so that it can be subtracted from any value once
+    let offset = offset % interval;
+    let offset = if offset < 0 { offset + interval } else { offset };
+
+    // The bounds must be in buckets that start within range
+    if let Some((min, max)) = extended_bounds {
+        if round_down(min, interval, offset).is_none() || round_down(max, interval, offset).is_none() {
+            return Err(AggregationParseError::InvalidValue);
+        }
+    }
+
+    Ok(Box::new(HistogramAggregationBuilder {
+        field: field.ok_or(AggregationParseError::ExpectedKey("field"))?,
+        interval: interval,
+        offset: offset,
+        min_doc_count: min_doc_count,
+        extended_bounds: extended_bounds,
+        sub_aggregations: Vec::new(),
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value as Json;
+    use kite::document::FieldValue;
+
+    use mapping::FieldType;
+    use search::aggregations::AggregationParseError;
+    use search::aggregations::testing::run_aggregation;
+
+    use super::{round_down, parse};
+
+    fn run_histogram(histogram: Json, prices: &[i64]) -> Result<Json, AggregationParseError> {
+        let values = prices.iter().map(|price| FieldValue::Integer(*price)).collect();
+        run_aggregation(json!({"histogram": histogram}), "price", FieldType::Integer, values)
+    }
+
+    #[test]
+    fn test_round_down() {
+        assert_eq!(round_down(0, 10, 0), Some(0));
+        assert_eq!(round_down(9, 10, 0), Some(0));
+        assert_eq!(round_down(10, 10, 0), Some(10));
+        assert_eq!(round_down(-1, 10, 0), Some(-10));
+        assert_eq!(round_down(-10, 10, 0), Some(-10));
+        assert_eq!(round_down(4, 10, 5), Some(-5));
+        assert_eq!(round_down(5, 10, 5), Some(5));
+    }
+
+    #[test]
+    fn test_round_down_overflow() {
+        assert_eq!(round_down(i64::min_value(), 1, 0), Some(i64::min_value()));
+        assert_eq!(round_down(i64::min_value(), 10, 0), None);
+        assert_eq!(round_down(i64::min_value(), 1, 1), None);
+        assert_eq!(round_down(i64::max_value(), 10, 9), Some(i64::max_value() - 8));
+    }
+
+    #[test]
+    fn test_histogram_aggregation() {
+        let result = run_histogram(json!({
+            "field": "price",
+            "interval": 10
+        }), &[1, 5, 12, 35]);
+
+        assert_eq!(result, Ok(json!({
+            "buckets": [
+                {"key": 0, "doc_count": 2},
+                {"key": 10, "doc_count": 1},
+                {"key": 20, "doc_count": 0},
+                {"key": 30, "doc_count": 1},
+            ]
+        })));
+    }
+
+    #[test]
+    fn test_min_doc_count() {
+        let result = run_histogram(json!({
+            "field": "price",
+            "interval": 10,
+            "min_doc_count": 1
+        }), &[1, 5, 12, 35]);
+
+        assert_eq!(result, Ok(json!({
+            "buckets": [
+                {"key": 0, "doc_count": 2},
+                {"key": 10, "doc_count": 1},
+                {"key": 30, "doc_count": 1},
+            ]
+        })));
+    }
+
+    #[test]
+    fn test_offset() {
+        let result = run_histogram(json!({
+            "field": "price",
+            "interval": 10,
+            "offset": 5
+        }), &[1, 5, 12]);
+
+        assert_eq!(result, Ok(json!({
+            "buckets": [
+                {"key": -5, "doc_count": 1},
+                {"key": 5, "doc_count": 2},
+            ]
+        })));
+    }
+
+    #[test]
+    fn test_extended_bounds() {
+        let result = run_histogram(json!({
+            "field": "price",
+            "interval": 10,
+            "extended_bounds": {"min": -5, "max": 25}
+        }), &[12]);
+
+        assert_eq!(result, Ok(json!({
+            "buckets": [
+                {"key": -10, "doc_count": 0},
+                {"key": 0, "doc_count": 0},
+                {"key": 10, "doc_count": 1},
+                {"key": 20, "doc_count": 0},
+            ]
+        })));
+
+        // No matching documents
+        let result = run_histogram(json!({
+            "field": "price",
+            "interval": 10,
+            "extended_bounds": {"min": 0, "max": 10}
+        }), &[]);
+
+        assert_eq!(result, Ok(json!({
+            "buckets": [
+                {"key": 0, "doc_count": 0},
+                {"key": 10, "doc_count": 0},
+            ]
+        })));
+    }
+
+    #[test]
+    fn test_too_many_buckets() {
+        let result = run_histogram(json!({
+            "field": "price",
+            "interval": 1,
+            "extended_bounds": {"min": 0, "max": 1000000000000000i64}
+        }), &[12]);
+
+        assert_eq!(result, Err(AggregationParseError::TooManyBuckets));
+    }
+
+    #[test]
+    fn test_extended_bounds_near_overflow() {
+        let result = run_histogram(json!({
+            "field": "price",
+            "interval": 10,
+            "extended_bounds": {"min": i64::max_value() - 15, "max": i64::max_value()}
+        }), &[]);
+
+        assert_eq!(result, Ok(json!({
+            "buckets": [
+                {"key": round_down(i64::max_value() - 15, 10, 0).unwrap(), "doc_count": 0},
+                {"key": round_down(i64::max_value(), 10, 0).unwrap(), "doc_count": 0},
+            ]
+        })));
+    }
+
+    #[test]
+    fn test_value_near_underflow() {
+        let result = run_histogram(json!({
+            "field": "price",
+            "interval": 10
+        }), &[i64::min_value() + 9]);
+
+        assert_eq!(result, Ok(json!({
+            "buckets": [
+                {"key": i64::min_value() + 8, "doc_count": 1},
+            ]
+        })));
+
+        // The bucket would start before the smallest integer
+        let result = run_histogram(json!({
+            "field": "price",
+            "interval": 10
+        }), &[i64::min_value()]);
+
+        assert_eq!(result, Err(AggregationParseError::BucketOutOfRange));
+    }
+
+    #[test]
+    fn test_gives_error_for_extended_bounds_near_underflow() {
+        let aggregation = parse(&json!({
+            "field": "price",
+            "interval": 10,
+            "extended_bounds": {"min": i64::min_value(), "max": 0}
+        }));
+
+        assert_eq!(aggregation.err(), Some(AggregationParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_large_offset() {
+        // Offsets are taken modulo the interval so they can't overflow
+        let result = run_histogram(json!({
+            "field": "price",
+            "interval": 10,
+            "offset": i64::min_value() + 5
+        }), &[1, 5, 12]);
+
+        assert_eq!(result, Ok(json!({
+            "buckets": [
+                {"key": -3, "doc_count": 2},
+                {"key": 7, "doc_count": 1},
+            ]
+        })));
+    }
+
+    #[test]
+    fn test_gives_error_for_string_field() {
+        let result = run_aggregation(json!({
+            "histogram": {
+                "field": "colour",
+                "interval": 10
+            }
+        }), "colour", FieldType::String, Vec::new());
+
+        assert_eq!(result, Err(AggregationParseError::FieldNotAggregatable("colour".to_string())));
+    }
+
+    #[test]
+    fn test_gives_error_for_missing_interval() {
+        let aggregation = parse(&json!({
+            "field": "price"
+        }));
+
+        assert_eq!(aggregation.err(), Some(AggregationParseError::ExpectedKey("interval")));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_interval() {
+        let aggregation = parse(&json!({
+            "field": "price",
+            "interval": 0
+        }));
+
+        assert_eq!(aggregation.err(), Some(AggregationParseError::InvalidInterval("0".to_string())));
+
+        let aggregation = parse(&json!({
+            "field": "price",
+            "interval": "10"
+        }));
+
+        assert_eq!(aggregation.err(), Some(AggregationParseError::ExpectedInteger));
+    }
+}
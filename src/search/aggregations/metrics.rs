@@ -0,0 +1,310 @@
+//! Parses and runs metric aggregations ("min", "max", "avg", "sum", "stats" and "value_count")
+//!
+//! These calculate values from an integer or date field of the matching documents. Dates are
+//! treated as milliseconds since epoch.
+
+use serde_json::Value as Json;
+use kite::document::{DocRef, FieldValue};
+use kite::schema::{Schema, FieldRef};
+
+use index::metadata::IndexMetadata;
+use mapping::FieldType;
+use search::aggregations::{AggregationBuilder, Aggregator, StoredFieldReader, AggregationParseError, resolve_field};
+use search::aggregations::date_histogram::{DateFormat, datetime_to_millis, format_millis};
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+    Min,
+    Max,
+    Avg,
+    Sum,
+    Stats,
+    ValueCount,
+}
+
+
+#[derive(Debug)]
+struct MetricAggregationBuilder {
+    metric: Metric,
+    field: String,
+}
+
+
+impl AggregationBuilder for MetricAggregationBuilder {
+    fn build(&self, index_metadata: &IndexMetadata, schema: &Schema) -> Result<Box<Aggregator>, AggregationParseError> {
+        // Values of any type can be counted
+        let allowed_types = match self.metric {
+            Metric::ValueCount => vec![FieldType::String, FieldType::Integer, FieldType::Boolean, FieldType::Date],
+            _ => vec![FieldType::Integer, FieldType::Date],
+        };
+
+        let field_ref = resolve_field(index_metadata, schema, &self.field, &allowed_types)?;
+        let is_date = index_metadata.get_field_mapping(&self.field).map_or(false, |field_mapping| field_mapping.data_type == FieldType::Date);
+
+        Ok(Box::new(MetricAggregator::new(self.metric, field_ref, is_date)))
+    }
+}
+
+
+struct MetricAggregator {
+    metric: Metric,
+    field_ref: FieldRef,
+    is_date: bool,
+    count: u64,
+    sum: f64,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+
+impl MetricAggregator {
+    fn new(metric: Metric, field_ref: FieldRef, is_date: bool) -> MetricAggregator {
+        MetricAggregator {
+            metric: metric,
+            field_ref: field_ref,
+            is_date: is_date,
+            count: 0,
+            sum: 0.0,
+            min: None,
+            max: None,
+        }
+    }
+
+    /// Adds a value to the aggregation. Values that aren't numeric are only counted
+    fn add_value(&mut self, value: Option<f64>) {
+        self.count += 1;
+
+        if let Some(value) = value {
+            self.sum += value;
+
+            if self.min.map_or(true, |min| value < min) {
+                self.min = Some(value);
+            }
+
+            if self.max.map_or(true, |max| value > max) {
+                self.max = Some(value);
+            }
+        }
+    }
+
+    fn avg(&self) -> Option<f64> {
+        if self.count > 0 {
+            Some(self.sum / self.count as f64)
+        } else {
+            None
+        }
+    }
+
+    /// Adds "<name>_as_string" alongside a value when aggregating dates
+    fn add_value_as_string(&self, json: &mut Json, name: &str, value: Option<f64>) {
+        if let (true, Some(value)) = (self.is_date, value) {
+            json[format!("{}_as_string", name).as_str()] = json!(format_millis(value as i64, 0, &DateFormat::Default));
+        }
+    }
+}
+
+
+impl Aggregator for MetricAggregator {
+    fn collect(&mut self, reader: &StoredFieldReader, doc_ref: DocRef) {
+        match reader.read_stored_field(self.field_ref, doc_ref) {
+            Some(FieldValue::Integer(value)) => self.add_value(Some(value as f64)),
+            Some(FieldValue::DateTime(value)) => self.add_value(Some(datetime_to_millis(&value) as f64)),
+            Some(_) => self.add_value(None),
+            None => {}
+        }
+    }
+
+    fn as_json(&self) -> Result<Json, AggregationParseError> {
+        Ok(match self.metric {
+            Metric::Min | Metric::Max => {
+                let value = if self.metric == Metric::Min { self.min } else { self.max };
+                let mut json = json!({"value": value});
+                self.add_value_as_string(&mut json, "value", value);
+                json
+            }
+            Metric::Avg => json!({"value": self.avg()}),
+            Metric::Sum => json!({"value": self.sum}),
+            Metric::ValueCount => json!({"value": self.count}),
+            Metric::Stats => {
+                let mut json = json!({
+                    "count": self.count,
+                    "min": self.min,
+                    "max": self.max,
+                    "avg": self.avg(),
+                    "sum": self.sum,
+                });
+
+                self.add_value_as_string(&mut json, "min", self.min);
+                self.add_value_as_string(&mut json, "max", self.max);
+                self.add_value_as_string(&mut json, "avg", self.avg());
+                json
+            }
+        })
+    }
+
+    fn new_empty(&self) -> Box<Aggregator> {
+        Box::new(MetricAggregator::new(self.metric, self.field_ref, self.is_date))
+    }
+}
+
+
+fn parse(json: &Json, metric: Metric) -> Result<Box<AggregationBuilder>, AggregationParseError> {
+    let object = json.as_object().ok_or(AggregationParseError::ExpectedObject)?;
+
+    let mut field = None;
+
+    for (key, value) in object.iter() {
+        match &key[..] {
+            "field" => {
+                field = Some(value.as_str().ok_or(AggregationParseError::ExpectedString)?.to_string());
+            }
+            _ => return Err(AggregationParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(MetricAggregationBuilder {
+        metric: metric,
+        field: field.ok_or(AggregationParseError::ExpectedKey("field"))?,
+    }))
+}
+
+
+pub fn parse_min(json: &Json) -> Result<Box<AggregationBuilder>, AggregationParseError> {
+    parse(json, Metric::Min)
+}
+
+
+pub fn parse_max(json: &Json) -> Result<Box<AggregationBuilder>, AggregationParseError> {
+    parse(json, Metric::Max)
+}
+
+
+pub fn parse_avg(json: &Json) -> Result<Box<AggregationBuilder>, AggregationParseError> {
+    parse(json, Metric::Avg)
+}
+
+
+pub fn parse_sum(json: &Json) -> Result<Box<AggregationBuilder>, AggregationParseError> {
+    parse(json, Metric::Sum)
+}
+
+
+pub fn parse_stats(json: &Json) -> Result<Box<AggregationBuilder>, AggregationParseError> {
+    parse(json, Metric::Stats)
+}
+
+
+pub fn parse_value_count(json: &Json) -> Result<Box<AggregationBuilder>, AggregationParseError> {
+    parse(json, Metric::ValueCount)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value as Json;
+    use chrono::{DateTime, Utc};
+    use kite::document::FieldValue;
+
+    use mapping::FieldType;
+    use search::aggregations::AggregationParseError;
+    use search::aggregations::testing::run_aggregation;
+
+    use super::parse_avg;
+
+    fn run_metric(metric: &str, prices: &[i64]) -> Result<Json, AggregationParseError> {
+        let mut aggregation = json!({});
+        aggregation[metric] = json!({"field": "price"});
+
+        let values = prices.iter().map(|price| FieldValue::Integer(*price)).collect();
+        run_aggregation(aggregation, "price", FieldType::Integer, values)
+    }
+
+    #[test]
+    fn test_single_value_metrics() {
+        let prices = [10, 2, 6];
+
+        assert_eq!(run_metric("min", &prices), Ok(json!({"value": 2.0})));
+        assert_eq!(run_metric("max", &prices), Ok(json!({"value": 10.0})));
+        assert_eq!(run_metric("avg", &prices), Ok(json!({"value": 6.0})));
+        assert_eq!(run_metric("sum", &prices), Ok(json!({"value": 18.0})));
+        assert_eq!(run_metric("value_count", &prices), Ok(json!({"value": 3})));
+    }
+
+    #[test]
+    fn test_stats() {
+        assert_eq!(run_metric("stats", &[10, 2, 6]), Ok(json!({
+            "count": 3,
+            "min": 2.0,
+            "max": 10.0,
+            "avg": 6.0,
+            "sum": 18.0,
+        })));
+    }
+
+    #[test]
+    fn test_no_values() {
+        assert_eq!(run_metric("min", &[]), Ok(json!({"value": null})));
+        assert_eq!(run_metric("avg", &[]), Ok(json!({"value": null})));
+        assert_eq!(run_metric("sum", &[]), Ok(json!({"value": 0.0})));
+        assert_eq!(run_metric("stats", &[]), Ok(json!({
+            "count": 0,
+            "min": null,
+            "max": null,
+            "avg": null,
+            "sum": 0.0,
+        })));
+    }
+
+    #[test]
+    fn test_dates() {
+        let result = run_aggregation(json!({
+            "max": {
+                "field": "date"
+            }
+        }), "date", FieldType::Date, vec![
+            FieldValue::DateTime("2016-06-01T12:00:00Z".parse::<DateTime<Utc>>().unwrap()),
+            FieldValue::DateTime("2017-01-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap()),
+        ]);
+
+        assert_eq!(result, Ok(json!({
+            "value": 1483228800000.0,
+            "value_as_string": "2017-01-01T00:00:00.000Z",
+        })));
+    }
+
+    #[test]
+    fn test_value_count_of_strings() {
+        let result = run_aggregation(json!({
+            "value_count": {
+                "field": "colour"
+            }
+        }), "colour", FieldType::String, vec![
+            FieldValue::String("red".to_string()),
+            FieldValue::String("blue".to_string()),
+        ]);
+
+        assert_eq!(result, Ok(json!({"value": 2})));
+    }
+
+    #[test]
+    fn test_gives_error_for_sum_of_strings() {
+        let result = run_aggregation(json!({
+            "sum": {
+                "field": "colour"
+            }
+        }), "colour", FieldType::String, Vec::new());
+
+        assert_eq!(result, Err(AggregationParseError::FieldNotAggregatable("colour".to_string())));
+    }
+
+    #[test]
+    fn test_gives_error_for_unrecognised_key() {
+        let aggregation = parse_avg(&json!({
+            "field": "price",
+            "foo": "bar"
+        }));
+
+        assert_eq!(aggregation.err(), Some(AggregationParseError::UnrecognisedKey("foo".to_string())));
+    }
+}
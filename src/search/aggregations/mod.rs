@@ -0,0 +1,450 @@
+//! Parses and runs the "aggs" section of search requests
+//!
+//! Aggregations are parsed into builders which are then bound to the fields of an index
+//! to create aggregators. Each matching document is passed into every aggregator, which
+//! reads the values it needs from the document's stored fields. Fields must be mapped with
+//! "store": true to be aggregated on, they aren't stored by default.
+//!
+//! Bucket aggregations (terms, histogram, etc) may contain sub-aggregations which are run
+//! separately over the documents in each bucket.
+
+pub mod terms;
+pub mod histogram;
+pub mod date_histogram;
+pub mod metrics;
+
+use std::fmt::Debug;
+
+use serde_json::Value as Json;
+use kite::document::{DocRef, FieldValue};
+use kite::schema::{Schema, FieldRef};
+use kite_rocksdb::RocksDBReader;
+
+use index::metadata::IndexMetadata;
+use mapping::FieldType;
+
+
+/// The most buckets that a single aggregation may return
+pub const MAX_BUCKETS: usize = 10000;
+
+
+#[derive(Debug, PartialEq)]
+pub enum AggregationParseError {
+    UnrecognisedAggregationType(String),
+    FieldDoesntExist(String),
+    FieldNotAggregatable(String),
+
+    /// The field isn't mapped with "store": true
+    FieldNotStored(String),
+
+    UnrecognisedKey(String),
+    ExpectedKey(&'static str),
+    ExpectedObject,
+    ExpectedString,
+    ExpectedInteger,
+    ExpectedSingleKey,
+    InvalidOrder(String),
+    InvalidInterval(String),
+    InvalidTimeZone(String),
+    InvalidFormat(String),
+    InvalidValue,
+    SubAggregationsNotSupported,
+    TooManyBuckets,
+
+    /// A value falls into a bucket whose key can't be represented
+    BucketOutOfRange,
+}
+
+
+impl AggregationParseError {
+    pub fn reason(&self) -> String {
+        match *self {
+            AggregationParseError::FieldNotStored(ref field_name) => format!("field {:?} must be mapped with \"store\": true to be aggregated on", field_name),
+            ref error => format!("{:?}", error),
+        }
+    }
+}
+
+
+pub trait AggregationBuilder: Debug {
+    fn build(&self, index_metadata: &IndexMetadata, schema: &Schema) -> Result<Box<Aggregator>, AggregationParseError>;
+
+    /// Sets the aggregations to run inside each bucket
+    ///
+    /// Only bucket aggregations support this
+    fn set_sub_aggregations(&mut self, _sub_aggregations: Vec<(String, Box<AggregationBuilder>)>) -> Result<(), AggregationParseError> {
+        Err(AggregationParseError::SubAggregationsNotSupported)
+    }
+}
+
+
+/// Gives aggregators access to the stored values of a document
+pub trait StoredFieldReader {
+    fn read_stored_field(&self, field_ref: FieldRef, doc_ref: DocRef) -> Option<FieldValue>;
+}
+
+
+impl<'a> StoredFieldReader for RocksDBReader<'a> {
+    fn read_stored_field(&self, field_ref: FieldRef, doc_ref: DocRef) -> Option<FieldValue> {
+        RocksDBReader::read_stored_field(self, field_ref, doc_ref).unwrap_or(None)
+    }
+}
+
+
+pub trait Aggregator {
+    fn collect(&mut self, reader: &StoredFieldReader, doc_ref: DocRef);
+    fn as_json(&self) -> Result<Json, AggregationParseError>;
+
+    /// Creates an aggregator with the same settings that hasn't seen any documents yet
+    fn new_empty(&self) -> Box<Aggregator>;
+}
+
+
+/// Builds an aggregator for each of the given aggregation builders
+pub fn build(builders: &[(String, Box<AggregationBuilder>)], index_metadata: &IndexMetadata, schema: &Schema) -> Result<Vec<(String, Box<Aggregator>)>, AggregationParseError> {
+    let mut aggregators = Vec::new();
+
+    for &(ref name, ref builder) in builders.iter() {
+        aggregators.push((name.clone(), builder.build(index_metadata, schema)?));
+    }
+
+    Ok(aggregators)
+}
+
+
+/// A bucket of a bucket aggregation
+///
+/// Counts the documents that fall into it and passes them into its own copy of the
+/// sub-aggregations.
+pub struct Bucket {
+    pub doc_count: u64,
+    pub sub_aggregators: Vec<(String, Box<Aggregator>)>,
+}
+
+
+impl Bucket {
+    pub fn collect(&mut self, reader: &StoredFieldReader, doc_ref: DocRef) {
+        self.doc_count += 1;
+
+        for &mut (_, ref mut aggregator) in self.sub_aggregators.iter_mut() {
+            aggregator.collect(reader, doc_ref);
+        }
+    }
+
+    /// Adds the document count and the results of each sub-aggregation to the bucket's JSON
+    pub fn add_to_json(&self, json: &mut Json) -> Result<(), AggregationParseError> {
+        json["doc_count"] = json!(self.doc_count);
+
+        for &(ref name, ref aggregator) in self.sub_aggregators.iter() {
+            json[name.as_str()] = aggregator.as_json()?;
+        }
+
+        Ok(())
+    }
+}
+
+
+/// The sub-aggregations of a bucket aggregation
+///
+/// Holds an empty aggregator for each sub-aggregation which is copied into every new bucket.
+pub struct SubAggregations {
+    prototypes: Vec<(String, Box<Aggregator>)>,
+}
+
+
+impl SubAggregations {
+    pub fn new(prototypes: Vec<(String, Box<Aggregator>)>) -> SubAggregations {
+        SubAggregations {
+            prototypes: prototypes,
+        }
+    }
+
+    pub fn build(builders: &[(String, Box<AggregationBuilder>)], index_metadata: &IndexMetadata, schema: &Schema) -> Result<SubAggregations, AggregationParseError> {
+        Ok(SubAggregations::new(build(builders, index_metadata, schema)?))
+    }
+
+    pub fn new_bucket(&self) -> Bucket {
+        Bucket {
+            doc_count: 0,
+            sub_aggregators: self.prototypes.iter().map(|&(ref name, ref aggregator)| (name.clone(), aggregator.new_empty())).collect(),
+        }
+    }
+
+    pub fn new_empty(&self) -> SubAggregations {
+        SubAggregations::new(self.prototypes.iter().map(|&(ref name, ref aggregator)| (name.clone(), aggregator.new_empty())).collect())
+    }
+}
+
+
+/// Finds the stored field that an aggregation reads its values from
+///
+/// Values are read from stored fields so only fields that are stored and have a single
+/// value per document can be aggregated on.
+pub fn resolve_field(index_metadata: &IndexMetadata, schema: &Schema, field_name: &str, allowed_types: &[FieldType]) -> Result<FieldRef, AggregationParseError> {
+    let field_mapping = match index_metadata.get_field_mapping(field_name) {
+        Some(field_mapping) => field_mapping,
+        None => return Err(AggregationParseError::FieldDoesntExist(field_name.to_string())),
+    };
+
+    if !allowed_types.contains(&field_mapping.data_type) {
+        return Err(AggregationParseError::FieldNotAggregatable(field_name.to_string()));
+    }
+
+    // Analyzed strings have many values per document
+    if field_mapping.data_type == FieldType::String && field_mapping.index_analyzer().is_some() {
+        return Err(AggregationParseError::FieldNotAggregatable(field_name.to_string()));
+    }
+
+    if !field_mapping.is_stored {
+        return Err(AggregationParseError::FieldNotStored(field_name.to_string()));
+    }
+
+    match schema.get_field_by_name(field_name) {
+        Some(field_ref) => Ok(field_ref),
+        None => Err(AggregationParseError::FieldDoesntExist(field_name.to_string())),
+    }
+}
+
+
+fn get_aggregation_parser(aggregation_type: &str) -> Option<fn(&Json) -> Result<Box<AggregationBuilder>, AggregationParseError>> {
+    match aggregation_type {
+        "terms" => Some(terms::parse),
+        "histogram" => Some(histogram::parse),
+        "date_histogram" => Some(date_histogram::parse),
+        "min" => Some(metrics::parse_min),
+        "max" => Some(metrics::parse_max),
+        "avg" => Some(metrics::parse_avg),
+        "sum" => Some(metrics::parse_sum),
+        "stats" => Some(metrics::parse_stats),
+        "value_count" => Some(metrics::parse_value_count),
+        _ => None
+    }
+}
+
+
+fn parse_aggregation(json: &Json) -> Result<Box<AggregationBuilder>, AggregationParseError> {
+    let object = json.as_object().ok_or(AggregationParseError::ExpectedObject)?;
+
+    // Aggregations contain a single key for their type alongside an optional "aggs" key
+    let mut aggregation_type = None;
+    let mut sub_aggregations = None;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "aggs" | "aggregations" => {
+                sub_aggregations = Some(parse(value)?);
+            }
+            _ => {
+                if aggregation_type.is_some() {
+                    return Err(AggregationParseError::ExpectedSingleKey);
+                }
+
+                aggregation_type = Some((key, value));
+            }
+        }
+    }
+
+    let (aggregation_type, aggregation_json) = aggregation_type.ok_or(AggregationParseError::ExpectedSingleKey)?;
+    let mut builder = match get_aggregation_parser(&aggregation_type) {
+        Some(parse) => parse(aggregation_json)?,
+        None => return Err(AggregationParseError::UnrecognisedAggregationType(aggregation_type.clone())),
+    };
+
+    if let Some(sub_aggregations) = sub_aggregations {
+        builder.set_sub_aggregations(sub_aggregations)?;
+    }
+
+    Ok(builder)
+}
+
+
+/// Parses an "aggs" object into a list of named aggregation builders
+pub fn parse(json: &Json) -> Result<Vec<(String, Box<AggregationBuilder>)>, AggregationParseError> {
+    let object = json.as_object().ok_or(AggregationParseError::ExpectedObject)?;
+
+    let mut aggregations = Vec::new();
+    for (name, aggregation_json) in object.iter() {
+        aggregations.push((name.clone(), parse_aggregation(aggregation_json)?));
+    }
+
+    Ok(aggregations)
+}
+
+
+/// Runs aggregations over documents that only have one field, for testing
+#[cfg(test)]
+pub mod testing {
+    use serde_json::Value as Json;
+    use kite::document::{DocRef, FieldValue};
+    use kite::schema::{self, Schema, FieldRef, FIELD_STORED};
+
+    use index::metadata::IndexMetadata;
+    use mapping::{Mapping, MappingProperty, FieldMapping, FieldType};
+
+    use super::{StoredFieldReader, AggregationParseError, parse, build};
+
+    /// Holds the value of each document, the position in the list is the document's DocRef
+    struct TestDocuments {
+        values: Vec<FieldValue>,
+    }
+
+    impl StoredFieldReader for TestDocuments {
+        fn read_stored_field(&self, _field_ref: FieldRef, doc_ref: DocRef) -> Option<FieldValue> {
+            self.values.get(doc_ref.as_u64() as usize).cloned()
+        }
+    }
+
+    /// Parses the aggregation, binds it to a stored field and passes each value into it as
+    /// a separate document
+    pub fn run_aggregation(aggregation: Json, field_name: &str, data_type: FieldType, values: Vec<FieldValue>) -> Result<Json, AggregationParseError> {
+        let mut field_mapping = FieldMapping::default();
+        field_mapping.data_type = data_type;
+        field_mapping.is_stored = true;
+
+        let mut index_metadata = IndexMetadata::default();
+        index_metadata.mappings.insert("test".to_string(), Mapping {
+            properties: hashmap! {
+                field_name.to_string() => MappingProperty::Field(field_mapping),
+            },
+        });
+
+        let store_field_type = match data_type {
+            FieldType::String => schema::FieldType::PlainString,
+            FieldType::Integer => schema::FieldType::I64,
+            FieldType::Boolean => schema::FieldType::Boolean,
+            FieldType::Date => schema::FieldType::DateTime,
+        };
+        let mut schema = Schema::new();
+        schema.add_field(field_name.to_string(), store_field_type, FIELD_STORED).unwrap();
+
+        let builders = parse(&json!({"test": aggregation}))?;
+        let mut aggregators = build(&builders, &index_metadata, &schema)?;
+        let (_, ref mut aggregator) = aggregators[0];
+
+        let documents = TestDocuments {
+            values: values,
+        };
+        for doc_id in 0..documents.values.len() {
+            aggregator.collect(&documents, DocRef::from_u64(doc_id as u64));
+        }
+
+        aggregator.as_json()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use kite::schema::{Schema, FieldType as StoreFieldType, FIELD_INDEXED};
+
+    use index::metadata::IndexMetadata;
+    use mapping::{Mapping, MappingProperty, FieldMapping, FieldType};
+
+    use super::{parse, resolve_field, AggregationParseError};
+
+    #[test]
+    fn test_parse() {
+        let aggregations = parse(&json!({
+            "colours": {
+                "terms": {
+                    "field": "colour"
+                }
+            },
+            "sizes": {
+                "terms": {
+                    "field": "size"
+                }
+            }
+        })).unwrap();
+
+        assert_eq!(aggregations.iter().map(|&(ref name, _)| name.clone()).collect::<Vec<String>>(), vec!["colours".to_string(), "sizes".to_string()]);
+    }
+
+    #[test]
+    fn test_parse_sub_aggregations() {
+        let aggregations = parse(&json!({
+            "colours": {
+                "terms": {
+                    "field": "colour"
+                },
+                "aggs": {
+                    "avg_price": {
+                        "avg": {
+                            "field": "price"
+                        }
+                    }
+                }
+            }
+        }));
+
+        assert!(aggregations.is_ok());
+    }
+
+    #[test]
+    fn test_gives_error_for_sub_aggregations_of_metric() {
+        let aggregations = parse(&json!({
+            "avg_price": {
+                "avg": {
+                    "field": "price"
+                },
+                "aggs": {
+                    "colours": {
+                        "terms": {
+                            "field": "colour"
+                        }
+                    }
+                }
+            }
+        }));
+
+        assert_eq!(aggregations.err(), Some(AggregationParseError::SubAggregationsNotSupported));
+    }
+
+    #[test]
+    fn test_gives_error_for_unrecognised_type() {
+        let aggregations = parse(&json!({
+            "colours": {
+                "foo": {
+                    "field": "colour"
+                }
+            }
+        }));
+
+        assert_eq!(aggregations.err(), Some(AggregationParseError::UnrecognisedAggregationType("foo".to_string())));
+    }
+
+    #[test]
+    fn test_gives_error_for_multiple_types() {
+        let aggregations = parse(&json!({
+            "colours": {
+                "terms": {
+                    "field": "colour"
+                },
+                "foo": {}
+            }
+        }));
+
+        assert_eq!(aggregations.err(), Some(AggregationParseError::ExpectedSingleKey));
+    }
+
+    #[test]
+    fn test_gives_error_for_field_that_isnt_stored() {
+        let mut schema = Schema::new();
+        let price_field = schema.add_field("price".to_string(), StoreFieldType::I64, FIELD_INDEXED).unwrap();
+
+        let mut field_mapping = FieldMapping::default();
+        field_mapping.data_type = FieldType::Integer;
+        field_mapping.index_ref = Some(price_field);
+
+        let mut index_metadata = IndexMetadata::default();
+        index_metadata.mappings.insert("test".to_string(), Mapping {
+            properties: hashmap! {
+                "price".to_string() => MappingProperty::Field(field_mapping),
+            },
+        });
+
+        let error = resolve_field(&index_metadata, &schema, "price", &[FieldType::Integer]).err();
+        assert_eq!(error, Some(AggregationParseError::FieldNotStored("price".to_string())));
+        assert!(error.unwrap().reason().contains("\"store\": true"));
+    }
+}
@@ -0,0 +1,406 @@
+//! Parses and runs "terms" aggregations
+//!
+//! Creates a bucket for each unique value of a field and counts the number of matching
+//! documents in each one. Only values of documents that matched the query are seen so
+//! terms with no matching documents are never returned, even if "min_doc_count" is 0.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use serde_json::Value as Json;
+use kite::document::{DocRef, FieldValue};
+use kite::schema::{Schema, FieldRef};
+
+use index::metadata::IndexMetadata;
+use mapping::FieldType;
+use search::sort::SortOrder;
+use search::aggregations::{AggregationBuilder, Aggregator, StoredFieldReader, AggregationParseError, Bucket, SubAggregations, resolve_field};
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TermsOrder {
+    Count(SortOrder),
+    Term(SortOrder),
+}
+
+
+#[derive(Debug)]
+struct TermsAggregationBuilder {
+    field: String,
+    size: usize,
+    order: TermsOrder,
+    min_doc_count: u64,
+    sub_aggregations: Vec<(String, Box<AggregationBuilder>)>,
+}
+
+
+impl AggregationBuilder for TermsAggregationBuilder {
+    fn build(&self, index_metadata: &IndexMetadata, schema: &Schema) -> Result<Box<Aggregator>, AggregationParseError> {
+        let field_ref = resolve_field(index_metadata, schema, &self.field, &[FieldType::String, FieldType::Integer, FieldType::Boolean])?;
+
+        Ok(Box::new(TermsAggregator {
+            field_ref: field_ref,
+            size: self.size,
+            order: self.order,
+            min_doc_count: self.min_doc_count,
+            buckets: HashMap::new(),
+            sub_aggregations: SubAggregations::build(&self.sub_aggregations, index_metadata, schema)?,
+        }))
+    }
+
+    fn set_sub_aggregations(&mut self, sub_aggregations: Vec<(String, Box<AggregationBuilder>)>) -> Result<(), AggregationParseError> {
+        self.sub_aggregations = sub_aggregations;
+        Ok(())
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum TermsKey {
+    Boolean(bool),
+    Integer(i64),
+    String(String),
+}
+
+
+impl TermsKey {
+    fn from_field_value(value: FieldValue) -> Option<TermsKey> {
+        match value {
+            FieldValue::String(string) => Some(TermsKey::String(string)),
+            FieldValue::Integer(value) => Some(TermsKey::Integer(value)),
+            FieldValue::Boolean(value) => Some(TermsKey::Boolean(value)),
+            FieldValue::DateTime(_) => None,
+        }
+    }
+
+    fn bucket_json(&self, bucket: &Bucket) -> Result<Json, AggregationParseError> {
+        let mut json = match *self {
+            // Elasticsearch returns boolean keys as 1/0 with a separate string representation
+            TermsKey::Boolean(value) => json!({
+                "key": if value { 1 } else { 0 },
+                "key_as_string": value.to_string(),
+            }),
+            TermsKey::Integer(value) => json!({
+                "key": value,
+            }),
+            TermsKey::String(ref value) => json!({
+                "key": value,
+            }),
+        };
+
+        bucket.add_to_json(&mut json)?;
+        Ok(json)
+    }
+}
+
+
+fn compare_buckets(order: TermsOrder, a: &(&TermsKey, &Bucket), b: &(&TermsKey, &Bucket)) -> Ordering {
+    let ordering = match order {
+        TermsOrder::Count(SortOrder::Asc) => a.1.doc_count.cmp(&b.1.doc_count),
+        TermsOrder::Count(SortOrder::Desc) => b.1.doc_count.cmp(&a.1.doc_count),
+        TermsOrder::Term(SortOrder::Asc) => a.0.cmp(b.0),
+        TermsOrder::Term(SortOrder::Desc) => b.0.cmp(a.0),
+    };
+
+    // Buckets with the same count are ordered by their term
+    match ordering {
+        Ordering::Equal => a.0.cmp(b.0),
+        ordering => ordering,
+    }
+}
+
+
+struct TermsAggregator {
+    field_ref: FieldRef,
+    size: usize,
+    order: TermsOrder,
+    min_doc_count: u64,
+    buckets: HashMap<TermsKey, Bucket>,
+    sub_aggregations: SubAggregations,
+}
+
+
+impl TermsAggregator {
+    /// Finds the bucket that the value belongs in, creating it if it doesn't exist yet
+    fn bucket(&mut self, value: FieldValue) -> Option<&mut Bucket> {
+        match TermsKey::from_field_value(value) {
+            Some(key) => {
+                let sub_aggregations = &self.sub_aggregations;
+                Some(self.buckets.entry(key).or_insert_with(|| sub_aggregations.new_bucket()))
+            }
+            None => None,
+        }
+    }
+}
+
+
+impl Aggregator for TermsAggregator {
+    fn collect(&mut self, reader: &StoredFieldReader, doc_ref: DocRef) {
+        if let Some(value) = reader.read_stored_field(self.field_ref, doc_ref) {
+            if let Some(bucket) = self.bucket(value) {
+                bucket.collect(reader, doc_ref);
+            }
+        }
+    }
+
+    fn as_json(&self) -> Result<Json, AggregationParseError> {
+        let mut buckets = self.buckets.iter()
+            .filter(|&(_, bucket)| bucket.doc_count >= self.min_doc_count)
+            .collect::<Vec<_>>();
+
+        let order = self.order;
+        buckets.sort_by(|a, b| compare_buckets(order, a, b));
+
+        // A size of 0 returns every bucket
+        let size = if self.size == 0 { buckets.len() } else { self.size };
+        let sum_other_doc_count: u64 = buckets.iter().skip(size).map(|&(_, bucket)| bucket.doc_count).sum();
+
+        let mut buckets_json = Vec::new();
+        for &(key, bucket) in buckets.iter().take(size) {
+            buckets_json.push(key.bucket_json(bucket)?);
+        }
+
+        Ok(json!({
+            "doc_count_error_upper_bound": 0,
+            "sum_other_doc_count": sum_other_doc_count,
+            "buckets": buckets_json,
+        }))
+    }
+
+    fn new_empty(&self) -> Box<Aggregator> {
+        Box::new(TermsAggregator {
+            field_ref: self.field_ref,
+            size: self.size,
+            order: self.order,
+            min_doc_count: self.min_doc_count,
+            buckets: HashMap::new(),
+            sub_aggregations: self.sub_aggregations.new_empty(),
+        })
+    }
+}
+
+
+fn parse_order(json: &Json) -> Result<TermsOrder, AggregationParseError> {
+    let object = json.as_object().ok_or(AggregationParseError::ExpectedObject)?;
+
+    if object.len() != 1 {
+        return Err(AggregationParseError::ExpectedSingleKey);
+    }
+
+    let (key, value) = object.iter().next().unwrap();
+    let direction = match value.as_str() {
+        Some("asc") => SortOrder::Asc,
+        Some("desc") => SortOrder::Desc,
+        Some(direction) => return Err(AggregationParseError::InvalidOrder(direction.to_string())),
+        None => return Err(AggregationParseError::ExpectedString),
+    };
+
+    match key.as_ref() {
+        "_count" => Ok(TermsOrder::Count(direction)),
+        "_term" | "_key" => Ok(TermsOrder::Term(direction)),
+        _ => Err(AggregationParseError::InvalidOrder(key.clone())),
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<AggregationBuilder>, AggregationParseError> {
+    let object = json.as_object().ok_or(AggregationParseError::ExpectedObject)?;
+
+    // Get configuration
+    let mut field = None;
+    let mut size = 10;
+    let mut order = TermsOrder::Count(SortOrder::Desc);
+    let mut min_doc_count = 1;
+
+    for (key, value) in object.iter() {
+        match &key[..] {
+            "field" => {
+                field = Some(value.as_str().ok_or(AggregationParseError::ExpectedString)?.to_string());
+            }
+            "size" => {
+                size = value.as_u64().ok_or(AggregationParseError::ExpectedInteger)? as usize;
+            }
+            "order" => {
+                order = parse_order(value)?;
+            }
+            "min_doc_count" => {
+                min_doc_count = value.as_u64().ok_or(AggregationParseError::ExpectedInteger)?;
+            }
+            _ => return Err(AggregationParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(TermsAggregationBuilder {
+        field: field.ok_or(AggregationParseError::ExpectedKey("field"))?,
+        size: size,
+        order: order,
+        min_doc_count: min_doc_count,
+        sub_aggregations: Vec::new(),
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value as Json;
+    use kite::document::FieldValue;
+
+    use mapping::FieldType;
+    use search::sort::SortOrder;
+    use search::aggregations::AggregationParseError;
+    use search::aggregations::testing::run_aggregation;
+
+    use super::{TermsOrder, parse, parse_order};
+
+    fn colours() -> Vec<FieldValue> {
+        vec![
+            FieldValue::String("red".to_string()),
+            FieldValue::String("blue".to_string()),
+            FieldValue::String("red".to_string()),
+            FieldValue::String("green".to_string()),
+            FieldValue::String("red".to_string()),
+            FieldValue::String("blue".to_string()),
+        ]
+    }
+
+    fn run_colours_aggregation(aggregation: Json) -> Result<Json, AggregationParseError> {
+        run_aggregation(aggregation, "colour", FieldType::String, colours())
+    }
+
+    #[test]
+    fn test_terms_aggregation() {
+        let result = run_colours_aggregation(json!({
+            "terms": {
+                "field": "colour"
+            }
+        }));
+
+        assert_eq!(result, Ok(json!({
+            "doc_count_error_upper_bound": 0,
+            "sum_other_doc_count": 0,
+            "buckets": [
+                {"key": "red", "doc_count": 3},
+                {"key": "blue", "doc_count": 2},
+                {"key": "green", "doc_count": 1},
+            ]
+        })));
+    }
+
+    #[test]
+    fn test_size() {
+        let result = run_colours_aggregation(json!({
+            "terms": {
+                "field": "colour",
+                "size": 1
+            }
+        }));
+
+        assert_eq!(result, Ok(json!({
+            "doc_count_error_upper_bound": 0,
+            "sum_other_doc_count": 3,
+            "buckets": [
+                {"key": "red", "doc_count": 3},
+            ]
+        })));
+    }
+
+    #[test]
+    fn test_order_by_term() {
+        let result = run_colours_aggregation(json!({
+            "terms": {
+                "field": "colour",
+                "order": {"_term": "asc"}
+            }
+        }));
+
+        assert_eq!(result, Ok(json!({
+            "doc_count_error_upper_bound": 0,
+            "sum_other_doc_count": 0,
+            "buckets": [
+                {"key": "blue", "doc_count": 2},
+                {"key": "green", "doc_count": 1},
+                {"key": "red", "doc_count": 3},
+            ]
+        })));
+    }
+
+    #[test]
+    fn test_min_doc_count() {
+        let result = run_colours_aggregation(json!({
+            "terms": {
+                "field": "colour",
+                "order": {"_count": "asc"},
+                "min_doc_count": 2
+            }
+        }));
+
+        assert_eq!(result, Ok(json!({
+            "doc_count_error_upper_bound": 0,
+            "sum_other_doc_count": 0,
+            "buckets": [
+                {"key": "blue", "doc_count": 2},
+                {"key": "red", "doc_count": 3},
+            ]
+        })));
+    }
+
+    #[test]
+    fn test_boolean_keys() {
+        let result = run_aggregation(json!({
+            "terms": {
+                "field": "available"
+            }
+        }), "available", FieldType::Boolean, vec![
+            FieldValue::Boolean(true),
+            FieldValue::Boolean(false),
+            FieldValue::Boolean(true),
+        ]);
+
+        assert_eq!(result, Ok(json!({
+            "doc_count_error_upper_bound": 0,
+            "sum_other_doc_count": 0,
+            "buckets": [
+                {"key": 1, "key_as_string": "true", "doc_count": 2},
+                {"key": 0, "key_as_string": "false", "doc_count": 1},
+            ]
+        })));
+    }
+
+    #[test]
+    fn test_parse_order() {
+        assert_eq!(parse_order(&json!({"_count": "asc"})), Ok(TermsOrder::Count(SortOrder::Asc)));
+        assert_eq!(parse_order(&json!({"_term": "desc"})), Ok(TermsOrder::Term(SortOrder::Desc)));
+        assert_eq!(parse_order(&json!({"_key": "asc"})), Ok(TermsOrder::Term(SortOrder::Asc)));
+        assert_eq!(parse_order(&json!({"_count": "up"})), Err(AggregationParseError::InvalidOrder("up".to_string())));
+    }
+
+    #[test]
+    fn test_gives_error_for_missing_field() {
+        let aggregation = parse(&json!({
+            "size": 5
+        }));
+
+        assert_eq!(aggregation.err(), Some(AggregationParseError::ExpectedKey("field")));
+    }
+
+    #[test]
+    fn test_gives_error_for_unrecognised_key() {
+        let aggregation = parse(&json!({
+            "field": "colour",
+            "foo": "bar"
+        }));
+
+        assert_eq!(aggregation.err(), Some(AggregationParseError::UnrecognisedKey("foo".to_string())));
+    }
+
+    #[test]
+    fn test_gives_error_for_date_field() {
+        let result = run_aggregation(json!({
+            "terms": {
+                "field": "date"
+            }
+        }), "date", FieldType::Date, Vec::new());
+
+        assert_eq!(result, Err(AggregationParseError::FieldNotAggregatable("date".to_string())));
+    }
+}
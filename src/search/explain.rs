@@ -0,0 +1,233 @@
+//! Explains how the score of a document was calculated
+//!
+//! The store doesn't expose the statistics that it scores terms with, so each term query is
+//! run on its own to find its contribution to the score. Only these scores are exact. The IDF
+//! is approximated from counts of live documents whereas the store also counts deleted
+//! documents that haven't been merged away yet, and the term frequency part of the score is
+//! derived from the score and the approximate IDF. Explanations are labelled to say so.
+
+use std::collections::HashMap;
+
+use serde_json::Value as Json;
+use kite::{Term, Query, TermScorer};
+use kite::similarity::SimilarityModel;
+use kite::schema::FieldRef;
+use kite::collectors::total_count::TotalCountCollector;
+use kite_rocksdb::RocksDBReader;
+
+use index::metadata::IndexMetadata;
+use mapping::MappingProperty;
+use collectors::doc_score::DocScoreCollector;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Explanation {
+    pub value: f32,
+    pub description: String,
+    pub details: Vec<Explanation>,
+}
+
+
+impl Explanation {
+    fn new(value: f32, description: &str, details: Vec<Explanation>) -> Explanation {
+        Explanation {
+            value: value,
+            description: description.to_string(),
+            details: details,
+        }
+    }
+
+    pub fn as_json(&self) -> Json {
+        json!({
+            "value": self.value,
+            "description": self.description,
+            "details": self.details.iter().map(|detail| detail.as_json()).collect::<Vec<Json>>(),
+        })
+    }
+}
+
+
+pub struct Explainer<'a> {
+    index_reader: &'a RocksDBReader<'a>,
+    field_names: HashMap<FieldRef, String>,
+    total_docs: u64,
+}
+
+
+impl<'a> Explainer<'a> {
+    pub fn new(index_reader: &'a RocksDBReader<'a>, index_metadata: &IndexMetadata) -> Explainer<'a> {
+        // Field names are only known by the mappings
+        let mut field_names = HashMap::new();
+        for mapping in index_metadata.mappings.values() {
+            for (field_name, property) in mapping.properties.iter() {
+                if let MappingProperty::Field(ref field_mapping) = *property {
+                    if let Some(field_ref) = field_mapping.index_ref {
+                        field_names.insert(field_ref, field_name.clone());
+                    }
+                }
+            }
+        }
+
+        let mut explainer = Explainer {
+            index_reader: index_reader,
+            field_names: field_names,
+            total_docs: 0,
+        };
+        explainer.total_docs = explainer.count(&Query::all());
+        explainer
+    }
+
+    fn field_name(&self, field_ref: FieldRef) -> String {
+        self.field_names.get(&field_ref).cloned().unwrap_or_else(|| format!("#{}", field_ref.ord()))
+    }
+
+    /// Runs a query, returns the score that it gives the document
+    fn score(&self, query: &Query, doc_id: u64) -> Option<f32> {
+        let mut collector = DocScoreCollector::new(doc_id);
+        match self.index_reader.search(&mut collector, query) {
+            Ok(()) => collector.score(),
+            Err(_) => None,
+        }
+    }
+
+    /// Runs a query, returns the number of documents that it matches
+    fn count(&self, query: &Query) -> u64 {
+        let mut collector = TotalCountCollector::new();
+        match self.index_reader.search(&mut collector, query) {
+            Ok(()) => collector.get_total_count(),
+            Err(_) => 0,
+        }
+    }
+
+    /// Explains the score of a document, returns None if the document doesn't match the query
+    pub fn explain(&self, query: &Query, doc_id: u64) -> Option<Explanation> {
+        match self.score(query, doc_id) {
+            Some(_) => Some(self.explain_query(query, doc_id)),
+            None => None,
+        }
+    }
+
+    fn explain_query(&self, query: &Query, doc_id: u64) -> Explanation {
+        match *query {
+            Query::All{score} => Explanation::new(score, "*:*", vec![]),
+            Query::None => Explanation::new(0.0f32, "match none", vec![]),
+            Query::Term{field, ref term, ref scorer} => self.explain_term(query, field, term, scorer, doc_id),
+            Query::MultiTerm{field, ref scorer, ..} => {
+                let score = self.score(query, doc_id).unwrap_or(0.0f32);
+                let description = format!("multi term query on {}, average of the scores of matching terms computed with:", self.field_name(field));
+
+                Explanation::new(score, &description, vec![
+                    Explanation::new(scorer.boost, "boost", vec![]),
+                    explain_similarity_model(&scorer.similarity_model),
+                ])
+            }
+            Query::Conjunction{ref queries} | Query::Disjunction{ref queries} => {
+                // Queries that don't match the document count towards the average with a score of 0
+                let details = queries.iter().map(|query| self.explain_query(query, doc_id)).collect::<Vec<Explanation>>();
+                let total_score = details.iter().fold(0.0f32, |total_score, detail| total_score + detail.value);
+                let score = if details.is_empty() { 0.0f32 } else { total_score / details.len() as f32 };
+
+                Explanation::new(score, "average of:", details)
+            }
+            Query::DisjunctionMax{ref queries} => {
+                let details = queries.iter().map(|query| self.explain_query(query, doc_id)).collect::<Vec<Explanation>>();
+                let score = details.iter().fold(0.0f32, |max_score, detail| if detail.value > max_score { detail.value } else { max_score });
+
+                Explanation::new(score, "max of:", details)
+            }
+            Query::Filter{ref query, ..} => {
+                let explanation = self.explain_query(query, doc_id);
+
+                Explanation::new(explanation.value, "filtered query, the filter doesn't affect the score:", vec![explanation])
+            }
+            Query::Exclude{ref query, ..} => {
+                let explanation = self.explain_query(query, doc_id);
+
+                Explanation::new(explanation.value, "query with exclusions, the exclusions don't affect the score:", vec![explanation])
+            }
+        }
+    }
+
+    fn explain_term(&self, query: &Query, field: FieldRef, term: &Term, scorer: &TermScorer, doc_id: u64) -> Explanation {
+        let description = format!("weight({}:{} in {})", self.field_name(field), String::from_utf8_lossy(term.as_bytes()), doc_id);
+
+        let score = match self.score(query, doc_id) {
+            Some(score) => score,
+            None => return Explanation::new(0.0f32, &format!("no matching term, {}", description), vec![]),
+        };
+
+        let doc_freq = self.count(query);
+        let idf = ((self.total_docs as f32 + 1.0) / (doc_freq as f32 + 1.0)).ln() + 1.0;
+        let idf_explanation = Explanation::new(idf, "idf (approximate, deleted documents aren't counted), computed as log((docCount + 1) / (docFreq + 1)) + 1 from:", vec![
+            Explanation::new(doc_freq as f32, "docFreq (live documents only)", vec![]),
+            Explanation::new(self.total_docs as f32, "docCount (live documents only)", vec![]),
+        ]);
+
+        // Whatever isn't explained by the boost and IDF comes from the term frequency
+        let tf = if idf * scorer.boost != 0.0 { score / (idf * scorer.boost) } else { 0.0f32 };
+        let tf_explanation = match scorer.similarity_model {
+            SimilarityModel::TfIdf => {
+                Explanation::new(tf, "tf (approximate), derived from the score as score / (boost * idf), which is log(termFreq + 1) + 1 for:", vec![
+                    Explanation::new(((tf - 1.0).exp() - 1.0).round(), "termFreq (estimated from tf)", vec![]),
+                ])
+            }
+            SimilarityModel::Bm25{..} => {
+                Explanation::new(tf, "tfNorm (approximate), derived from the score as score / (boost * idf), computed by the store from the term frequency and field length with:", vec![
+                    explain_similarity_model(&scorer.similarity_model),
+                ])
+            }
+        };
+
+        Explanation::new(score, &format!("{}, product of:", description), vec![
+            Explanation::new(scorer.boost, "boost", vec![]),
+            idf_explanation,
+            tf_explanation,
+        ])
+    }
+}
+
+
+fn explain_similarity_model(similarity_model: &SimilarityModel) -> Explanation {
+    match *similarity_model {
+        SimilarityModel::TfIdf => Explanation::new(0.0f32, "similarity model: TF-IDF", vec![]),
+        SimilarityModel::Bm25{k1, b} => {
+            Explanation::new(0.0f32, "similarity model: BM25 with parameters:", vec![
+                Explanation::new(k1, "k1", vec![]),
+                Explanation::new(b, "b", vec![]),
+            ])
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::Explanation;
+
+    #[test]
+    fn test_explanation_as_json() {
+        let explanation = Explanation {
+            value: 2.0,
+            description: "average of:".to_string(),
+            details: vec![
+                Explanation {
+                    value: 4.0,
+                    description: "boost".to_string(),
+                    details: vec![],
+                },
+            ],
+        };
+
+        assert_eq!(explanation.as_json(), json!({
+            "value": 2.0,
+            "description": "average of:",
+            "details": [
+                {
+                    "value": 4.0,
+                    "description": "boost",
+                    "details": [],
+                }
+            ],
+        }));
+    }
+}
@@ -0,0 +1,577 @@
+//! Parses the "highlight" section of search requests and highlights matching terms
+//!
+//! Highlighting works on stored string fields. The stored value is re-analyzed with the field's
+//! search analyzer and any tokens that match a term in the query are wrapped in tags. The text
+//! is then broken into fragments and the fragments with the most matches are returned.
+//!
+//! Tokens are mapped back to the text by their position, so fields analyzed with an ngram
+//! tokenizer can't be highlighted.
+
+use unicode_segmentation::UnicodeSegmentation;
+use serde_json::Value as Json;
+use kite::{Term, Token, Query, MultiTermSelector};
+use kite::document::{DocRef, FieldValue};
+use kite::schema::{Schema, FieldRef};
+use kite_rocksdb::RocksDBReader;
+
+use analysis::AnalyzerSpec;
+use analysis::tokenizers::TokenizerSpec;
+use index::metadata::IndexMetadata;
+use mapping::FieldType;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HighlightOptions {
+    pub pre_tags: Vec<String>,
+    pub post_tags: Vec<String>,
+    pub fragment_size: usize,
+
+    /// If 0, the whole value is returned as a single fragment
+    pub number_of_fragments: usize,
+
+    /// Return fragments in order of their score rather than their position in the text
+    pub order_by_score: bool,
+
+    /// Only highlight terms that were searched for in this field
+    pub require_field_match: bool,
+}
+
+
+impl Default for HighlightOptions {
+    fn default() -> HighlightOptions {
+        HighlightOptions {
+            pre_tags: vec!["<em>".to_string()],
+            post_tags: vec!["</em>".to_string()],
+            fragment_size: 100,
+            number_of_fragments: 5,
+            order_by_score: false,
+            require_field_match: false,
+        }
+    }
+}
+
+
+#[derive(Debug, PartialEq)]
+pub enum HighlightParseError {
+    ExpectedObject,
+    ExpectedArray,
+    ExpectedString,
+    ExpectedInteger,
+    ExpectedBoolean,
+    ExpectedKey(&'static str),
+    UnrecognisedKey(String),
+    InvalidOrder(String),
+    FieldDoesntExist(String),
+    FieldNotHighlightable(String),
+}
+
+
+#[derive(Debug, PartialEq)]
+pub struct HighlightSpec {
+    pub fields: Vec<(String, HighlightOptions)>,
+}
+
+
+impl HighlightSpec {
+    /// Finds the stored field and search analyzer of each field to highlight
+    pub fn resolve(&self, index_metadata: &IndexMetadata, schema: &Schema) -> Result<Highlighter, HighlightParseError> {
+        let mut fields = Vec::new();
+
+        for &(ref field_name, ref options) in self.fields.iter() {
+            let field_mapping = match index_metadata.get_field_mapping(field_name) {
+                Some(field_mapping) => field_mapping,
+                None => return Err(HighlightParseError::FieldDoesntExist(field_name.clone())),
+            };
+
+            // The original text is read from the stored field
+            if field_mapping.data_type != FieldType::String || !field_mapping.is_stored {
+                return Err(HighlightParseError::FieldNotHighlightable(field_name.clone()));
+            }
+
+            let field_ref = match schema.get_field_by_name(field_name) {
+                Some(field_ref) => field_ref,
+                None => return Err(HighlightParseError::FieldDoesntExist(field_name.clone())),
+            };
+
+            fields.push(HighlightField {
+                name: field_name.clone(),
+                field_ref: field_ref,
+                analyzer: field_mapping.search_analyzer().cloned(),
+                options: options.clone(),
+            });
+        }
+
+        Ok(Highlighter {
+            fields: fields,
+        })
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum QueryTerm {
+    Term(Term),
+    Prefix(String),
+}
+
+
+impl QueryTerm {
+    fn matches(&self, term: &Term) -> bool {
+        match *self {
+            QueryTerm::Term(ref query_term) => query_term == term,
+            QueryTerm::Prefix(ref prefix) => term.as_bytes().starts_with(prefix.as_bytes()),
+        }
+    }
+}
+
+
+/// The terms that were searched for in a query
+#[derive(Debug)]
+pub struct QueryTerms {
+    terms: Vec<(FieldRef, QueryTerm)>,
+}
+
+
+impl QueryTerms {
+    fn collect_terms(&mut self, query: &Query) {
+        match *query {
+            Query::All{..} | Query::None => {}
+            Query::Term{field, ref term, ..} => {
+                self.terms.push((field, QueryTerm::Term(term.clone())));
+            }
+            Query::MultiTerm{field, term_selector: MultiTermSelector::Prefix(ref prefix), ..} => {
+                self.terms.push((field, QueryTerm::Prefix(prefix.clone())));
+            }
+            Query::Conjunction{ref queries} | Query::Disjunction{ref queries} | Query::DisjunctionMax{ref queries} => {
+                for query in queries.iter() {
+                    self.collect_terms(query);
+                }
+            }
+            // Filters and exclusions don't contribute to the score so aren't highlighted
+            Query::Filter{ref query, ..} | Query::Exclude{ref query, ..} => {
+                self.collect_terms(query);
+            }
+        }
+    }
+
+    pub fn from_query(query: &Query) -> QueryTerms {
+        let mut query_terms = QueryTerms {
+            terms: Vec::new(),
+        };
+
+        query_terms.collect_terms(query);
+        query_terms
+    }
+
+    /// Returns the index of the first query term that matches the given term
+    fn find_match(&self, field_ref: Option<FieldRef>, term: &Term) -> Option<usize> {
+        self.terms.iter().position(|&(query_field_ref, ref query_term)| {
+            field_ref.map_or(true, |field_ref| field_ref == query_field_ref) && query_term.matches(term)
+        })
+    }
+}
+
+
+#[derive(Debug)]
+struct HighlightField {
+    name: String,
+    field_ref: FieldRef,
+    analyzer: Option<AnalyzerSpec>,
+    options: HighlightOptions,
+}
+
+
+#[derive(Debug)]
+pub struct Highlighter {
+    fields: Vec<HighlightField>,
+}
+
+
+impl Highlighter {
+    /// Highlights the fields of a document, returns None if nothing matched
+    pub fn highlight(&self, index_reader: &RocksDBReader, doc_ref: DocRef, query_terms: &QueryTerms) -> Option<Json> {
+        let mut highlight = json!({});
+        let mut found_matches = false;
+
+        for field in self.fields.iter() {
+            let text = match index_reader.read_stored_field(field.field_ref, doc_ref) {
+                Ok(Some(FieldValue::String(text))) => text,
+                _ => continue,
+            };
+
+            let field_ref = if field.options.require_field_match { Some(field.field_ref) } else { None };
+            let fragments = highlight_text(&text, field.analyzer.as_ref(), &field.options, |term| query_terms.find_match(field_ref, term));
+
+            if !fragments.is_empty() {
+                highlight[field.name.as_str()] = json!(fragments);
+                found_matches = true;
+            }
+        }
+
+        if found_matches {
+            Some(highlight)
+        } else {
+            None
+        }
+    }
+}
+
+
+/// Finds the byte range of each word in the text
+///
+/// Tokenizers give each word a position, starting at 1, so the range of a token can be found
+/// by looking up its position in this list. This only holds for tokenizers that split the text
+/// into words, the ngram tokenizers give each gram its own position so None is returned for them.
+fn word_ranges(text: &str, analyzer: Option<&AnalyzerSpec>) -> Option<Vec<(usize, usize)>> {
+    match analyzer {
+        Some(analyzer) => {
+            match analyzer.tokenizer {
+                TokenizerSpec::Standard | TokenizerSpec::Lowercase => {
+                    Some(text.split_word_bound_indices()
+                        .filter(|&(_, word)| word.chars().any(|c| c.is_alphanumeric()))
+                        .map(|(start, word)| (start, start + word.len()))
+                        .collect())
+                }
+                TokenizerSpec::NGram{..} => None,
+            }
+        }
+        // Not analyzed, the whole value is a single token
+        None => Some(vec![(0, text.len())]),
+    }
+}
+
+
+/// Writes a section of text, wrapping any matched words in tags
+fn render_fragment(text: &str, start: usize, end: usize, words: &[(usize, usize)], matches: &[Option<usize>], options: &HighlightOptions) -> String {
+    let mut fragment = String::new();
+    let mut position = start;
+
+    for (&(word_start, word_end), matched_term) in words.iter().zip(matches.iter()) {
+        if word_start < start || word_end > end {
+            continue;
+        }
+
+        if let Some(term_index) = *matched_term {
+            fragment.push_str(&text[position..word_start]);
+            fragment.push_str(&options.pre_tags[term_index % options.pre_tags.len()]);
+            fragment.push_str(&text[word_start..word_end]);
+            fragment.push_str(&options.post_tags[term_index % options.post_tags.len()]);
+            position = word_end;
+        }
+    }
+
+    fragment.push_str(&text[position..end]);
+    fragment
+}
+
+
+/// Highlights the words in the text that match a query term and returns the best fragments
+///
+/// "find_match" returns the index of the query term that matched, which is used to pick the tags
+fn highlight_text<F>(text: &str, analyzer: Option<&AnalyzerSpec>, options: &HighlightOptions, find_match: F) -> Vec<String>
+    where F: Fn(&Term) -> Option<usize>
+{
+    let words = match word_ranges(text, analyzer) {
+        Some(words) => words,
+        None => return Vec::new(),
+    };
+    let tokens = match analyzer {
+        Some(analyzer) => analyzer.initialise(text).collect::<Vec<Token>>(),
+        None => vec![Token { term: Term::from_string(text), position: 1 }],
+    };
+
+    // Find which words match the query
+    let mut matches = vec![None; words.len()];
+    let mut found_matches = false;
+    for token in tokens.iter() {
+        if token.position == 0 || token.position as usize > words.len() {
+            continue;
+        }
+
+        if let Some(term_index) = find_match(&token.term) {
+            let word = token.position as usize - 1;
+            if matches[word].is_none() {
+                matches[word] = Some(term_index);
+                found_matches = true;
+            }
+        }
+    }
+
+    if !found_matches || options.pre_tags.is_empty() || options.post_tags.is_empty() {
+        return Vec::new();
+    }
+
+    if options.number_of_fragments == 0 {
+        return vec![render_fragment(text, 0, text.len(), &words, &matches, options)];
+    }
+
+    // Split the words into fragments of roughly "fragment_size" characters
+    // Each fragment is a range of words with the number of matches in it
+    let mut fragments = Vec::new();
+    let mut first_word = 0;
+    while first_word < words.len() {
+        let start = words[first_word].0;
+        let mut last_word = first_word + 1;
+
+        while last_word < words.len() && text[start..words[last_word].1].chars().count() <= options.fragment_size {
+            last_word += 1;
+        }
+
+        let score = matches[first_word..last_word].iter().filter(|matched_term| matched_term.is_some()).count();
+        if score > 0 {
+            fragments.push((first_word, last_word, score));
+        }
+
+        first_word = last_word;
+    }
+
+    // Pick the fragments with the most matches
+    fragments.sort_by(|a, b| b.2.cmp(&a.2));
+    fragments.truncate(options.number_of_fragments);
+
+    if !options.order_by_score {
+        fragments.sort_by_key(|fragment| fragment.0);
+    }
+
+    fragments.iter().map(|&(first_word, last_word, _)| {
+        render_fragment(text, words[first_word].0, words[last_word - 1].1, &words, &matches, options)
+    }).collect()
+}
+
+
+fn parse_tags(json: &Json) -> Result<Vec<String>, HighlightParseError> {
+    let array = json.as_array().ok_or(HighlightParseError::ExpectedArray)?;
+
+    let mut tags = Vec::new();
+    for tag in array.iter() {
+        tags.push(tag.as_str().ok_or(HighlightParseError::ExpectedString)?.to_string());
+    }
+
+    Ok(tags)
+}
+
+
+/// Applies a highlight option to the given options, returns false if the key isn't an option
+fn parse_option(key: &str, value: &Json, options: &mut HighlightOptions) -> Result<bool, HighlightParseError> {
+    match key {
+        "pre_tags" => {
+            options.pre_tags = parse_tags(value)?;
+        }
+        "post_tags" => {
+            options.post_tags = parse_tags(value)?;
+        }
+        "fragment_size" => {
+            options.fragment_size = value.as_u64().ok_or(HighlightParseError::ExpectedInteger)? as usize;
+        }
+        "number_of_fragments" => {
+            options.number_of_fragments = value.as_u64().ok_or(HighlightParseError::ExpectedInteger)? as usize;
+        }
+        "order" => {
+            options.order_by_score = match value.as_str() {
+                Some("score") => true,
+                Some("none") => false,
+                Some(order) => return Err(HighlightParseError::InvalidOrder(order.to_string())),
+                None => return Err(HighlightParseError::ExpectedString),
+            };
+        }
+        "require_field_match" => {
+            options.require_field_match = value.as_bool().ok_or(HighlightParseError::ExpectedBoolean)?;
+        }
+        _ => return Ok(false),
+    }
+
+    Ok(true)
+}
+
+
+pub fn parse(json: &Json) -> Result<HighlightSpec, HighlightParseError> {
+    let object = json.as_object().ok_or(HighlightParseError::ExpectedObject)?;
+
+    // Options set at the top level are the defaults for each field
+    let mut default_options = HighlightOptions::default();
+    let mut fields_json = None;
+
+    for (key, value) in object.iter() {
+        if key == "fields" {
+            fields_json = Some(value.as_object().ok_or(HighlightParseError::ExpectedObject)?);
+        } else if !parse_option(key, value, &mut default_options)? {
+            return Err(HighlightParseError::UnrecognisedKey(key.clone()));
+        }
+    }
+
+    let fields_json = fields_json.ok_or(HighlightParseError::ExpectedKey("fields"))?;
+    let mut fields = Vec::new();
+
+    for (field_name, field_json) in fields_json.iter() {
+        let field_object = field_json.as_object().ok_or(HighlightParseError::ExpectedObject)?;
+        let mut options = default_options.clone();
+
+        for (key, value) in field_object.iter() {
+            if !parse_option(key, value, &mut options)? {
+                return Err(HighlightParseError::UnrecognisedKey(key.clone()));
+            }
+        }
+
+        fields.push((field_name.clone(), options));
+    }
+
+    Ok(HighlightSpec {
+        fields: fields,
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use kite::Term;
+
+    use analysis::AnalyzerSpec;
+    use analysis::tokenizers::TokenizerSpec;
+    use analysis::filters::FilterSpec;
+    use analysis::ngram_generator::Edge;
+
+    use super::{HighlightOptions, HighlightParseError, highlight_text, parse};
+
+    fn standard_analyzer() -> AnalyzerSpec {
+        AnalyzerSpec {
+            tokenizer: TokenizerSpec::Standard,
+            filters: vec![
+                FilterSpec::Lowercase,
+            ]
+        }
+    }
+
+    fn find_match(query_terms: &[&str], term: &Term) -> Option<usize> {
+        query_terms.iter().position(|query_term| Term::from_string(query_term) == *term)
+    }
+
+    #[test]
+    fn test_highlight() {
+        let fragments = highlight_text("The quick brown fox", Some(&standard_analyzer()), &HighlightOptions::default(), |term| find_match(&["quick", "fox"], term));
+
+        assert_eq!(fragments, vec!["The <em>quick</em> brown <em>fox</em>".to_string()]);
+    }
+
+    #[test]
+    fn test_highlight_not_analyzed() {
+        let fragments = highlight_text("New York", None, &HighlightOptions::default(), |term| find_match(&["New York"], term));
+
+        assert_eq!(fragments, vec!["<em>New York</em>".to_string()]);
+    }
+
+    #[test]
+    fn test_highlight_no_matches() {
+        let fragments = highlight_text("The quick brown fox", Some(&standard_analyzer()), &HighlightOptions::default(), |term| find_match(&["dog"], term));
+
+        assert!(fragments.is_empty());
+    }
+
+    #[test]
+    fn test_highlight_ngram_filter() {
+        // Grams keep the position of the word they came from so the whole word is highlighted
+        let analyzer = AnalyzerSpec {
+            tokenizer: TokenizerSpec::Standard,
+            filters: vec![
+                FilterSpec::Lowercase,
+                FilterSpec::NGram { min_size: 2, max_size: 3, edge: Edge::Left },
+            ]
+        };
+
+        let fragments = highlight_text("The quick brown fox", Some(&analyzer), &HighlightOptions::default(), |term| find_match(&["qu"], term));
+
+        assert_eq!(fragments, vec!["The <em>quick</em> brown fox".to_string()]);
+    }
+
+    #[test]
+    fn test_highlight_ngram_tokenizer() {
+        // Positions of ngram tokens don't line up with words so nothing is highlighted
+        let analyzer = AnalyzerSpec {
+            tokenizer: TokenizerSpec::NGram { min_size: 2, max_size: 3, edge: Edge::Neither },
+            filters: vec![]
+        };
+
+        let fragments = highlight_text("The quick brown fox", Some(&analyzer), &HighlightOptions::default(), |term| find_match(&["qu"], term));
+
+        assert!(fragments.is_empty());
+    }
+
+    #[test]
+    fn test_highlight_tags() {
+        let options = HighlightOptions {
+            pre_tags: vec!["<b>".to_string(), "<i>".to_string()],
+            post_tags: vec!["</b>".to_string(), "</i>".to_string()],
+            .. HighlightOptions::default()
+        };
+
+        let fragments = highlight_text("The quick brown fox", Some(&standard_analyzer()), &options, |term| find_match(&["quick", "fox"], term));
+
+        assert_eq!(fragments, vec!["The <b>quick</b> brown <i>fox</i>".to_string()]);
+    }
+
+    #[test]
+    fn test_highlight_fragments() {
+        let options = HighlightOptions {
+            fragment_size: 10,
+            number_of_fragments: 2,
+            .. HighlightOptions::default()
+        };
+
+        let fragments = highlight_text("one fox two three four fox five fox six", Some(&standard_analyzer()), &options, |term| find_match(&["fox"], term));
+
+        assert_eq!(fragments, vec![
+            "one <em>fox</em>".to_string(),
+            "four <em>fox</em>".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_highlight_whole_value() {
+        let options = HighlightOptions {
+            fragment_size: 10,
+            number_of_fragments: 0,
+            .. HighlightOptions::default()
+        };
+
+        let fragments = highlight_text("one fox two three four fox.", Some(&standard_analyzer()), &options, |term| find_match(&["fox"], term));
+
+        assert_eq!(fragments, vec!["one <em>fox</em> two three four <em>fox</em>.".to_string()]);
+    }
+
+    #[test]
+    fn test_parse() {
+        let spec = parse(&json!({
+            "pre_tags": ["<b>"],
+            "post_tags": ["</b>"],
+            "fields": {
+                "title": {},
+                "body": {
+                    "fragment_size": 50
+                }
+            }
+        })).unwrap();
+
+        let options = HighlightOptions {
+            pre_tags: vec!["<b>".to_string()],
+            post_tags: vec!["</b>".to_string()],
+            .. HighlightOptions::default()
+        };
+
+        assert_eq!(spec.fields, vec![
+            ("body".to_string(), HighlightOptions { fragment_size: 50, .. options.clone() }),
+            ("title".to_string(), options),
+        ]);
+    }
+
+    #[test]
+    fn test_gives_error_for_unrecognised_key() {
+        let spec = parse(&json!({
+            "fields": {
+                "title": {
+                    "foo": "bar"
+                }
+            }
+        }));
+
+        assert_eq!(spec.err(), Some(HighlightParseError::UnrecognisedKey("foo".to_string())));
+    }
+}
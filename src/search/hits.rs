@@ -0,0 +1,77 @@
+//! Converts matching documents into the hits of a search response
+
+use std::collections::BTreeMap;
+
+use serde_json::Value as Json;
+use kite::document::DocRef;
+use kite::schema::FieldRef;
+use kite_rocksdb::RocksDBReader;
+
+use index::lookup::{read_stored_string, read_source};
+use search::highlight::{Highlighter, QueryTerms};
+use search::source_filter::SourceFilter;
+use collectors::top_sorted::SortedDocument;
+
+
+/// Controls which parts of each document are included in its hit
+pub struct HitFormat {
+    pub fields: Vec<(String, FieldRef)>,
+    pub source_filter: SourceFilter,
+    pub highlighter: Option<Highlighter>,
+    pub query_terms: QueryTerms,
+    pub include_sort: bool,
+}
+
+
+impl HitFormat {
+    pub fn hit_json(&self, index_name: &str, index_reader: &RocksDBReader, doc_match: &SortedDocument) -> Json {
+        let doc_ref = DocRef::from_u64(doc_match.doc_id);
+
+        // Fields that hold the document key and mapping name of each document
+        let id_field = index_reader.schema().get_field_by_name("_id");
+        let type_field = index_reader.schema().get_field_by_name("_type");
+
+        let mut hit = json!({
+            "_index": index_name,
+            "_type": read_stored_string(index_reader, type_field, doc_ref),
+            "_id": read_stored_string(index_reader, id_field, doc_ref),
+            "_score": doc_match.score,
+        });
+
+        if self.source_filter.enabled {
+            if let Some(source) = read_source(index_reader, doc_ref).and_then(|source| self.source_filter.apply(&source)) {
+                hit["_source"] = source;
+            }
+        }
+
+        if !self.fields.is_empty() {
+            let mut field_values = BTreeMap::new();
+
+            for &(ref field_name, field_ref) in self.fields.iter() {
+                let value = match index_reader.read_stored_field(field_ref, doc_ref) {
+                    Ok(Some(value)) => vec![value],
+                    Ok(None) => vec![],
+                    Err(_) => vec![],
+                };
+
+                field_values.insert(field_name.clone(), value);
+            }
+
+            hit["fields"] = json!(field_values);
+        }
+
+        if let Some(ref highlighter) = self.highlighter {
+            if let Some(highlight) = highlighter.highlight(index_reader, doc_ref, &self.query_terms) {
+                hit["highlight"] = highlight;
+            }
+        }
+
+        if self.include_sort {
+            hit["sort"] = json!(doc_match.sort_values.iter().map(|value| {
+                value.as_ref().map(|value| value.as_json()).unwrap_or(Json::Null)
+            }).collect::<Vec<Json>>());
+        }
+
+        hit
+    }
+}
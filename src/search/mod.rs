@@ -0,0 +1,9 @@
+//! Features of search requests that sit alongside the query
+
+pub mod sort;
+pub mod aggregations;
+pub mod highlight;
+pub mod source_filter;
+pub mod hits;
+pub mod scroll;
+pub mod explain;
@@ -0,0 +1,225 @@
+//! Keeps the state of scrolling searches between requests
+//!
+//! Store readers borrow the store so they can't outlive the request that created them.
+//! Instead, every matching document is found and sorted once when the scroll is created and
+//! the context keeps the list, so each page is a slice of the same result set however the
+//! index changes in between. Hits are read from a new reader for each page; documents that
+//! have since been deleted and merged away can't be read back so they are left out of the page.
+//! Contexts expire if they aren't used for longer than their keep alive time.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, TryLockError};
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+use cluster::metadata::IndexRef;
+use collectors::top_sorted::SortedDocument;
+use search::hits::HitFormat;
+
+
+/// Finds the pages of a scrolling search
+///
+/// This holds the sorted documents that follow the first page
+pub struct ScrollCursor {
+    docs: Vec<SortedDocument>,
+    position: usize,
+    size: usize,
+}
+
+
+impl ScrollCursor {
+    /// Creates a cursor over the documents that follow the first page
+    pub fn new(docs: Vec<SortedDocument>, size: usize) -> ScrollCursor {
+        ScrollCursor {
+            docs: docs,
+            position: 0,
+            size: size,
+        }
+    }
+
+    /// Takes the next page of documents
+    ///
+    /// This returns an empty page once every document has been returned
+    pub fn next_page(&mut self) -> &[SortedDocument] {
+        let start = self.position;
+        self.position = cmp::min(start + self.size, self.docs.len());
+        &self.docs[start..self.position]
+    }
+}
+
+
+pub struct ScrollContext {
+    pub index_ref: IndexRef,
+    pub cursor: ScrollCursor,
+    pub total: u64,
+    pub max_score: Option<f32>,
+    pub hit_format: HitFormat,
+    keep_alive: Duration,
+    expires_at: Instant,
+}
+
+
+impl ScrollContext {
+    pub fn new(index_ref: IndexRef, cursor: ScrollCursor, total: u64, max_score: Option<f32>, hit_format: HitFormat, keep_alive: Duration) -> ScrollContext {
+        ScrollContext {
+            index_ref: index_ref,
+            cursor: cursor,
+            total: total,
+            max_score: max_score,
+            hit_format: hit_format,
+            keep_alive: keep_alive,
+            expires_at: Instant::now() + keep_alive,
+        }
+    }
+
+    /// Extends the life of the context, optionally changing its keep alive time
+    pub fn touch(&mut self, keep_alive: Option<Duration>) {
+        if let Some(keep_alive) = keep_alive {
+            self.keep_alive = keep_alive;
+        }
+
+        self.expires_at = Instant::now() + self.keep_alive;
+    }
+
+    pub fn is_expired(&self, now: Instant) -> bool {
+        now >= self.expires_at
+    }
+}
+
+
+/// Holds the scroll contexts of every index
+///
+/// Each context has its own lock so the registry only needs to be locked while a context is
+/// looked up, not while its page is being searched and rendered.
+pub struct ScrollRegistry {
+    contexts: HashMap<String, Arc<Mutex<ScrollContext>>>,
+}
+
+
+impl ScrollRegistry {
+    pub fn new() -> ScrollRegistry {
+        ScrollRegistry {
+            contexts: HashMap::new(),
+        }
+    }
+
+    /// Removes any contexts that have expired
+    ///
+    /// Contexts that are being used by a scroll request are never expired
+    fn remove_expired(&mut self) {
+        let now = Instant::now();
+        let expired = self.contexts.iter()
+            .filter(|&(_, context)| {
+                match context.try_lock() {
+                    Ok(context) => context.is_expired(now),
+                    Err(TryLockError::Poisoned(_)) => true,
+                    Err(TryLockError::WouldBlock) => false,
+                }
+            })
+            .map(|(scroll_id, _)| scroll_id.clone())
+            .collect::<Vec<String>>();
+
+        for scroll_id in expired {
+            self.contexts.remove(&scroll_id);
+        }
+    }
+
+    /// Stores a context, returns its scroll id
+    pub fn insert(&mut self, context: ScrollContext) -> String {
+        self.remove_expired();
+
+        let scroll_id = Uuid::new_v4().to_string();
+        self.contexts.insert(scroll_id.clone(), Arc::new(Mutex::new(context)));
+        scroll_id
+    }
+
+    pub fn get(&mut self, scroll_id: &str) -> Option<Arc<Mutex<ScrollContext>>> {
+        self.remove_expired();
+        self.contexts.get(scroll_id).cloned()
+    }
+
+    pub fn remove(&mut self, scroll_id: &str) -> bool {
+        self.remove_expired();
+        self.contexts.remove(scroll_id).is_some()
+    }
+
+    /// Removes every context, returns the number that were removed
+    pub fn clear(&mut self) -> usize {
+        self.remove_expired();
+
+        let num_removed = self.contexts.len();
+        self.contexts.clear();
+        num_removed
+    }
+}
+
+
+/// Parses a keep alive time such as "1m" or "30s"
+pub fn parse_keep_alive(value: &str) -> Option<Duration> {
+    let split_at = match value.find(|c: char| !c.is_digit(10)) {
+        Some(split_at) => split_at,
+        None => return None,
+    };
+    let (number, unit) = value.split_at(split_at);
+
+    let number = match number.parse::<u64>() {
+        Ok(number) => number,
+        Err(_) => return None,
+    };
+
+    match unit {
+        "ms" => Some(Duration::from_millis(number)),
+        "s" => Some(Duration::from_secs(number)),
+        "m" => Some(Duration::from_secs(number * 60)),
+        "h" => Some(Duration::from_secs(number * 60 * 60)),
+        "d" => Some(Duration::from_secs(number * 60 * 60 * 24)),
+        _ => None,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use collectors::top_sorted::SortedDocument;
+
+    use super::{ScrollCursor, parse_keep_alive};
+
+    fn sorted_document(doc_id: u64) -> SortedDocument {
+        SortedDocument {
+            doc_id: doc_id,
+            score: None,
+            sort_values: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_cursor_pages() {
+        let mut cursor = ScrollCursor::new((0..5).map(sorted_document).collect(), 2);
+
+        assert_eq!(cursor.next_page().iter().map(|doc| doc.doc_id).collect::<Vec<u64>>(), vec![0, 1]);
+        assert_eq!(cursor.next_page().iter().map(|doc| doc.doc_id).collect::<Vec<u64>>(), vec![2, 3]);
+        assert_eq!(cursor.next_page().iter().map(|doc| doc.doc_id).collect::<Vec<u64>>(), vec![4]);
+        assert!(cursor.next_page().is_empty());
+        assert!(cursor.next_page().is_empty());
+    }
+
+    #[test]
+    fn test_parse_keep_alive() {
+        assert_eq!(parse_keep_alive("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_keep_alive("30s"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_keep_alive("1m"), Some(Duration::from_secs(60)));
+        assert_eq!(parse_keep_alive("2h"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_keep_alive("1d"), Some(Duration::from_secs(86400)));
+    }
+
+    #[test]
+    fn test_parse_keep_alive_invalid() {
+        assert_eq!(parse_keep_alive("1"), None);
+        assert_eq!(parse_keep_alive("m"), None);
+        assert_eq!(parse_keep_alive("1y"), None);
+    }
+}
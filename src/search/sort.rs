@@ -0,0 +1,584 @@
+//! Parses the "sort" parameter of search requests
+//!
+//! Documents can be sorted by their score or by the value of any stored field that has a
+//! single value per document (integer, date, boolean or not analyzed string fields).
+//!
+//! Values are read from stored fields, so fields must be mapped with "store": true to be sorted
+//! on. Fields aren't stored by default.
+
+use std::cmp::Ordering;
+
+use serde_json::Value as Json;
+use kite::document::FieldValue;
+use kite::schema::{Schema, FieldRef};
+
+use index::metadata::IndexMetadata;
+use mapping::FieldType;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortMissing {
+    First,
+    Last,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SortKey {
+    Score,
+    Field(String),
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortSpec {
+    pub key: SortKey,
+    pub order: SortOrder,
+    pub missing: SortMissing,
+}
+
+
+impl SortSpec {
+    fn new(key: SortKey) -> SortSpec {
+        // Scores are sorted highest first by default, everything else is sorted lowest first
+        let order = match key {
+            SortKey::Score => SortOrder::Desc,
+            SortKey::Field(_) => SortOrder::Asc,
+        };
+
+        SortSpec {
+            key: key,
+            order: order,
+            missing: SortMissing::Last,
+        }
+    }
+
+    /// Finds the stored field that this sort key reads its values from
+    pub fn resolve(&self, index_metadata: &IndexMetadata, schema: &Schema) -> Result<ResolvedSortSpec, SortParseError> {
+        let source = match self.key {
+            SortKey::Score => SortValueSource::Score,
+            SortKey::Field(ref field_name) => {
+                let field_mapping = match index_metadata.get_field_mapping(field_name) {
+                    Some(field_mapping) => field_mapping,
+                    None => return Err(SortParseError::FieldDoesntExist(field_name.clone())),
+                };
+
+                // Analyzed strings have many values per document so can't be sorted on
+                if field_mapping.data_type == FieldType::String && field_mapping.index_analyzer().is_some() {
+                    return Err(SortParseError::FieldNotSortable(field_name.clone()));
+                }
+
+                // Values are read from the stored field
+                if !field_mapping.is_stored {
+                    return Err(SortParseError::FieldNotStored(field_name.clone()));
+                }
+
+                match schema.get_field_by_name(field_name) {
+                    Some(field_ref) => SortValueSource::StoredField(field_ref),
+                    None => return Err(SortParseError::FieldDoesntExist(field_name.clone())),
+                }
+            }
+        };
+
+        Ok(ResolvedSortSpec {
+            source: source,
+            order: self.order,
+            missing: self.missing,
+        })
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortValueSource {
+    Score,
+    StoredField(FieldRef),
+}
+
+
+/// A sort spec that has been bound to the fields of an index
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResolvedSortSpec {
+    pub source: SortValueSource,
+    pub order: SortOrder,
+    pub missing: SortMissing,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SortValue {
+    Score(f32),
+    Integer(i64),
+    String(String),
+}
+
+
+impl SortValue {
+    /// Converts a stored field value into a sort value
+    /// Booleans are sorted as 0/1 and dates as milliseconds since epoch, like Elasticsearch
+    pub fn from_field_value(value: FieldValue) -> SortValue {
+        match value {
+            FieldValue::String(string) => SortValue::String(string),
+            FieldValue::Integer(value) => SortValue::Integer(value),
+            FieldValue::Boolean(value) => SortValue::Integer(if value { 1 } else { 0 }),
+            FieldValue::DateTime(value) => SortValue::Integer(value.timestamp() * 1000 + value.timestamp_subsec_millis() as i64),
+        }
+    }
+
+    pub fn as_json(&self) -> Json {
+        match *self {
+            SortValue::Score(score) => json!(score),
+            SortValue::Integer(value) => json!(value),
+            SortValue::String(ref string) => json!(string),
+        }
+    }
+
+    fn compare(&self, other: &SortValue) -> Ordering {
+        match (self, other) {
+            (&SortValue::Score(a), &SortValue::Score(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+            (&SortValue::Integer(a), &SortValue::Integer(b)) => a.cmp(&b),
+            (&SortValue::String(ref a), &SortValue::String(ref b)) => a.cmp(b),
+            _ => Ordering::Equal,
+        }
+    }
+}
+
+
+/// Compares two lists of sort values
+///
+/// Missing values are placed first or last regardless of the sort order
+pub fn compare_sort_values(sort: &[ResolvedSortSpec], a: &[Option<SortValue>], b: &[Option<SortValue>]) -> Ordering {
+    for (spec, (a, b)) in sort.iter().zip(a.iter().zip(b.iter())) {
+        let ordering = match (a, b) {
+            (&Some(ref a), &Some(ref b)) => {
+                match spec.order {
+                    SortOrder::Asc => a.compare(b),
+                    SortOrder::Desc => b.compare(a),
+                }
+            }
+            (&Some(_), &None) => {
+                match spec.missing {
+                    SortMissing::First => Ordering::Greater,
+                    SortMissing::Last => Ordering::Less,
+                }
+            }
+            (&None, &Some(_)) => {
+                match spec.missing {
+                    SortMissing::First => Ordering::Less,
+                    SortMissing::Last => Ordering::Greater,
+                }
+            }
+            (&None, &None) => Ordering::Equal,
+        };
+
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+
+    Ordering::Equal
+}
+
+
+#[derive(Debug, PartialEq)]
+pub enum SortParseError {
+    ExpectedString,
+    ExpectedObjectOrString,
+    ExpectedSingleKey,
+    UnrecognisedKey(String),
+    InvalidOrder(String),
+    InvalidMissing(String),
+    EmptyFieldName,
+    FieldDoesntExist(String),
+    FieldNotSortable(String),
+
+    /// The field isn't mapped with "store": true
+    FieldNotStored(String),
+
+    ExpectedArray,
+    WrongNumberOfSearchAfterValues,
+    InvalidSearchAfterValue,
+}
+
+
+impl SortParseError {
+    pub fn reason(&self) -> String {
+        match *self {
+            SortParseError::FieldNotStored(ref field_name) => format!("field {:?} must be mapped with \"store\": true to be sorted on", field_name),
+            ref error => format!("{:?}", error),
+        }
+    }
+}
+
+
+fn parse_key(name: &str) -> Result<SortKey, SortParseError> {
+    match name {
+        "" => Err(SortParseError::EmptyFieldName),
+        "_score" => Ok(SortKey::Score),
+        _ => Ok(SortKey::Field(name.to_string())),
+    }
+}
+
+
+fn parse_order(order: &str) -> Result<SortOrder, SortParseError> {
+    match order {
+        "asc" => Ok(SortOrder::Asc),
+        "desc" => Ok(SortOrder::Desc),
+        _ => Err(SortParseError::InvalidOrder(order.to_string())),
+    }
+}
+
+
+fn parse_missing(missing: &str) -> Result<SortMissing, SortParseError> {
+    match missing {
+        "_first" => Ok(SortMissing::First),
+        "_last" => Ok(SortMissing::Last),
+        _ => Err(SortParseError::InvalidMissing(missing.to_string())),
+    }
+}
+
+
+fn parse_sort_item(json: &Json) -> Result<SortSpec, SortParseError> {
+    match *json {
+        Json::String(ref name) => Ok(SortSpec::new(parse_key(name)?)),
+        Json::Object(ref object) => {
+            let name = if object.len() == 1 {
+                object.keys().collect::<Vec<_>>()[0]
+            } else {
+                return Err(SortParseError::ExpectedSingleKey);
+            };
+
+            let mut spec = SortSpec::new(parse_key(name)?);
+
+            match *object.get(name).unwrap() {
+                Json::String(ref order) => {
+                    spec.order = parse_order(order)?;
+                }
+                Json::Object(ref inner_object) => {
+                    for (key, value) in inner_object.iter() {
+                        let value = value.as_str().ok_or(SortParseError::ExpectedString)?;
+
+                        match key.as_ref() {
+                            "order" => {
+                                spec.order = parse_order(value)?;
+                            }
+                            "missing" => {
+                                spec.missing = parse_missing(value)?;
+                            }
+                            _ => return Err(SortParseError::UnrecognisedKey(key.clone()))
+                        }
+                    }
+                }
+                _ => return Err(SortParseError::ExpectedObjectOrString),
+            }
+
+            Ok(spec)
+        }
+        _ => Err(SortParseError::ExpectedObjectOrString),
+    }
+}
+
+
+/// Parses the "sort" key of a search request body
+pub fn parse(json: &Json) -> Result<Vec<SortSpec>, SortParseError> {
+    match *json {
+        Json::Array(ref array) => {
+            let mut sort = Vec::with_capacity(array.len());
+
+            for item in array.iter() {
+                sort.push(parse_sort_item(item)?);
+            }
+
+            Ok(sort)
+        }
+        _ => Ok(vec![parse_sort_item(json)?]),
+    }
+}
+
+
+/// Parses the "sort" URL parameter, for example: "published_at:desc,title"
+pub fn parse_url_param(param: &str) -> Result<Vec<SortSpec>, SortParseError> {
+    let mut sort = Vec::new();
+
+    for item in param.split(',') {
+        let mut split = item.splitn(2, ':');
+        let mut spec = SortSpec::new(parse_key(split.next().unwrap_or(""))?);
+
+        if let Some(order) = split.next() {
+            spec.order = parse_order(order)?;
+        }
+
+        sort.push(spec);
+    }
+
+    Ok(sort)
+}
+
+
+/// Adds the document key to the end of a sort if it isn't already in it
+///
+/// This makes sure that documents with equal sort values are always returned in the same
+/// order and gives "search_after" a unique value to continue from.
+pub fn add_tiebreaker(sort: &mut Vec<ResolvedSortSpec>, schema: &Schema) {
+    let id_field = match schema.get_field_by_name("_id") {
+        Some(id_field) => id_field,
+        None => return,
+    };
+
+    if sort.iter().any(|spec| spec.source == SortValueSource::StoredField(id_field)) {
+        return;
+    }
+
+    sort.push(ResolvedSortSpec {
+        source: SortValueSource::StoredField(id_field),
+        order: SortOrder::Asc,
+        missing: SortMissing::Last,
+    });
+}
+
+
+fn parse_search_after_value(json: &Json, spec: &ResolvedSortSpec) -> Result<Option<SortValue>, SortParseError> {
+    match *json {
+        Json::Null => Ok(None),
+        Json::Number(ref number) => {
+            let value = match spec.source {
+                SortValueSource::Score => number.as_f64().map(|score| SortValue::Score(score as f32)),
+                SortValueSource::StoredField(_) => number.as_i64().map(SortValue::Integer),
+            };
+
+            value.map(Some).ok_or(SortParseError::InvalidSearchAfterValue)
+        }
+        Json::String(ref string) if spec.source != SortValueSource::Score => Ok(Some(SortValue::String(string.clone()))),
+        Json::Bool(value) if spec.source != SortValueSource::Score => Ok(Some(SortValue::Integer(if value { 1 } else { 0 }))),
+        _ => Err(SortParseError::InvalidSearchAfterValue),
+    }
+}
+
+
+/// Parses the "search_after" parameter
+///
+/// This contains the sort values of the last hit of the previous page, one for each key of
+/// the resolved sort (including the tiebreaker).
+pub fn parse_search_after(json: &Json, sort: &[ResolvedSortSpec]) -> Result<Vec<Option<SortValue>>, SortParseError> {
+    let array = json.as_array().ok_or(SortParseError::ExpectedArray)?;
+
+    if array.len() != sort.len() {
+        return Err(SortParseError::WrongNumberOfSearchAfterValues);
+    }
+
+    array.iter().zip(sort.iter()).map(|(value, spec)| parse_search_after_value(value, spec)).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use serde_json;
+    use kite::schema::{Schema, FieldType, FIELD_STORED, FIELD_INDEXED};
+
+    use mapping::{self, Mapping, MappingProperty, FieldMapping};
+    use index::metadata::IndexMetadata;
+
+    use super::{SortSpec, SortKey, SortOrder, SortMissing, SortParseError, SortValue, SortValueSource,
+                ResolvedSortSpec, parse, parse_url_param, compare_sort_values, add_tiebreaker, parse_search_after};
+
+    #[test]
+    fn test_parse_field_name() {
+        let sort = parse(&serde_json::from_str("
+        \"title\"
+        ").unwrap());
+
+        assert_eq!(sort, Ok(vec![
+            SortSpec {
+                key: SortKey::Field("title".to_string()),
+                order: SortOrder::Asc,
+                missing: SortMissing::Last,
+            }
+        ]));
+    }
+
+    #[test]
+    fn test_parse_list() {
+        let sort = parse(&serde_json::from_str("
+        [
+            {\"published_at\": \"desc\"},
+            {\"price\": {\"order\": \"asc\", \"missing\": \"_first\"}},
+            \"_score\"
+        ]
+        ").unwrap());
+
+        assert_eq!(sort, Ok(vec![
+            SortSpec {
+                key: SortKey::Field("published_at".to_string()),
+                order: SortOrder::Desc,
+                missing: SortMissing::Last,
+            },
+            SortSpec {
+                key: SortKey::Field("price".to_string()),
+                order: SortOrder::Asc,
+                missing: SortMissing::First,
+            },
+            SortSpec {
+                key: SortKey::Score,
+                order: SortOrder::Desc,
+                missing: SortMissing::Last,
+            }
+        ]));
+    }
+
+    #[test]
+    fn test_parse_url_param() {
+        let sort = parse_url_param("published_at:desc,title");
+
+        assert_eq!(sort, Ok(vec![
+            SortSpec {
+                key: SortKey::Field("published_at".to_string()),
+                order: SortOrder::Desc,
+                missing: SortMissing::Last,
+            },
+            SortSpec {
+                key: SortKey::Field("title".to_string()),
+                order: SortOrder::Asc,
+                missing: SortMissing::Last,
+            }
+        ]));
+    }
+
+    #[test]
+    fn test_gives_error_for_empty_url_param_field() {
+        assert_eq!(parse_url_param("").err(), Some(SortParseError::EmptyFieldName));
+        assert_eq!(parse_url_param(":desc").err(), Some(SortParseError::EmptyFieldName));
+        assert_eq!(parse_url_param("title,").err(), Some(SortParseError::EmptyFieldName));
+    }
+
+    #[test]
+    fn test_gives_error_for_empty_field() {
+        let sort = parse(&serde_json::from_str("
+        {\"\": \"asc\"}
+        ").unwrap());
+
+        assert_eq!(sort.err(), Some(SortParseError::EmptyFieldName));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_order() {
+        let sort = parse(&serde_json::from_str("
+        {\"title\": \"up\"}
+        ").unwrap());
+
+        assert_eq!(sort.err(), Some(SortParseError::InvalidOrder("up".to_string())));
+    }
+
+    #[test]
+    fn test_gives_error_for_extra_inner_key() {
+        let sort = parse(&serde_json::from_str("
+        {\"title\": {\"order\": \"asc\", \"hello\": \"world\"}}
+        ").unwrap());
+
+        assert_eq!(sort.err(), Some(SortParseError::UnrecognisedKey("hello".to_string())));
+    }
+
+    #[test]
+    fn test_compare_sort_values() {
+        let sort = vec![
+            ResolvedSortSpec {
+                source: SortValueSource::Score,
+                order: SortOrder::Desc,
+                missing: SortMissing::Last,
+            }
+        ];
+
+        assert_eq!(compare_sort_values(&sort, &[Some(SortValue::Score(2.0))], &[Some(SortValue::Score(1.0))]), Ordering::Less);
+        assert_eq!(compare_sort_values(&sort, &[Some(SortValue::Score(1.0))], &[Some(SortValue::Score(1.0))]), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_compare_sort_values_missing() {
+        let mut sort = vec![
+            ResolvedSortSpec {
+                source: SortValueSource::Score,
+                order: SortOrder::Desc,
+                missing: SortMissing::Last,
+            }
+        ];
+
+        assert_eq!(compare_sort_values(&sort, &[None], &[Some(SortValue::Integer(1))]), Ordering::Greater);
+
+        sort[0].missing = SortMissing::First;
+        assert_eq!(compare_sort_values(&sort, &[None], &[Some(SortValue::Integer(1))]), Ordering::Less);
+    }
+
+    #[test]
+    fn test_add_tiebreaker() {
+        let mut schema = Schema::new();
+        let id_field = schema.add_field("_id".to_string(), FieldType::PlainString, FIELD_STORED).unwrap();
+        let mut sort = vec![
+            ResolvedSortSpec {
+                source: SortValueSource::Score,
+                order: SortOrder::Desc,
+                missing: SortMissing::Last,
+            }
+        ];
+
+        add_tiebreaker(&mut sort, &schema);
+        assert_eq!(sort[1].source, SortValueSource::StoredField(id_field));
+
+        // Shouldn't be added twice
+        add_tiebreaker(&mut sort, &schema);
+        assert_eq!(sort.len(), 2);
+    }
+
+    #[test]
+    fn test_gives_error_for_field_that_isnt_stored() {
+        let mut schema = Schema::new();
+        let price_field = schema.add_field("price".to_string(), FieldType::I64, FIELD_INDEXED).unwrap();
+
+        let mut field_mapping = FieldMapping::default();
+        field_mapping.data_type = mapping::FieldType::Integer;
+        field_mapping.index_ref = Some(price_field);
+
+        let mut index_metadata = IndexMetadata::default();
+        index_metadata.mappings.insert("test".to_string(), Mapping {
+            properties: hashmap! {
+                "price".to_string() => MappingProperty::Field(field_mapping),
+            },
+        });
+
+        let error = SortSpec::new(SortKey::Field("price".to_string())).resolve(&index_metadata, &schema).err();
+        assert_eq!(error, Some(SortParseError::FieldNotStored("price".to_string())));
+        assert!(error.unwrap().reason().contains("\"store\": true"));
+    }
+
+    #[test]
+    fn test_parse_search_after() {
+        let mut schema = Schema::new();
+        let id_field = schema.add_field("_id".to_string(), FieldType::PlainString, FIELD_STORED).unwrap();
+        let sort = vec![
+            ResolvedSortSpec {
+                source: SortValueSource::Score,
+                order: SortOrder::Desc,
+                missing: SortMissing::Last,
+            },
+            ResolvedSortSpec {
+                source: SortValueSource::StoredField(id_field),
+                order: SortOrder::Asc,
+                missing: SortMissing::Last,
+            }
+        ];
+
+        assert_eq!(parse_search_after(&json!([1.5, "doc-10"]), &sort), Ok(vec![
+            Some(SortValue::Score(1.5)),
+            Some(SortValue::String("doc-10".to_string())),
+        ]));
+        assert_eq!(parse_search_after(&json!([1.5]), &sort), Err(SortParseError::WrongNumberOfSearchAfterValues));
+        assert_eq!(parse_search_after(&json!(["foo", "doc-10"]), &sort), Err(SortParseError::InvalidSearchAfterValue));
+    }
+}
@@ -0,0 +1,321 @@
+//! Parses the "_source" parameter and filters the fields of source documents
+//!
+//! Include and exclude patterns are dotted field paths (eg, "user.name") which may contain
+//! "*" wildcards.
+
+use serde_json::Value as Json;
+use serde_json::Map;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceFilter {
+    pub enabled: bool,
+    pub includes: Vec<String>,
+    pub excludes: Vec<String>,
+}
+
+
+impl Default for SourceFilter {
+    fn default() -> SourceFilter {
+        SourceFilter {
+            enabled: true,
+            includes: Vec::new(),
+            excludes: Vec::new(),
+        }
+    }
+}
+
+
+impl SourceFilter {
+    pub fn disabled() -> SourceFilter {
+        SourceFilter {
+            enabled: false,
+            .. SourceFilter::default()
+        }
+    }
+
+    /// Filters a source document, returns None if the source shouldn't be returned at all
+    pub fn apply(&self, source: &Json) -> Option<Json> {
+        if !self.enabled {
+            return None;
+        }
+
+        if self.includes.is_empty() && self.excludes.is_empty() {
+            return Some(source.clone());
+        }
+
+        match *source {
+            Json::Object(ref object) => Some(Json::Object(filter_object(object, "", &self.includes, &self.excludes))),
+            _ => Some(source.clone()),
+        }
+    }
+}
+
+
+/// Matches a path against a pattern that may contain "*" wildcards
+fn matches_pattern(pattern: &[u8], path: &[u8]) -> bool {
+    match pattern.first() {
+        Some(&b'*') => {
+            // Try matching the rest of the pattern at every position
+            (0..path.len() + 1).any(|skip| matches_pattern(&pattern[1..], &path[skip..]))
+        }
+        Some(&c) => {
+            match path.first() {
+                Some(&p) if p == c => matches_pattern(&pattern[1..], &path[1..]),
+                _ => false,
+            }
+        }
+        None => path.is_empty(),
+    }
+}
+
+
+fn matches_any(patterns: &[String], path: &str) -> bool {
+    patterns.iter().any(|pattern| matches_pattern(pattern.as_bytes(), path.as_bytes()))
+}
+
+
+fn filter_value(value: &Json, path: &str, includes: &[String], excludes: &[String]) -> Option<Json> {
+    if matches_any(excludes, path) {
+        return None;
+    }
+
+    // Once a field is included, everything inside it is included too
+    let no_includes: &[String] = &[];
+    let includes = if includes.is_empty() || matches_any(includes, path) { no_includes } else { includes };
+
+    match *value {
+        Json::Object(ref object) => {
+            let filtered = filter_object(object, path, includes, excludes);
+
+            // Objects that only exist to hold included fields are dropped if none were found
+            if filtered.is_empty() && !includes.is_empty() {
+                None
+            } else {
+                Some(Json::Object(filtered))
+            }
+        }
+        Json::Array(ref array) => {
+            let filtered = array.iter().filter_map(|item| filter_value(item, path, includes, excludes)).collect::<Vec<Json>>();
+
+            if filtered.is_empty() && !includes.is_empty() {
+                None
+            } else {
+                Some(Json::Array(filtered))
+            }
+        }
+        _ => {
+            if includes.is_empty() {
+                Some(value.clone())
+            } else {
+                None
+            }
+        }
+    }
+}
+
+
+fn filter_object(object: &Map<String, Json>, path: &str, includes: &[String], excludes: &[String]) -> Map<String, Json> {
+    let mut filtered = Map::new();
+
+    for (key, value) in object.iter() {
+        let field_path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+
+        if let Some(value) = filter_value(value, &field_path, includes, excludes) {
+            filtered.insert(key.clone(), value);
+        }
+    }
+
+    filtered
+}
+
+
+#[derive(Debug, PartialEq)]
+pub enum SourceFilterParseError {
+    ExpectedString,
+    ExpectedBooleanStringArrayOrObject,
+    UnrecognisedKey(String),
+}
+
+
+fn parse_patterns(json: &Json) -> Result<Vec<String>, SourceFilterParseError> {
+    match *json {
+        Json::String(ref pattern) => Ok(vec![pattern.clone()]),
+        Json::Array(ref array) => {
+            let mut patterns = Vec::new();
+
+            for item in array.iter() {
+                patterns.push(item.as_str().ok_or(SourceFilterParseError::ExpectedString)?.to_string());
+            }
+
+            Ok(patterns)
+        }
+        _ => Err(SourceFilterParseError::ExpectedString),
+    }
+}
+
+
+/// Parses the "_source" key of a request body
+pub fn parse(json: &Json) -> Result<SourceFilter, SourceFilterParseError> {
+    match *json {
+        Json::Bool(true) => Ok(SourceFilter::default()),
+        Json::Bool(false) => Ok(SourceFilter::disabled()),
+        Json::String(_) | Json::Array(_) => {
+            Ok(SourceFilter {
+                includes: parse_patterns(json)?,
+                .. SourceFilter::default()
+            })
+        }
+        Json::Object(ref object) => {
+            let mut source_filter = SourceFilter::default();
+
+            for (key, value) in object.iter() {
+                match key.as_ref() {
+                    "includes" | "include" => {
+                        source_filter.includes = parse_patterns(value)?;
+                    }
+                    "excludes" | "exclude" => {
+                        source_filter.excludes = parse_patterns(value)?;
+                    }
+                    _ => return Err(SourceFilterParseError::UnrecognisedKey(key.clone()))
+                }
+            }
+
+            Ok(source_filter)
+        }
+        _ => Err(SourceFilterParseError::ExpectedBooleanStringArrayOrObject),
+    }
+}
+
+
+/// Applies one of the "_source", "_source_includes" or "_source_excludes" URL parameters
+pub fn apply_url_param(source_filter: &mut SourceFilter, key: &str, value: &str) {
+    let patterns = || value.split(",").map(|pattern| pattern.to_string()).collect::<Vec<String>>();
+
+    match key {
+        "_source" => {
+            match value {
+                "true" => source_filter.enabled = true,
+                "false" => source_filter.enabled = false,
+                _ => source_filter.includes = patterns(),
+            }
+        }
+        "_source_include" | "_source_includes" => source_filter.includes = patterns(),
+        "_source_exclude" | "_source_excludes" => source_filter.excludes = patterns(),
+        _ => {}
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{SourceFilter, SourceFilterParseError, parse, apply_url_param};
+
+    fn source() -> ::serde_json::Value {
+        json!({
+            "title": "Hello",
+            "user": {
+                "name": "Karl",
+                "email": "karl@example.com"
+            },
+            "tags": ["a", "b"]
+        })
+    }
+
+    #[test]
+    fn test_no_filter() {
+        assert_eq!(SourceFilter::default().apply(&source()), Some(source()));
+    }
+
+    #[test]
+    fn test_disabled() {
+        assert_eq!(SourceFilter::disabled().apply(&source()), None);
+    }
+
+    #[test]
+    fn test_includes() {
+        let source_filter = SourceFilter {
+            includes: vec!["title".to_string(), "user.name".to_string()],
+            .. SourceFilter::default()
+        };
+
+        assert_eq!(source_filter.apply(&source()), Some(json!({
+            "title": "Hello",
+            "user": {
+                "name": "Karl"
+            }
+        })));
+    }
+
+    #[test]
+    fn test_includes_object() {
+        let source_filter = SourceFilter {
+            includes: vec!["user".to_string()],
+            .. SourceFilter::default()
+        };
+
+        assert_eq!(source_filter.apply(&source()), Some(json!({
+            "user": {
+                "name": "Karl",
+                "email": "karl@example.com"
+            }
+        })));
+    }
+
+    #[test]
+    fn test_excludes() {
+        let source_filter = SourceFilter {
+            excludes: vec!["user.email".to_string(), "tags".to_string()],
+            .. SourceFilter::default()
+        };
+
+        assert_eq!(source_filter.apply(&source()), Some(json!({
+            "title": "Hello",
+            "user": {
+                "name": "Karl"
+            }
+        })));
+    }
+
+    #[test]
+    fn test_wildcards() {
+        let source_filter = SourceFilter {
+            includes: vec!["user.*".to_string()],
+            excludes: vec!["*.email".to_string()],
+            .. SourceFilter::default()
+        };
+
+        assert_eq!(source_filter.apply(&source()), Some(json!({
+            "user": {
+                "name": "Karl"
+            }
+        })));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(&json!(false)), Ok(SourceFilter::disabled()));
+        assert_eq!(parse(&json!("title")), Ok(SourceFilter {
+            includes: vec!["title".to_string()],
+            .. SourceFilter::default()
+        }));
+        assert_eq!(parse(&json!({"includes": ["user.*"], "excludes": "user.email"})), Ok(SourceFilter {
+            includes: vec!["user.*".to_string()],
+            excludes: vec!["user.email".to_string()],
+            .. SourceFilter::default()
+        }));
+        assert_eq!(parse(&json!(123)), Err(SourceFilterParseError::ExpectedBooleanStringArrayOrObject));
+        assert_eq!(parse(&json!({"foo": "bar"})), Err(SourceFilterParseError::UnrecognisedKey("foo".to_string())));
+    }
+
+    #[test]
+    fn test_apply_url_param() {
+        let mut source_filter = SourceFilter::default();
+
+        apply_url_param(&mut source_filter, "_source_includes", "title,user.name");
+        assert_eq!(source_filter.includes, vec!["title".to_string(), "user.name".to_string()]);
+
+        apply_url_param(&mut source_filter, "_source", "false");
+        assert!(!source_filter.enabled);
+    }
+}
@@ -1,4 +1,4 @@
-use std::sync::RwLock;
+use std::sync::{RwLock, Mutex};
 use std::path::{Path, PathBuf};
 use std::fs;
 
@@ -6,15 +6,18 @@ use slog::Logger;
 use kite_rocksdb::RocksDBStore;
 use uuid::Uuid;
 
-use index::Index;
+use index::{Index, term_dictionary_path};
 use index::metadata::IndexMetadata;
+use index::term_dictionary::TermDictionary;
 use cluster::metadata::ClusterMetadata;
+use search::scroll::ScrollRegistry;
 
 
 pub struct System {
     pub log: Logger,
     data_dir: PathBuf,
     pub metadata: RwLock<ClusterMetadata>,
+    pub scrolls: Mutex<ScrollRegistry>,
 }
 
 
@@ -24,6 +27,7 @@ impl System {
             log: log,
             data_dir: data_dir,
             metadata: RwLock::new(ClusterMetadata::new()),
+            scrolls: Mutex::new(ScrollRegistry::new()),
         }
     }
 
@@ -41,7 +45,10 @@ impl System {
         metadata_path.push("metadata.json");
         let metadata = IndexMetadata::load(metadata_path)?;
 
-        Ok(Index::new(id, name, metadata, store))
+        // Load term dictionary
+        let term_dictionary = TermDictionary::open(term_dictionary_path(path))?;
+
+        Ok(Index::new(id, name, metadata, store, term_dictionary))
     }
 
     pub fn load_indices(&self) {